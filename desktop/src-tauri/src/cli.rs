@@ -0,0 +1,394 @@
+//! Headless command line, for scripting imports without the window
+//!
+//! `mastery status`, `list-devices`, `pull`, `parse` and `sync --dry-run` use the
+//! same `kindle` module and state files as the app. Results go to stdout as one
+//! JSON document (or CSV for `parse --format csv`); failures go to stderr as
+//! `{ kind, message, code }` with an exit code per kind of failure, so cron jobs
+//! can tell "no Kindle attached" from a real error.
+
+use crate::kindle::auto_sync::AutoSyncSettings;
+use crate::kindle::readers::{connected_readers, fill_last_synced, find_reader, ReaderStatus};
+use crate::kindle::sync_state::{vocab_delta, SyncStateStore};
+use crate::kindle::vocab::Vocab;
+use crate::kindle::{
+    read_device_file, read_reader_vocab_db, read_vocab_db_content, CancelToken, KindleError,
+    CLIPPINGS,
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Bundle identifier from tauri.conf.json, which names the app data directory
+const IDENTIFIER: &str = "com.mastery.desktop";
+
+const USAGE: &str = "\
+Usage: mastery <command> [options]
+
+Commands:
+  status                              Whether a reader is connected and when it was last synced
+  list-devices                        Every connected reader as JSON
+  pull vocab|clippings --out <path>   Copy vocab.db or My Clippings.txt from the reader
+  parse <vocab.db> [--format json|csv]
+                                      Print the words and lookups in a vocab.db
+  sync --dry-run                      Print the lookups the next import would upload
+
+Exit codes: 0 success, 1 error, 2 usage, 3 no reader connected,
+4 file not found on the reader, 5 permission denied, 6 device busy, 130 cancelled";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PullSource {
+    Vocab,
+    Clippings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Csv,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Help,
+    Status,
+    ListDevices,
+    Pull { source: PullSource, out: PathBuf },
+    Parse { path: PathBuf, format: Format },
+    SyncDryRun,
+}
+
+/// Runs a CLI subcommand and returns its exit code
+///
+/// `None` when `args` (without the program name) is no subcommand, e.g. the URL
+/// a deep link launches the app with, so the GUI starts instead.
+pub fn run(args: &[String]) -> Option<i32> {
+    let command = match parse_args(args)? {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!(
+                "{}",
+                serde_json::json!({ "kind": "usage", "message": message })
+            );
+            eprintln!("\n{}", USAGE);
+            return Some(2);
+        }
+    };
+    let code = match execute(command) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", serde_json::to_string(&e).unwrap_or_default());
+            exit_code(&e)
+        }
+    };
+    Some(code)
+}
+
+fn parse_args(args: &[String]) -> Option<Result<Command, String>> {
+    let (name, rest) = args.split_first()?;
+    let command = match name.as_str() {
+        "help" | "--help" | "-h" => Ok(Command::Help),
+        "status" => no_options(rest).map(|_| Command::Status),
+        "list-devices" => no_options(rest).map(|_| Command::ListDevices),
+        "pull" => parse_pull(rest),
+        "parse" => parse_parse(rest),
+        "sync" => match rest {
+            [flag] if flag == "--dry-run" => Ok(Command::SyncDryRun),
+            // Uploading needs the Supabase session, which only the app holds
+            [] => Err("sync uploads through the app; pass --dry-run to preview".to_string()),
+            _ => Err(format!("unexpected arguments: {}", rest.join(" "))),
+        },
+        _ => return None,
+    };
+    Some(command)
+}
+
+fn no_options(rest: &[String]) -> Result<(), String> {
+    match rest {
+        [] => Ok(()),
+        _ => Err(format!("unexpected arguments: {}", rest.join(" "))),
+    }
+}
+
+fn parse_pull(rest: &[String]) -> Result<Command, String> {
+    let mut source = None;
+    let mut out = None;
+    let mut args = rest.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" | "-o" => {
+                out = Some(PathBuf::from(
+                    args.next().ok_or("--out needs a path".to_string())?,
+                ))
+            }
+            "vocab" if source.is_none() => source = Some(PullSource::Vocab),
+            "clippings" if source.is_none() => source = Some(PullSource::Clippings),
+            other => return Err(format!("unexpected argument: {}", other)),
+        }
+    }
+    Ok(Command::Pull {
+        source: source.ok_or("pull needs vocab or clippings".to_string())?,
+        out: out.ok_or("pull needs --out <path>".to_string())?,
+    })
+}
+
+fn parse_parse(rest: &[String]) -> Result<Command, String> {
+    let mut path = None;
+    let mut format = Format::Json;
+    let mut args = rest.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" | "-f" => {
+                format = match args.next().map(String::as_str) {
+                    Some("json") => Format::Json,
+                    Some("csv") => Format::Csv,
+                    _ => return Err("--format must be json or csv".to_string()),
+                }
+            }
+            other if other.starts_with('-') => {
+                return Err(format!("unexpected argument: {}", other))
+            }
+            other if path.is_none() => path = Some(PathBuf::from(other)),
+            other => return Err(format!("unexpected argument: {}", other)),
+        }
+    }
+    Ok(Command::Parse {
+        path: path.ok_or("parse needs the path of a vocab.db".to_string())?,
+        format,
+    })
+}
+
+fn execute(command: Command) -> Result<i32, KindleError> {
+    match command {
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(0)
+        }
+        Command::Status => {
+            let readers = readers_with_last_synced();
+            let settings = AutoSyncSettings::load(&data_dir()?.join("auto-sync.json"));
+            print_json(&serde_json::json!({
+                "connected": !readers.is_empty(),
+                "readers": readers.len(),
+                "lastSynced": readers.iter().filter_map(|r| r.last_synced).max(),
+                "autoSyncPaused": settings.paused,
+            }));
+            Ok(if readers.is_empty() { 3 } else { 0 })
+        }
+        Command::ListDevices => {
+            print_json(&readers_with_last_synced());
+            Ok(0)
+        }
+        Command::Pull { source, out } => {
+            let cancel = CancelToken::new();
+            let content = match source {
+                PullSource::Vocab => read_vocab_db_content(&cancel, &mut |_| {})?,
+                PullSource::Clippings => read_device_file(&CLIPPINGS, &cancel, &mut |_| {})?,
+            };
+            if let Some(dir) = out.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(&out, &content)?;
+            print_json(&serde_json::json!({
+                "source": match source {
+                    PullSource::Vocab => "vocabDb",
+                    PullSource::Clippings => "clippings",
+                },
+                "path": out,
+                "bytes": content.len(),
+            }));
+            Ok(0)
+        }
+        Command::Parse { path, format } => {
+            if !path.is_file() {
+                return Err(KindleError::Io(format!("{} not found", path.display())));
+            }
+            let vocab = Vocab::open(&path)?;
+            match format {
+                Format::Json => print_json(&vocab),
+                Format::Csv => print!("{}", vocab_csv(&vocab)),
+            }
+            Ok(0)
+        }
+        Command::SyncDryRun => {
+            let reader = find_reader(None)?;
+            let content =
+                read_reader_vocab_db(&reader.transport, &CancelToken::new(), &mut |_| {})?;
+            let vocab = Vocab::from_bytes(&content)?;
+            let key = reader.sync_key();
+            let store = SyncStateStore::load(&sync_state_path()?);
+            let previous = key
+                .as_deref()
+                .and_then(|key| store.device(key).cloned())
+                .unwrap_or_default();
+            let (delta, _) = vocab_delta(&vocab, &previous);
+            print_json(&serde_json::json!({ "device": key, "delta": delta }));
+            Ok(0)
+        }
+    }
+}
+
+/// Exit code for a failed command, grouped by what a script can do about it
+fn exit_code(error: &KindleError) -> i32 {
+    match error {
+        KindleError::NoDevice => 3,
+        KindleError::VocabNotFound
+        | KindleError::ClippingsNotFound
+        | KindleError::KoreaderVocabNotFound
+        | KindleError::KoboDbNotFound => 4,
+        KindleError::PermissionDenied(_) | KindleError::UdevRuleMissing => 5,
+        KindleError::InterfaceBusy(_) => 6,
+        KindleError::Cancelled => 130,
+        _ => 1,
+    }
+}
+
+/// Same directory as Tauri's `app_data_dir`, which the CLI has no app handle for
+fn data_dir() -> Result<PathBuf, KindleError> {
+    dirs::data_dir()
+        .map(|dir| dir.join(IDENTIFIER))
+        .ok_or_else(|| KindleError::Io("No data directory for this user".to_string()))
+}
+
+fn sync_state_path() -> Result<PathBuf, KindleError> {
+    data_dir().map(|dir| dir.join("sync-state.json"))
+}
+
+fn readers_with_last_synced() -> Vec<ReaderStatus> {
+    let mut readers = connected_readers();
+    if let Ok(path) = sync_state_path() {
+        fill_last_synced(&mut readers, &SyncStateStore::load(&path));
+    }
+    readers
+}
+
+fn print_json(value: &impl serde::Serialize) {
+    println!("{}", serde_json::to_string(value).unwrap_or_default());
+}
+
+/// One row per lookup, with its word and book
+fn vocab_csv(vocab: &Vocab) -> String {
+    let words: BTreeMap<_, _> = vocab.words.iter().map(|w| (w.id.as_str(), w)).collect();
+    let books: BTreeMap<_, _> = vocab.books.iter().map(|b| (b.id.as_str(), b)).collect();
+
+    let mut csv = String::from("word,stem,lang,usage,book,authors,timestamp\n");
+    for lookup in &vocab.lookups {
+        let Some(word) = words.get(lookup.word_key.as_str()) else {
+            continue;
+        };
+        let book = lookup.book_key.as_deref().and_then(|key| books.get(key));
+        let fields = [
+            Some(word.word.as_str()),
+            word.stem.as_deref(),
+            word.lang.as_deref(),
+            lookup.usage.as_deref(),
+            book.and_then(|b| b.title.as_deref()),
+            book.and_then(|b| b.authors.as_deref()),
+        ];
+        for field in fields {
+            csv.push_str(&csv_field(field.unwrap_or_default()));
+            csv.push(',');
+        }
+        if let Some(timestamp) = lookup.timestamp {
+            csv.push_str(&timestamp.to_string());
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Quotes a field per RFC 4180 when it holds a delimiter, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kindle::vocab::{Book, Lookup, Word};
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parses_subcommands() {
+        assert_eq!(parse_args(&args("status")), Some(Ok(Command::Status)));
+        assert_eq!(
+            parse_args(&args("pull clippings --out /tmp/clippings.txt")),
+            Some(Ok(Command::Pull {
+                source: PullSource::Clippings,
+                out: PathBuf::from("/tmp/clippings.txt"),
+            }))
+        );
+        assert_eq!(
+            parse_args(&args("parse vocab.db --format csv")),
+            Some(Ok(Command::Parse {
+                path: PathBuf::from("vocab.db"),
+                format: Format::Csv,
+            }))
+        );
+        assert_eq!(
+            parse_args(&args("sync --dry-run")),
+            Some(Ok(Command::SyncDryRun))
+        );
+
+        assert!(matches!(parse_args(&args("pull vocab")), Some(Err(_))));
+        assert!(matches!(
+            parse_args(&args("parse a.db --format xml")),
+            Some(Err(_))
+        ));
+        assert!(matches!(parse_args(&args("sync")), Some(Err(_))));
+        // Anything else starts the app, such as a deep link
+        assert_eq!(parse_args(&args("mastery://auth/callback")), None);
+        assert_eq!(parse_args(&[]), None);
+    }
+
+    #[test]
+    fn writes_lookups_as_csv() {
+        let vocab = Vocab {
+            words: vec![Word {
+                id: "en:serendipity".to_string(),
+                word: "serendipity".to_string(),
+                stem: Some("serendipity".to_string()),
+                lang: Some("en".to_string()),
+                category: 0,
+                timestamp: None,
+            }],
+            lookups: vec![Lookup {
+                id: "l1".to_string(),
+                word_key: "en:serendipity".to_string(),
+                book_key: Some("b1".to_string()),
+                dict_key: None,
+                usage: Some("It was, he said, \"pure serendipity\".".to_string()),
+                timestamp: Some(1_700_000_000_000),
+            }],
+            books: vec![Book {
+                id: "b1".to_string(),
+                asin: None,
+                guid: None,
+                lang: Some("en".to_string()),
+                title: Some("Notes".to_string()),
+                authors: None,
+            }],
+            dictionaries: Vec::new(),
+        };
+        assert_eq!(
+            vocab_csv(&vocab),
+            "word,stem,lang,usage,book,authors,timestamp\n\
+             serendipity,serendipity,en,\"It was, he said, \"\"pure serendipity\"\".\",Notes,,1700000000000\n"
+        );
+    }
+
+    #[test]
+    fn maps_errors_to_exit_codes() {
+        assert_eq!(exit_code(&KindleError::NoDevice), 3);
+        assert_eq!(exit_code(&KindleError::ClippingsNotFound), 4);
+        assert_eq!(exit_code(&KindleError::UdevRuleMissing), 5);
+        assert_eq!(exit_code(&KindleError::Cancelled), 130);
+        assert_eq!(exit_code(&KindleError::Timeout), 1);
+    }
+}
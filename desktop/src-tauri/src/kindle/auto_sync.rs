@@ -0,0 +1,224 @@
+//! Whether to import from a reader as soon as it is plugged in
+//!
+//! Each device has a policy: `always` imports in the background, `ask` prompts
+//! first and `never` leaves it to the user. Only readers synced before, or given
+//! a policy, count as known; a reader seen for the first time is never imported
+//! from unprompted. A minimum interval since the last sync, or the last attempt
+//! when that failed, keeps a Kindle that stays plugged in, and re-enumerates on
+//! every wake, from syncing again and again.
+
+use crate::kindle::readers::{ReaderStatus, Source};
+use crate::kindle::sync_state::write_json;
+use crate::kindle::KindleError;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const DEFAULT_MIN_INTERVAL_MINUTES: u32 = 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SyncPolicy {
+    Always,
+    #[default]
+    Ask,
+    Never,
+}
+
+/// Auto-sync configuration, persisted as JSON
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoSyncSettings {
+    /// Policy by reader ID; readers without one are asked
+    #[serde(default)]
+    pub devices: BTreeMap<String, SyncPolicy>,
+    #[serde(default = "default_min_interval")]
+    pub min_interval_minutes: u32,
+    /// Set from the tray to stop syncing on connect without touching the policies
+    #[serde(default)]
+    pub paused: bool,
+}
+
+fn default_min_interval() -> u32 {
+    DEFAULT_MIN_INTERVAL_MINUTES
+}
+
+impl Default for AutoSyncSettings {
+    fn default() -> Self {
+        Self {
+            devices: BTreeMap::new(),
+            min_interval_minutes: DEFAULT_MIN_INTERVAL_MINUTES,
+            paused: false,
+        }
+    }
+}
+
+impl AutoSyncSettings {
+    /// Loads the settings, falling back to the defaults when the file is missing or unreadable
+    pub fn load(path: &Path) -> Self {
+        fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), KindleError> {
+        write_json(path, self)
+    }
+
+    pub fn policy(&self, reader_id: &str) -> SyncPolicy {
+        self.devices.get(reader_id).copied().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoSyncAction {
+    /// Import right away
+    Sync,
+    /// Ask the user before importing
+    Ask,
+}
+
+/// What to do about a reader that was just connected, if anything
+///
+/// `reader.last_synced` must already be filled in. `last_attempt` is when the
+/// reader was last synced or asked about on connect, successfully or not; times
+/// are in ms since the Unix epoch.
+pub fn auto_sync_action(
+    settings: &AutoSyncSettings,
+    reader: &ReaderStatus,
+    last_attempt: Option<i64>,
+    now: i64,
+) -> Option<AutoSyncAction> {
+    // Only vocab.db imports have a delta to upload so far
+    if settings.paused || !reader.sources.contains(&Source::VocabDb) {
+        return None;
+    }
+    let known = reader.last_synced.is_some() || settings.devices.contains_key(&reader.id);
+    if !known {
+        return None;
+    }
+    let min_interval = i64::from(settings.min_interval_minutes) * 60_000;
+    if reader
+        .last_synced
+        .max(last_attempt)
+        .is_some_and(|last| now - last < min_interval)
+    {
+        return None;
+    }
+    match settings.policy(&reader.id) {
+        SyncPolicy::Always => Some(AutoSyncAction::Sync),
+        SyncPolicy::Ask => Some(AutoSyncAction::Ask),
+        SyncPolicy::Never => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kindle::readers::{DeviceFamily, Transport};
+
+    const HOUR: i64 = 3_600_000;
+
+    fn kindle(last_synced: Option<i64>) -> ReaderStatus {
+        ReaderStatus {
+            id: "G000TEST".to_string(),
+            family: DeviceFamily::Kindle,
+            transport: Transport::Mtp { bus: 1, address: 4 },
+            sources: vec![Source::VocabDb, Source::Clippings],
+            device: None,
+            last_synced,
+        }
+    }
+
+    fn settings(policy: Option<SyncPolicy>) -> AutoSyncSettings {
+        let mut settings = AutoSyncSettings::default();
+        if let Some(policy) = policy {
+            settings.devices.insert("G000TEST".to_string(), policy);
+        }
+        settings
+    }
+
+    #[test]
+    fn follows_the_device_policy() {
+        let now = 10 * HOUR;
+        let reader = kindle(Some(now - 2 * HOUR));
+        assert_eq!(
+            auto_sync_action(&settings(Some(SyncPolicy::Always)), &reader, None, now),
+            Some(AutoSyncAction::Sync)
+        );
+        assert_eq!(
+            auto_sync_action(&settings(None), &reader, None, now),
+            Some(AutoSyncAction::Ask)
+        );
+        assert_eq!(
+            auto_sync_action(&settings(Some(SyncPolicy::Never)), &reader, None, now),
+            None
+        );
+
+        let paused = AutoSyncSettings {
+            paused: true,
+            ..settings(Some(SyncPolicy::Always))
+        };
+        assert_eq!(auto_sync_action(&paused, &reader, None, now), None);
+    }
+
+    #[test]
+    fn skips_unknown_readers_and_recent_syncs() {
+        let now = 10 * HOUR;
+        assert_eq!(
+            auto_sync_action(&settings(None), &kindle(None), None, now),
+            None
+        );
+        assert_eq!(
+            auto_sync_action(
+                &settings(Some(SyncPolicy::Always)),
+                &kindle(None),
+                None,
+                now
+            ),
+            Some(AutoSyncAction::Sync)
+        );
+        assert_eq!(
+            auto_sync_action(
+                &settings(Some(SyncPolicy::Always)),
+                &kindle(Some(now - 10 * 60_000)),
+                None,
+                now
+            ),
+            None
+        );
+
+        // A failed sync is not retried on every reconnect either
+        assert_eq!(
+            auto_sync_action(
+                &settings(Some(SyncPolicy::Always)),
+                &kindle(Some(0)),
+                Some(now - 10 * 60_000),
+                now
+            ),
+            None
+        );
+
+        let mut kobo = kindle(Some(0));
+        kobo.sources = vec![Source::KoboDb];
+        assert_eq!(
+            auto_sync_action(&settings(Some(SyncPolicy::Always)), &kobo, None, now),
+            None
+        );
+    }
+
+    #[test]
+    fn settings_round_trip_through_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("auto-sync.json");
+        assert_eq!(AutoSyncSettings::load(&path), AutoSyncSettings::default());
+
+        let settings = AutoSyncSettings {
+            min_interval_minutes: 15,
+            ..settings(Some(SyncPolicy::Always))
+        };
+        settings.save(&path).unwrap();
+        assert_eq!(AutoSyncSettings::load(&path), settings);
+    }
+}
@@ -0,0 +1,419 @@
+//! Parser for the Kindle `documents/My Clippings.txt` file
+//!
+//! The file is append-only; each entry is terminated by a line of ten `=`:
+//!
+//! ```text
+//! Book Title (Author Name)
+//! - Your Highlight on page 5 | Location 72-75 | Added on Monday, January 20, 2026 10:30:00 AM
+//!
+//! Highlighted text
+//! ==========
+//! ```
+//!
+//! The header line is written in the Kindle's UI language, so its parts are
+//! recognized by keyword rather than by position. Dates are kept as written
+//! because their format depends on the language as well.
+
+use std::collections::HashSet;
+
+const SEPARATOR: &str = "==========";
+
+/// Written instead of the text once a publisher's clipping limit is reached
+const CLIPPING_LIMIT_PREFIX: &str = "<You have reached the clipping limit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ClippingKind {
+    Highlight,
+    Note,
+    Bookmark,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Clipping {
+    pub title: String,
+    pub author: Option<String>,
+    pub kind: ClippingKind,
+    pub page: Option<String>,
+    /// Kindle location, either a single position or a range like `72-75`
+    pub location: Option<String>,
+    /// Date as written in the header, in the Kindle's UI language
+    pub added_on: Option<String>,
+    /// Highlighted text or note; empty for bookmarks
+    pub content: String,
+}
+
+/// Keywords for the clipping kind, per UI language
+const BOOKMARK_WORDS: &[&str] = &[
+    "bookmark",
+    "lesezeichen",
+    "signet",
+    "marcador",
+    "segnalibro",
+    "bladwijzer",
+    "ブックマーク",
+    "书签",
+];
+const HIGHLIGHT_WORDS: &[&str] = &[
+    "highlight",
+    "markierung",
+    "surlignement",
+    "subrayado",
+    "evidenziazione",
+    "destaque",
+    "markering",
+    "ハイライト",
+    "标注",
+];
+const NOTE_WORDS: &[&str] = &["note", "notiz", "nota", "notitie", "メモ", "笔记"];
+
+/// Keywords followed by the location number
+const LOCATION_WORDS: &[&str] = &[
+    "location",
+    "loc.",
+    "position",
+    "posición",
+    "posizione",
+    "posição",
+    "emplacement",
+    "locatie",
+    "位置",
+];
+/// Keywords followed by the page number
+const PAGE_WORDS: &[&str] = &["page", "seite", "página", "pagina"];
+/// Keywords preceded by the page number
+const PAGE_SUFFIXES: &[&str] = &["ページ", "页"];
+/// Keywords followed by the date
+const ADDED_WORDS: &[&str] = &[
+    "added on",
+    "hinzugefügt am",
+    "ajouté le",
+    "añadido el",
+    "aggiunto in data",
+    "aggiunto il",
+    "adicionado em",
+    "adicionado:",
+    "toegevoegd op",
+    "作成日:",
+    "作成日：",
+    "添加于",
+];
+
+/// Parses every entry of a clippings file, skipping entries that are not clippings
+pub fn parse_clippings(text: &str) -> Vec<Clipping> {
+    let mut clippings = Vec::new();
+    let mut block = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.trim_start_matches('\u{feff}').trim() == SEPARATOR {
+            clippings.extend(parse_entry(&block));
+            block.clear();
+        } else {
+            block.push(line);
+        }
+    }
+    // A file cut short may lack the final separator
+    clippings.extend(parse_entry(&block));
+    clippings
+}
+
+/// Drops repeated clippings, keeping the first of each content, book and location
+pub fn dedupe_clippings(clippings: Vec<Clipping>) -> Vec<Clipping> {
+    let mut seen = HashSet::new();
+    clippings
+        .into_iter()
+        .filter(|c| {
+            seen.insert((
+                c.title.clone(),
+                c.author.clone(),
+                c.location.clone(),
+                c.content.clone(),
+            ))
+        })
+        .collect()
+}
+
+fn parse_entry(lines: &[&str]) -> Option<Clipping> {
+    let mut lines = lines
+        .iter()
+        .map(|line| line.trim_start_matches('\u{feff}'))
+        .skip_while(|line| line.trim().is_empty());
+
+    let (title, author) = split_title_author(lines.next()?.trim());
+    let header = lines.next()?.trim().trim_start_matches('-').trim();
+    let content = lines.collect::<Vec<_>>().join("\n").trim().to_string();
+    if content.starts_with(CLIPPING_LIMIT_PREFIX) {
+        return None;
+    }
+
+    let mut kind = None;
+    let mut page = None;
+    let mut location = None;
+    let mut added_on = None;
+    for segment in header.split('|').map(str::trim) {
+        let lower = segment.to_lowercase();
+        if let Some(date) = text_after_keyword(segment, &lower, ADDED_WORDS) {
+            added_on = Some(date);
+            continue;
+        }
+        kind = kind.or_else(|| clipping_kind(&lower));
+        location = location.or_else(|| number_after_keyword(&lower, LOCATION_WORDS));
+        page = page
+            .or_else(|| number_after_keyword(&lower, PAGE_WORDS))
+            .or_else(|| number_before_keyword(&lower, PAGE_SUFFIXES));
+    }
+
+    Some(Clipping {
+        title,
+        author,
+        kind: kind?,
+        page,
+        location,
+        added_on,
+        content,
+    })
+}
+
+/// Splits `Title (Author)`, where the title itself may contain parentheses
+fn split_title_author(line: &str) -> (String, Option<String>) {
+    if line.ends_with(')') {
+        let mut depth = 0;
+        for (i, c) in line.char_indices().rev() {
+            match c {
+                ')' => depth += 1,
+                '(' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        let title = line[..i].trim();
+                        let author = line[i + 1..line.len() - 1].trim();
+                        if title.is_empty() {
+                            break;
+                        }
+                        return (
+                            title.to_string(),
+                            (!author.is_empty()).then(|| author.to_string()),
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    (line.to_string(), None)
+}
+
+fn clipping_kind(lower: &str) -> Option<ClippingKind> {
+    let has = |words: &[&str]| words.iter().any(|w| lower.contains(w));
+    if has(BOOKMARK_WORDS) {
+        Some(ClippingKind::Bookmark)
+    } else if has(HIGHLIGHT_WORDS) {
+        Some(ClippingKind::Highlight)
+    } else if has(NOTE_WORDS) {
+        Some(ClippingKind::Note)
+    } else {
+        None
+    }
+}
+
+fn text_after_keyword(segment: &str, lower: &str, keywords: &[&str]) -> Option<String> {
+    let (start, keyword) = find_keyword(lower, keywords)?;
+    // Lowercasing keeps byte offsets for everything a header contains in practice
+    let rest = if lower.len() == segment.len() {
+        &segment[start + keyword.len()..]
+    } else {
+        &lower[start + keyword.len()..]
+    };
+    Some(rest.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+fn number_after_keyword(lower: &str, keywords: &[&str]) -> Option<String> {
+    let (start, keyword) = find_keyword(lower, keywords)?;
+    let rest = &lower[start + keyword.len()..];
+    let first_digit = rest.find(|c: char| c.is_ascii_digit());
+    match first_digit {
+        Some(i) => Some(number_at(&rest[i..])),
+        // Front matter is often paginated in roman numerals
+        None => rest
+            .split_whitespace()
+            .next()
+            .filter(|word| word.chars().all(|c| "ivxlcdm".contains(c)))
+            .map(str::to_string),
+    }
+}
+
+fn number_before_keyword(lower: &str, keywords: &[&str]) -> Option<String> {
+    let (start, _) = find_keyword(lower, keywords)?;
+    let before = lower[..start].trim_end();
+    let digits_start = before
+        .rfind(|c: char| !c.is_ascii_digit() && c != '-')
+        .map_or(0, |i| {
+            i + before[i..].chars().next().map_or(1, char::len_utf8)
+        });
+    Some(number_at(&before[digits_start..])).filter(|n| !n.is_empty())
+}
+
+/// Reads a number or range like `72-75` from the start of `text`
+fn number_at(text: &str) -> String {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit() && c != '-')
+        .unwrap_or(text.len());
+    text[..end].trim_matches('-').to_string()
+}
+
+fn find_keyword<'a>(lower: &str, keywords: &[&'a str]) -> Option<(usize, &'a str)> {
+    keywords
+        .iter()
+        .filter_map(|k| lower.find(k).map(|i| (i, *k)))
+        .min_by_key(|(i, _)| *i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIPPINGS: &str = "\u{feff}The Great Gatsby (F. Scott Fitzgerald)\r
+- Your Highlight on page 5 | Location 72-75 | Added on Monday, January 20, 2026 10:30:00 AM\r
+\r
+In my younger and more vulnerable years my father gave me some advice.\r
+==========\r
+1984 (George Orwell)\r
+- Your Note on page 50 | Location 750 | Added on Tuesday, January 21, 2026 9:30:00 AM\r
+\r
+This reminds me of modern surveillance concerns.\r
+==========\r
+1984 (George Orwell)\r
+- Your Bookmark at location 812 | Added on Tuesday, January 21, 2026 9:45:00 AM\r
+\r
+\r
+==========\r
+The Great Gatsby (F. Scott Fitzgerald)\r
+- Your Highlight on page 5 | Location 72-75 | Added on Monday, January 20, 2026 10:31:00 AM\r
+\r
+In my younger and more vulnerable years my father gave me some advice.\r
+==========\r
+";
+
+    #[test]
+    fn parses_highlights_notes_and_bookmarks() {
+        let clippings = parse_clippings(CLIPPINGS);
+        assert_eq!(clippings.len(), 4);
+
+        assert_eq!(
+            clippings[0],
+            Clipping {
+                title: "The Great Gatsby".to_string(),
+                author: Some("F. Scott Fitzgerald".to_string()),
+                kind: ClippingKind::Highlight,
+                page: Some("5".to_string()),
+                location: Some("72-75".to_string()),
+                added_on: Some("Monday, January 20, 2026 10:30:00 AM".to_string()),
+                content: "In my younger and more vulnerable years my father gave me some advice."
+                    .to_string(),
+            }
+        );
+        assert_eq!(clippings[1].kind, ClippingKind::Note);
+        assert_eq!(clippings[1].location.as_deref(), Some("750"));
+        assert_eq!(clippings[2].kind, ClippingKind::Bookmark);
+        assert_eq!(clippings[2].location.as_deref(), Some("812"));
+        assert_eq!(clippings[2].page, None);
+        assert_eq!(clippings[2].content, "");
+    }
+
+    #[test]
+    fn dedupes_by_content_book_and_location() {
+        let clippings = dedupe_clippings(parse_clippings(CLIPPINGS));
+        assert_eq!(clippings.len(), 3);
+        assert_eq!(
+            clippings[0].added_on.as_deref(),
+            Some("Monday, January 20, 2026 10:30:00 AM")
+        );
+    }
+
+    #[test]
+    fn reads_headers_in_other_languages() {
+        let text = "\
+Der Process (Kafka, Franz)
+- Ihre Markierung auf Seite 12 | Position 170-172 | Hinzugefügt am Montag, 20. Januar 2026 10:30:00
+
+Jemand musste Josef K. verleumdet haben.
+==========
+L'Étranger (Camus, Albert)
+- Votre note sur la page xii | emplacement 88 | Ajouté le lundi 20 janvier 2026 10:30:00
+
+Aujourd'hui, maman est morte.
+==========
+吾輩は猫である (夏目 漱石)
+- 5ページ|位置No. 72-75のハイライト |作成日: 2026年1月20日月曜日 10:30:00
+
+吾輩は猫である。
+==========
+三体 (刘慈欣)
+- 您在第 8 页（位置 #120-121）的标注 | 添加于 2026年1月20日星期一 上午10:30:00
+
+物理学不存在了。
+==========
+Don Quijote (Cervantes)
+- Tu marcador en la posición 300 | Añadido el lunes, 20 de enero de 2026 10:30:00
+
+
+==========
+";
+        let clippings = parse_clippings(text);
+        let summary: Vec<_> = clippings
+            .iter()
+            .map(|c| (c.kind, c.page.as_deref(), c.location.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (ClippingKind::Highlight, Some("12"), Some("170-172")),
+                (ClippingKind::Note, Some("xii"), Some("88")),
+                (ClippingKind::Highlight, Some("5"), Some("72-75")),
+                (ClippingKind::Highlight, Some("8"), Some("120-121")),
+                (ClippingKind::Bookmark, None, Some("300")),
+            ]
+        );
+        assert_eq!(clippings[0].author.as_deref(), Some("Kafka, Franz"));
+        assert_eq!(
+            clippings[0].added_on.as_deref(),
+            Some("Montag, 20. Januar 2026 10:30:00")
+        );
+        assert_eq!(
+            clippings[2].added_on.as_deref(),
+            Some("2026年1月20日月曜日 10:30:00")
+        );
+    }
+
+    #[test]
+    fn handles_parentheses_in_titles_and_clipping_limits() {
+        let text = "\
+Dune (Dune Chronicles, Book 1) (Herbert, Frank)
+- Your Highlight at location 1200-1201 | Added on Friday, 3 April 2020 18:37:26
+
+Fear is the mind-killer.
+
+Fear is the little-death.
+==========
+Some Book (Someone)
+- Your Highlight at location 10 | Added on Friday, 3 April 2020 18:40:00
+
+<You have reached the clipping limit for this item>
+==========
+Untitled
+- Your Highlight at location 5 | Added on Friday, 3 April 2020 18:41:00
+
+no separator at the end";
+        let clippings = parse_clippings(text);
+        assert_eq!(clippings.len(), 2);
+        assert_eq!(clippings[0].title, "Dune (Dune Chronicles, Book 1)");
+        assert_eq!(clippings[0].author.as_deref(), Some("Herbert, Frank"));
+        assert_eq!(
+            clippings[0].content,
+            "Fear is the mind-killer.\n\nFear is the little-death."
+        );
+        assert_eq!(clippings[1].title, "Untitled");
+        assert_eq!(clippings[1].author, None);
+        assert_eq!(clippings[1].content, "no separator at the end");
+    }
+}
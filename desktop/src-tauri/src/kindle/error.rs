@@ -0,0 +1,272 @@
+//! Errors surfaced by Kindle detection, MTP transfers and uploads
+//!
+//! Serialized to the frontend as `{ kind, message, code? }` so the UI can pick
+//! recovery steps from `kind` instead of matching on message text.
+
+use serde::ser::SerializeStruct;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KindleError {
+    /// No Kindle is attached over USB or mounted as a volume
+    NoDevice,
+    /// The OS refused access to the USB device or a file on it
+    PermissionDenied(String),
+    /// Another process or driver holds the MTP interface, named when it could be found
+    InterfaceBusy(Option<String>),
+    /// Linux refused to open the device because no udev rule grants the user access
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    UdevRuleMissing,
+    /// The device stopped responding within the transfer timeout
+    Timeout,
+    /// The device answered an operation with a non-OK PTP response code
+    ProtocolError { operation: &'static str, code: u16 },
+    /// The device sent a container that could not be parsed
+    InvalidResponse(String),
+    /// Fewer bytes arrived than the device reported for the file
+    IncompleteTransfer { expected: u64, received: u64 },
+    /// The downloaded file is not a complete SQLite database
+    CorruptDatabase(String),
+    /// The Kindle has no `system/vocabulary/vocab.db`
+    VocabNotFound,
+    /// The Kindle has no `documents/My Clippings.txt`
+    ClippingsNotFound,
+    /// No KOReader `vocabulary_builder.sqlite3` on the reader
+    KoreaderVocabNotFound,
+    /// No mounted Kobo with `.kobo/KoboReader.sqlite`
+    KoboDbNotFound,
+    /// The user cancelled the import
+    Cancelled,
+    /// The Supabase session was rejected and could not be refreshed
+    SessionExpired,
+    /// The server answered an upload with an error status
+    Server { status: u16, message: String },
+    /// The server could not be reached, so the request was never sent
+    Unreachable(String),
+    /// The connection to the server failed, possibly after it handled the request
+    Network(String),
+    /// Any other libusb failure
+    Usb(String),
+    /// Local filesystem or process failure
+    Io(String),
+}
+
+impl KindleError {
+    /// Stable identifier for the frontend
+    pub fn kind(&self) -> &'static str {
+        match self {
+            KindleError::NoDevice => "noDevice",
+            KindleError::PermissionDenied(_) => "permissionDenied",
+            KindleError::InterfaceBusy(_) => "interfaceBusy",
+            KindleError::UdevRuleMissing => "udevRuleMissing",
+            KindleError::Timeout => "timeout",
+            KindleError::ProtocolError { .. } => "protocolError",
+            KindleError::InvalidResponse(_) => "invalidResponse",
+            KindleError::IncompleteTransfer { .. } => "incompleteTransfer",
+            KindleError::CorruptDatabase(_) => "corruptDatabase",
+            KindleError::VocabNotFound => "vocabNotFound",
+            KindleError::ClippingsNotFound => "clippingsNotFound",
+            KindleError::KoreaderVocabNotFound => "koreaderVocabNotFound",
+            KindleError::KoboDbNotFound => "koboDbNotFound",
+            KindleError::Cancelled => "cancelled",
+            KindleError::SessionExpired => "sessionExpired",
+            KindleError::Server { .. } => "server",
+            // The same to the UI: the server is out of reach
+            KindleError::Unreachable(_) | KindleError::Network(_) => "network",
+            KindleError::Usb(_) => "usb",
+            KindleError::Io(_) => "io",
+        }
+    }
+
+    /// Whether the failure may clear up if the same request is sent again
+    pub fn is_transient(&self) -> bool {
+        match self {
+            KindleError::Timeout
+            | KindleError::InvalidResponse(_)
+            | KindleError::Usb(_)
+            | KindleError::Unreachable(_)
+            | KindleError::Network(_) => true,
+            // Rate limited, or the gateway in front of the edge function is overloaded
+            KindleError::Server { status, .. } => matches!(status, 429 | 502 | 503 | 504),
+            _ => false,
+        }
+    }
+
+    /// Whether the server never acted on the request, so sending it again can't
+    /// import anything twice: it could not be reached, turned the request away
+    /// (429) or was unavailable (503)
+    ///
+    /// A lost connection or a 502 or 504 from the gateway may come after
+    /// `parse-vocab` imported the batch.
+    pub fn not_processed(&self) -> bool {
+        match self {
+            KindleError::Unreachable(_) => true,
+            KindleError::Server { status, .. } => matches!(status, 429 | 503),
+            _ => false,
+        }
+    }
+
+    /// PTP response code, when the device sent one
+    pub fn code(&self) -> Option<u16> {
+        match self {
+            KindleError::ProtocolError { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for KindleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KindleError::NoDevice => {
+                write!(f, "No Kindle device found. Make sure it's connected.")
+            }
+            KindleError::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
+            KindleError::InterfaceBusy(None) => {
+                write!(f, "Kindle is in use by another application")
+            }
+            KindleError::InterfaceBusy(Some(holder)) if holder.starts_with("gvfs") => write!(
+                f,
+                "Kindle is open in the file manager ({}). Eject it there and try again.",
+                holder
+            ),
+            KindleError::InterfaceBusy(Some(holder)) => {
+                write!(f, "Kindle is in use by {}. Close it and try again.", holder)
+            }
+            KindleError::UdevRuleMissing => write!(
+                f,
+                "No permission to access the Kindle over USB. Install the Mastery udev rule \
+                 (70-mastery-kindle.rules) and reconnect the Kindle."
+            ),
+            KindleError::Timeout => write!(f, "Kindle did not respond in time"),
+            KindleError::ProtocolError { operation, code } => {
+                write!(f, "{} failed: {:#06x}", operation, code)
+            }
+            KindleError::InvalidResponse(msg) => write!(f, "Invalid response: {}", msg),
+            KindleError::IncompleteTransfer { expected, received } => write!(
+                f,
+                "Transfer incomplete: received {} of {} bytes",
+                received, expected
+            ),
+            KindleError::CorruptDatabase(msg) => write!(f, "vocab.db is damaged: {}", msg),
+            KindleError::VocabNotFound => write!(f, "vocab.db not found on Kindle"),
+            KindleError::ClippingsNotFound => write!(f, "My Clippings.txt not found on Kindle"),
+            KindleError::KoreaderVocabNotFound => {
+                write!(f, "KOReader vocabulary builder not found on the reader")
+            }
+            KindleError::KoboDbNotFound => write!(f, "KoboReader.sqlite not found on Kobo"),
+            KindleError::Cancelled => write!(f, "Import cancelled by user"),
+            KindleError::SessionExpired => write!(f, "Your session has expired. Sign in again."),
+            KindleError::Server { status, message } => {
+                write!(f, "Server error {}: {}", status, message)
+            }
+            KindleError::Unreachable(msg) => write!(f, "Could not reach the server: {}", msg),
+            KindleError::Network(msg) => write!(f, "Lost the connection to the server: {}", msg),
+            KindleError::Usb(msg) => write!(f, "USB error: {}", msg),
+            KindleError::Io(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for KindleError {}
+
+impl serde::Serialize for KindleError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("KindleError", 3)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("code", &self.code())?;
+        state.end()
+    }
+}
+
+impl From<rusb::Error> for KindleError {
+    fn from(e: rusb::Error) -> Self {
+        match e {
+            rusb::Error::NoDevice | rusb::Error::NotFound => KindleError::NoDevice,
+            rusb::Error::Access => KindleError::PermissionDenied(e.to_string()),
+            rusb::Error::Busy => KindleError::InterfaceBusy(None),
+            rusb::Error::Timeout => KindleError::Timeout,
+            _ => KindleError::Usb(e.to_string()),
+        }
+    }
+}
+
+impl From<std::io::Error> for KindleError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::PermissionDenied => KindleError::PermissionDenied(e.to_string()),
+            _ => KindleError::Io(e.to_string()),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for KindleError {
+    fn from(e: rusqlite::Error) -> Self {
+        use rusqlite::ErrorCode;
+        match e.sqlite_error_code() {
+            Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase) => {
+                KindleError::CorruptDatabase(e.to_string())
+            }
+            Some(ErrorCode::CannotOpen | ErrorCode::PermissionDenied) => {
+                KindleError::PermissionDenied(e.to_string())
+            }
+            _ => KindleError::Io(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_damaged_databases_count_as_corrupt() {
+        let sqlite_error = |code, message: &str| {
+            KindleError::from(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(code),
+                Some(message.to_string()),
+            ))
+        };
+        assert!(matches!(
+            sqlite_error(rusqlite::ffi::SQLITE_NOTADB, "file is not a database"),
+            KindleError::CorruptDatabase(_)
+        ));
+        assert!(matches!(
+            sqlite_error(
+                rusqlite::ffi::SQLITE_CANTOPEN,
+                "unable to open database file"
+            ),
+            KindleError::PermissionDenied(_)
+        ));
+        assert!(matches!(
+            sqlite_error(rusqlite::ffi::SQLITE_BUSY, "database is locked"),
+            KindleError::Io(_)
+        ));
+    }
+
+    #[test]
+    fn serializes_as_tagged_json() {
+        let err = KindleError::ProtocolError {
+            operation: "GetObject",
+            code: 0x2009,
+        };
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            serde_json::json!({
+                "kind": "protocolError",
+                "message": "GetObject failed: 0x2009",
+                "code": 0x2009,
+            })
+        );
+
+        assert_eq!(
+            serde_json::to_value(KindleError::Cancelled).unwrap(),
+            serde_json::json!({
+                "kind": "cancelled",
+                "message": "Import cancelled by user",
+                "code": null,
+            })
+        );
+    }
+}
@@ -0,0 +1,612 @@
+//! Privileged helper for reading a Kindle over MTP on macOS
+//!
+//! macOS only lets root claim the Kindle's MTP interface. The app listens on a
+//! Unix socket in a private temp directory and starts this binary as root with
+//! `--privileged-helper <socket>`. The helper connects back and reads a single
+//! request, which can only name a `Source` and the USB location of the Kindle,
+//! never a path. It then streams the file back in frames: data chunks and
+//! progress, then done or an error. Nothing is written to disk, and the helper
+//! exits when the app hangs up.
+
+// Only macOS needs root for MTP; the protocol itself is tested on every Unix
+#![cfg_attr(not(target_os = "macos"), allow(dead_code))]
+
+use crate::kindle::readers::Source;
+use crate::kindle::{
+    mtp, CancelToken, DownloadProgress, KindleError, UsbLocation, CLIPPINGS, KOBO_DB,
+    KOREADER_VOCAB,
+};
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+/// Argument that starts the binary as the helper
+pub const HELPER_FLAG: &str = "--privileged-helper";
+
+const PROTOCOL_VERSION: u32 = 2;
+const MAX_REQUEST_LEN: u64 = 4096;
+/// Far above the helper's chunk size; anything larger means the stream is garbled
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+/// How long the helper waits for the app to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const FRAME_DATA: u8 = b'D';
+const FRAME_PROGRESS: u8 = b'P';
+const FRAME_DONE: u8 = b'F';
+const FRAME_ERROR: u8 = b'E';
+
+/// MTP operations named in `ProtocolError`, which must be `'static` on this side
+const MTP_OPERATIONS: &[&str] = &[
+    "OpenSession",
+    "CloseSession",
+    "GetDeviceInfo",
+    "GetStorageIDs",
+    "GetStorageInfo",
+    "GetObjectHandles",
+    "GetObjectInfo",
+    "GetObject",
+    "GetPartialObject",
+    "GetPartialObject64",
+];
+
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct Request {
+    version: u32,
+    source: Source,
+    /// USB bus number and address of the Kindle to read; the first Kindle when absent
+    location: Option<UsbLocation>,
+}
+
+/// `KindleError` with all its fields, so the app gets back exactly what failed
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+enum WireError {
+    NoDevice,
+    PermissionDenied { detail: String },
+    InterfaceBusy { holder: Option<String> },
+    UdevRuleMissing,
+    Timeout,
+    ProtocolError { operation: String, code: u16 },
+    InvalidResponse { detail: String },
+    IncompleteTransfer { expected: u64, received: u64 },
+    CorruptDatabase { detail: String },
+    VocabNotFound,
+    ClippingsNotFound,
+    KoreaderVocabNotFound,
+    KoboDbNotFound,
+    Cancelled,
+    SessionExpired,
+    Server { status: u16, message: String },
+    Unreachable { detail: String },
+    Network { detail: String },
+    Usb { detail: String },
+    Io { detail: String },
+}
+
+impl From<&KindleError> for WireError {
+    fn from(e: &KindleError) -> Self {
+        match e.clone() {
+            KindleError::NoDevice => WireError::NoDevice,
+            KindleError::PermissionDenied(detail) => WireError::PermissionDenied { detail },
+            KindleError::InterfaceBusy(holder) => WireError::InterfaceBusy { holder },
+            KindleError::UdevRuleMissing => WireError::UdevRuleMissing,
+            KindleError::Timeout => WireError::Timeout,
+            KindleError::ProtocolError { operation, code } => WireError::ProtocolError {
+                operation: operation.to_string(),
+                code,
+            },
+            KindleError::InvalidResponse(detail) => WireError::InvalidResponse { detail },
+            KindleError::IncompleteTransfer { expected, received } => {
+                WireError::IncompleteTransfer { expected, received }
+            }
+            KindleError::CorruptDatabase(detail) => WireError::CorruptDatabase { detail },
+            KindleError::VocabNotFound => WireError::VocabNotFound,
+            KindleError::ClippingsNotFound => WireError::ClippingsNotFound,
+            KindleError::KoreaderVocabNotFound => WireError::KoreaderVocabNotFound,
+            KindleError::KoboDbNotFound => WireError::KoboDbNotFound,
+            KindleError::Cancelled => WireError::Cancelled,
+            KindleError::SessionExpired => WireError::SessionExpired,
+            KindleError::Server { status, message } => WireError::Server { status, message },
+            KindleError::Unreachable(detail) => WireError::Unreachable { detail },
+            KindleError::Network(detail) => WireError::Network { detail },
+            KindleError::Usb(detail) => WireError::Usb { detail },
+            KindleError::Io(detail) => WireError::Io { detail },
+        }
+    }
+}
+
+impl From<WireError> for KindleError {
+    fn from(e: WireError) -> Self {
+        match e {
+            WireError::NoDevice => KindleError::NoDevice,
+            WireError::PermissionDenied { detail } => KindleError::PermissionDenied(detail),
+            WireError::InterfaceBusy { holder } => KindleError::InterfaceBusy(holder),
+            WireError::UdevRuleMissing => KindleError::UdevRuleMissing,
+            WireError::Timeout => KindleError::Timeout,
+            WireError::ProtocolError { operation, code } => KindleError::ProtocolError {
+                operation: MTP_OPERATIONS
+                    .iter()
+                    .find(|known| **known == operation)
+                    .copied()
+                    .unwrap_or("MTP operation"),
+                code,
+            },
+            WireError::InvalidResponse { detail } => KindleError::InvalidResponse(detail),
+            WireError::IncompleteTransfer { expected, received } => {
+                KindleError::IncompleteTransfer { expected, received }
+            }
+            WireError::CorruptDatabase { detail } => KindleError::CorruptDatabase(detail),
+            WireError::VocabNotFound => KindleError::VocabNotFound,
+            WireError::ClippingsNotFound => KindleError::ClippingsNotFound,
+            WireError::KoreaderVocabNotFound => KindleError::KoreaderVocabNotFound,
+            WireError::KoboDbNotFound => KindleError::KoboDbNotFound,
+            WireError::Cancelled => KindleError::Cancelled,
+            WireError::SessionExpired => KindleError::SessionExpired,
+            WireError::Server { status, message } => KindleError::Server { status, message },
+            WireError::Unreachable { detail } => KindleError::Unreachable(detail),
+            WireError::Network { detail } => KindleError::Network(detail),
+            WireError::Usb { detail } => KindleError::Usb(detail),
+            WireError::Io { detail } => KindleError::Io(detail),
+        }
+    }
+}
+
+/// Entry point of the helper; returns the process exit code
+pub fn run(socket: &str) -> i32 {
+    let stream = match connect(Path::new(socket)) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    match serve(&stream, download_over_mtp) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// Connects to the app, refusing anything but an existing socket at an absolute path
+fn connect(socket: &Path) -> Result<UnixStream, KindleError> {
+    if !socket.is_absolute() {
+        return Err(KindleError::Io(format!(
+            "{} is not an absolute path",
+            socket.display()
+        )));
+    }
+    if !fs::symlink_metadata(socket)?.file_type().is_socket() {
+        return Err(KindleError::Io(format!(
+            "{} is not a socket",
+            socket.display()
+        )));
+    }
+    let stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    Ok(stream)
+}
+
+/// Answers one request on `stream`, reading the file with `download`
+fn serve(
+    stream: &UnixStream,
+    download: impl FnOnce(
+        Source,
+        Option<UsbLocation>,
+        &mut dyn Write,
+        &mut dyn FnMut(DownloadProgress),
+    ) -> Result<u64, KindleError>,
+) -> Result<u64, KindleError> {
+    let result = read_request(stream).and_then(|request| {
+        download(
+            request.source,
+            request.location,
+            &mut DataFrames(stream),
+            &mut |progress| {
+                let _ = write_frame(stream, FRAME_PROGRESS, &encode_progress(progress));
+            },
+        )
+    });
+    match &result {
+        Ok(total) => write_frame(stream, FRAME_DONE, &total.to_le_bytes())?,
+        Err(e) => {
+            let payload = serde_json::to_vec(&WireError::from(e))
+                .map_err(|e| KindleError::Io(e.to_string()))?;
+            // The app may be gone already, which is why this failed
+            let _ = write_frame(stream, FRAME_ERROR, &payload);
+        }
+    }
+    result
+}
+
+fn read_request(stream: &UnixStream) -> Result<Request, KindleError> {
+    let mut line = String::new();
+    BufReader::new(stream.take(MAX_REQUEST_LEN)).read_line(&mut line)?;
+    let request: Request = serde_json::from_str(line.trim_end())
+        .map_err(|e| KindleError::InvalidResponse(format!("Malformed helper request: {}", e)))?;
+    if request.version != PROTOCOL_VERSION {
+        return Err(KindleError::InvalidResponse(format!(
+            "Unsupported helper protocol version {}",
+            request.version
+        )));
+    }
+    Ok(request)
+}
+
+fn download_over_mtp(
+    source: Source,
+    location: Option<UsbLocation>,
+    out: &mut dyn Write,
+    on_progress: &mut dyn FnMut(DownloadProgress),
+) -> Result<u64, KindleError> {
+    // A write to a closed socket fails, which stops the transfer when the app cancels
+    let cancel = CancelToken::new();
+    let open = || match location {
+        Some(location) => mtp::MtpDevice::find_kindle_at(location),
+        None => mtp::MtpDevice::find_kindle(),
+    };
+    let file = match source {
+        Source::VocabDb => return open()?.download_vocab_db_to(out, &cancel, on_progress),
+        Source::Clippings => &CLIPPINGS,
+        Source::KoreaderVocab => &KOREADER_VOCAB,
+        Source::KoboDb => &KOBO_DB,
+    };
+    if file.mtp_path.is_empty() {
+        return Err(file.not_found.clone());
+    }
+    open()?.download_file_to(
+        file.mtp_path,
+        file.not_found.clone(),
+        out,
+        &cancel,
+        on_progress,
+    )
+}
+
+/// Sends everything written to it as data frames
+struct DataFrames<'a>(&'a UnixStream);
+
+impl Write for DataFrames<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(MAX_FRAME_LEN);
+        write_frame(self.0, FRAME_DATA, &buf[..len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A frame is a tag byte, the payload length as little-endian u32, then the payload
+fn write_frame(mut stream: &UnixStream, tag: u8, payload: &[u8]) -> std::io::Result<()> {
+    let mut header = [tag, 0, 0, 0, 0];
+    header[1..].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    stream.write_all(&header)?;
+    stream.write_all(payload)
+}
+
+fn read_frame(reader: &mut impl Read) -> Result<(u8, Vec<u8>), KindleError> {
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header).map_err(frame_error)?;
+    let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(KindleError::InvalidResponse(format!(
+            "Helper sent a {} byte frame",
+            len
+        )));
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).map_err(frame_error)?;
+    Ok((header[0], payload))
+}
+
+fn frame_error(e: std::io::Error) -> KindleError {
+    match e.kind() {
+        ErrorKind::UnexpectedEof => {
+            KindleError::Io("Privileged helper stopped before finishing".to_string())
+        }
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => KindleError::Timeout,
+        _ => e.into(),
+    }
+}
+
+fn encode_progress(progress: DownloadProgress) -> [u8; 16] {
+    let mut payload = [0u8; 16];
+    payload[..8].copy_from_slice(&progress.bytes_done.to_le_bytes());
+    payload[8..].copy_from_slice(&progress.total.to_le_bytes());
+    payload
+}
+
+fn u64_at(payload: &[u8], offset: usize) -> Result<u64, KindleError> {
+    payload
+        .get(offset..offset + 8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or_else(|| KindleError::InvalidResponse("Short helper frame".to_string()))
+}
+
+/// Reads the helper's answer into `out` until it reports the transfer done
+fn receive(
+    stream: &UnixStream,
+    out: &mut dyn Write,
+    cancel: &CancelToken,
+    on_progress: &mut dyn FnMut(DownloadProgress),
+) -> Result<u64, KindleError> {
+    let mut reader = BufReader::new(stream);
+    let mut received = 0u64;
+    loop {
+        cancel.check()?;
+        let (tag, payload) = read_frame(&mut reader)?;
+        match tag {
+            FRAME_DATA => {
+                out.write_all(&payload)?;
+                received += payload.len() as u64;
+            }
+            FRAME_PROGRESS => on_progress(DownloadProgress {
+                bytes_done: u64_at(&payload, 0)?,
+                total: u64_at(&payload, 8)?,
+            }),
+            FRAME_DONE => {
+                let expected = u64_at(&payload, 0)?;
+                if received != expected {
+                    return Err(KindleError::IncompleteTransfer { expected, received });
+                }
+                return Ok(received);
+            }
+            FRAME_ERROR => {
+                let error: WireError = serde_json::from_slice(&payload).map_err(|e| {
+                    KindleError::InvalidResponse(format!("Malformed helper error: {}", e))
+                })?;
+                return Err(error.into());
+            }
+            other => {
+                return Err(KindleError::InvalidResponse(format!(
+                    "Unknown helper frame {:#04x}",
+                    other
+                )))
+            }
+        }
+    }
+}
+
+/// AppleScript that runs the helper as root, with every argument quoted for the shell
+fn elevate_script(exe: &Path, socket: &Path) -> Result<String, KindleError> {
+    Ok(format!(
+        "do shell script (quoted form of {}) & \" {} \" & (quoted form of {}) \
+         with administrator privileges",
+        applescript_string(exe)?,
+        HELPER_FLAG,
+        applescript_string(socket)?
+    ))
+}
+
+fn applescript_string(path: &Path) -> Result<String, KindleError> {
+    let text = path
+        .to_str()
+        .filter(|text| !text.chars().any(char::is_control))
+        .ok_or_else(|| KindleError::Io(format!("Unsupported path: {}", path.display())))?;
+    Ok(format!(
+        "\"{}\"",
+        text.replace('\\', "\\\\").replace('"', "\\\"")
+    ))
+}
+
+/// Reads `source` off the Kindle at `location`, or the first one, through the
+/// helper, asking for administrator rights
+#[cfg(target_os = "macos")]
+pub fn read_privileged(
+    source: Source,
+    location: Option<UsbLocation>,
+    out: &mut dyn Write,
+    cancel: &CancelToken,
+    on_progress: &mut dyn FnMut(DownloadProgress),
+) -> Result<u64, KindleError> {
+    use std::os::unix::net::UnixListener;
+    use std::process::{Command, Stdio};
+
+    let dir = PrivateDir::create()?;
+    let socket = dir.0.join("helper.sock");
+    let listener = UnixListener::bind(&socket)?;
+    listener.set_nonblocking(true)?;
+
+    let script = elevate_script(&std::env::current_exe()?, &socket)?;
+    let mut child = Command::new("osascript")
+        .arg("-e")
+        .arg(&script)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            KindleError::PermissionDenied(format!("Failed to request admin privileges: {}", e))
+        })?;
+
+    let result = (|| {
+        let stream = loop {
+            let exited = child.try_wait()?.is_some();
+            match listener.accept() {
+                Ok((stream, _)) => break stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock && !exited => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    return Err(elevation_error(&mut child));
+                }
+                Err(e) => return Err(e.into()),
+            }
+            cancel.check()?;
+            std::thread::sleep(Duration::from_millis(100));
+        };
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+
+        let mut request = serde_json::to_vec(&Request {
+            version: PROTOCOL_VERSION,
+            source,
+            location,
+        })
+        .map_err(|e| KindleError::Io(e.to_string()))?;
+        request.push(b'\n');
+        (&stream).write_all(&request)?;
+        receive(&stream, out, cancel, on_progress)
+    })();
+
+    // Hanging up makes the helper's next write fail, so it exits on its own
+    if result.is_err() {
+        let _ = child.kill();
+    }
+    let _ = child.wait();
+    result
+}
+
+/// Why osascript exited without the helper ever connecting
+#[cfg(target_os = "macos")]
+fn elevation_error(child: &mut std::process::Child) -> KindleError {
+    let mut stderr = String::new();
+    if let Some(mut pipe) = child.stderr.take() {
+        let _ = pipe.read_to_string(&mut stderr);
+    }
+    if stderr.contains("User canceled") || stderr.contains("(-128)") {
+        return KindleError::Cancelled;
+    }
+    KindleError::PermissionDenied(format!(
+        "Failed to get administrator rights: {}",
+        stderr.trim()
+    ))
+}
+
+/// Directory only the current user can enter, removed again on drop
+#[cfg(target_os = "macos")]
+struct PrivateDir(std::path::PathBuf);
+
+#[cfg(target_os = "macos")]
+impl PrivateDir {
+    fn create() -> Result<Self, KindleError> {
+        use std::os::unix::fs::DirBuilderExt;
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or_default();
+        let path =
+            std::env::temp_dir().join(format!("mastery-helper-{}-{}", std::process::id(), nanos));
+        // Fails if the path exists, so nobody can plant the directory beforehand
+        fs::DirBuilder::new().mode(0o700).create(&path)?;
+        Ok(Self(path))
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl Drop for PrivateDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn request(stream: &UnixStream, source: Source, location: Option<UsbLocation>) {
+        let mut line = serde_json::to_vec(&Request {
+            version: PROTOCOL_VERSION,
+            source,
+            location,
+        })
+        .unwrap();
+        line.push(b'\n');
+        (&*stream).write_all(&line).unwrap();
+    }
+
+    #[test]
+    fn streams_the_requested_file_in_frames() {
+        let (app, helper) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            serve(&helper, |source, location, out, on_progress| {
+                assert_eq!(source, Source::Clippings);
+                assert_eq!(location, Some((3, 7)));
+                out.write_all(b"==========\n")?;
+                on_progress(DownloadProgress {
+                    bytes_done: 11,
+                    total: 22,
+                });
+                out.write_all(b"Highlight\n\n")?;
+                Ok(22)
+            })
+        });
+
+        request(&app, Source::Clippings, Some((3, 7)));
+        let mut content = Vec::new();
+        let mut progress = Vec::new();
+        let received = receive(&app, &mut content, &CancelToken::new(), &mut |p| {
+            progress.push((p.bytes_done, p.total))
+        });
+        assert_eq!(received, Ok(22));
+        assert_eq!(content, b"==========\nHighlight\n\n");
+        assert_eq!(progress, vec![(11, 22)]);
+        assert_eq!(server.join().unwrap(), Ok(22));
+    }
+
+    #[test]
+    fn passes_errors_back_unchanged() {
+        for error in [
+            KindleError::VocabNotFound,
+            KindleError::InterfaceBusy(Some("Android File Transfer".to_string())),
+            KindleError::ProtocolError {
+                operation: "GetObject",
+                code: 0x2009,
+            },
+        ] {
+            let (app, helper) = UnixStream::pair().unwrap();
+            let expected = error.clone();
+            let server = thread::spawn(move || serve(&helper, |_, _, _, _| Err(error)));
+            request(&app, Source::VocabDb, None);
+            let mut content = Vec::new();
+            assert_eq!(
+                receive(&app, &mut content, &CancelToken::new(), &mut |_| {}),
+                Err(expected.clone())
+            );
+            assert_eq!(server.join().unwrap(), Err(expected));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        let (app, helper) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || serve(&helper, |_, _, _, _| Ok(0)));
+        (&app)
+            .write_all(b"{\"version\":2,\"source\":\"vocabDb\",\"path\":\"/etc/sudoers\"}\n")
+            .unwrap();
+        let mut content = Vec::new();
+        assert!(matches!(
+            receive(&app, &mut content, &CancelToken::new(), &mut |_| {}),
+            Err(KindleError::InvalidResponse(_))
+        ));
+        assert!(server.join().unwrap().is_err());
+        assert!(content.is_empty());
+
+        assert!(connect(Path::new("relative.sock")).is_err());
+        assert!(connect(Path::new("/etc/hosts")).is_err());
+    }
+
+    #[test]
+    fn quotes_paths_for_applescript_and_the_shell() {
+        let script = elevate_script(
+            Path::new("/Applications/Mastery.app/Contents/MacOS/desktop"),
+            Path::new("/tmp/it's \"here\"/helper.sock"),
+        )
+        .unwrap();
+        assert_eq!(
+            script,
+            "do shell script (quoted form of \"/Applications/Mastery.app/Contents/MacOS/desktop\") \
+             & \" --privileged-helper \" & (quoted form of \"/tmp/it's \\\"here\\\"/helper.sock\") \
+             with administrator privileges"
+        );
+        assert!(elevate_script(Path::new("/bin/sh\n"), Path::new("/tmp/s")).is_err());
+    }
+}
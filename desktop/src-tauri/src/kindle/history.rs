@@ -0,0 +1,274 @@
+//! Local record of every import the agent ran, including those that failed
+//!
+//! Entries are appended to a JSON Lines file, so a crash while writing loses at
+//! most the entry being written. Unlike `import_sessions` on the server, failures
+//! before the upload are recorded too, and the history is there offline. Each
+//! entry lists the `import_sessions` IDs its batches created, so the two can be
+//! merged without counting an import twice. Once the file grows past 1 MB it is
+//! cut down to its newest entries, so reading it stays cheap.
+
+use crate::kindle::readers::Source;
+use crate::kindle::KindleError;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Size past which the file is cut down to its newest `KEPT_ENTRIES`
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+/// Entries left after a cut, well over what the UI lists
+const KEPT_ENTRIES: usize = 200;
+
+/// What started the import
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HistoryOrigin {
+    /// An import of a connected reader, by the user or on connect
+    Import,
+    /// Batches sent from the outbox once the server was reachable again
+    Outbox,
+}
+
+/// Step an import failed at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportStage {
+    Read,
+    Parse,
+    Upload,
+    Record,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryError {
+    pub stage: ImportStage,
+    /// `KindleError` kind, or `server` for lookups the server reported it could not import
+    pub kind: String,
+    pub message: String,
+    #[serde(default)]
+    pub code: Option<u16>,
+}
+
+impl HistoryError {
+    pub fn new(stage: ImportStage, error: &KindleError) -> Self {
+        Self {
+            stage,
+            kind: error.kind().to_string(),
+            message: error.to_string(),
+            code: error.code(),
+        }
+    }
+}
+
+/// Sync watermark of the device before and after the import (ms since the Unix epoch)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatermarkMove {
+    pub from: i64,
+    pub to: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    /// Local ID, unique per machine
+    pub id: String,
+    pub origin: HistoryOrigin,
+    /// Serial number of the reader, when it has one
+    pub device_id: Option<String>,
+    pub sources: Vec<Source>,
+    /// When the import started (ms since the Unix epoch)
+    pub started_at: i64,
+    pub duration_ms: u64,
+    /// Lookups read off the reader
+    pub lookups_read: usize,
+    /// Lookups the server accepted
+    pub lookups_sent: usize,
+    /// Lookups left in the outbox
+    pub lookups_queued: usize,
+    pub imported: u64,
+    pub skipped: u64,
+    /// Unset when the sync state did not move
+    pub watermark: Option<WatermarkMove>,
+    /// `import_sessions` rows created by the batches this import sent
+    pub server_session_ids: Vec<String>,
+    /// The failure that ended the import, then the lookups the server rejected
+    pub errors: Vec<HistoryError>,
+}
+
+impl HistoryEntry {
+    pub fn new(origin: HistoryOrigin, sources: Vec<Source>, started_at: i64) -> Self {
+        let prefix = match origin {
+            HistoryOrigin::Import => "import",
+            HistoryOrigin::Outbox => "outbox",
+        };
+        Self {
+            id: format!("{}-{}", prefix, started_at),
+            origin,
+            device_id: None,
+            sources,
+            started_at,
+            duration_ms: 0,
+            lookups_read: 0,
+            lookups_sent: 0,
+            lookups_queued: 0,
+            imported: 0,
+            skipped: 0,
+            watermark: None,
+            server_session_ids: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Records that the device's watermark moved from `from` to `to`, keeping the
+    /// earliest `from` when it moves more than once
+    pub fn move_watermark(&mut self, from: i64, to: i64) {
+        let from = self.watermark.map_or(from, |moved| moved.from);
+        self.watermark = (to != from).then_some(WatermarkMove { from, to });
+    }
+}
+
+/// Import history, persisted as JSON Lines
+#[derive(Debug)]
+pub struct ImportHistory {
+    path: PathBuf,
+    max_file_bytes: u64,
+}
+
+impl ImportHistory {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            max_file_bytes: MAX_FILE_BYTES,
+        }
+    }
+
+    #[cfg(test)]
+    fn with_max_file_bytes(mut self, max_file_bytes: u64) -> Self {
+        self.max_file_bytes = max_file_bytes;
+        self
+    }
+
+    pub fn append(&self, entry: &HistoryEntry) -> Result<(), KindleError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut line = serde_json::to_vec(entry).map_err(|e| KindleError::Io(e.to_string()))?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)?;
+        if file.metadata()?.len() > self.max_file_bytes {
+            self.keep_newest()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the file with only its newest `KEPT_ENTRIES` lines
+    fn keep_newest(&self) -> Result<(), KindleError> {
+        let content = fs::read_to_string(&self.path)?;
+        let lines: Vec<&str> = content.lines().collect();
+        let mut kept = String::new();
+        for line in &lines[lines.len().saturating_sub(KEPT_ENTRIES)..] {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+        // Written aside and renamed, so a crash leaves the old file or the new one
+        let tmp = self.path.with_extension("jsonl.tmp");
+        fs::write(&tmp, kept)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// The newest `limit` entries, newest first
+    ///
+    /// Lines that don't parse, like one cut short by a crash, are skipped.
+    pub fn recent(&self, limit: usize) -> Vec<HistoryEntry> {
+        let Ok(content) = fs::read_to_string(&self.path) else {
+            return Vec::new();
+        };
+        content
+            .lines()
+            .rev()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .take(limit)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty history, deleted with the returned directory
+    fn history() -> (tempfile::TempDir, ImportHistory) {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ImportHistory::new(&dir.path().join("import-history.jsonl"));
+        (dir, history)
+    }
+
+    #[test]
+    fn lists_entries_newest_first() {
+        let (_dir, history) = history();
+        for started_at in [1000, 2000, 3000] {
+            history
+                .append(&HistoryEntry::new(
+                    HistoryOrigin::Import,
+                    vec![Source::VocabDb],
+                    started_at,
+                ))
+                .unwrap();
+        }
+
+        let ids: Vec<_> = history.recent(2).into_iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec!["import-3000", "import-2000"]);
+    }
+
+    #[test]
+    fn cuts_the_file_down_to_the_newest_entries() {
+        let (_dir, history) = history();
+        let history = history.with_max_file_bytes(64 * 1024);
+        for started_at in 0..1000 {
+            history
+                .append(&HistoryEntry::new(
+                    HistoryOrigin::Import,
+                    vec![Source::VocabDb],
+                    started_at,
+                ))
+                .unwrap();
+        }
+
+        assert!(fs::metadata(&history.path).unwrap().len() <= 64 * 1024);
+        let entries = history.recent(usize::MAX);
+        assert!(entries.len() >= KEPT_ENTRIES && entries.len() < 1000);
+        assert_eq!(entries[0].id, "import-999");
+    }
+
+    #[test]
+    fn skips_a_line_cut_short_by_a_crash() {
+        let (_dir, history) = history();
+        let mut entry = HistoryEntry::new(HistoryOrigin::Outbox, vec![Source::VocabDb], 1000);
+        entry.errors.push(HistoryError::new(
+            ImportStage::Upload,
+            &KindleError::Network("offline".to_string()),
+        ));
+        history.append(&entry).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&history.path).unwrap();
+        file.write_all(br#"{"id":"import-2000","origin":"#).unwrap();
+
+        assert_eq!(history.recent(10), vec![entry]);
+    }
+
+    #[test]
+    fn watermark_keeps_where_it_started() {
+        let mut entry = HistoryEntry::new(HistoryOrigin::Outbox, vec![Source::VocabDb], 1000);
+        entry.move_watermark(100, 100);
+        assert_eq!(entry.watermark, None);
+
+        entry.move_watermark(100, 200);
+        entry.move_watermark(200, 300);
+        assert_eq!(entry.watermark, Some(WatermarkMove { from: 100, to: 300 }));
+    }
+}
@@ -0,0 +1,299 @@
+//! Reader for the Kobo library database (`.kobo/KoboReader.sqlite`)
+//!
+//! Kobos only mount as USB mass storage. Their database holds:
+//!
+//! - `WordList`: words looked up in the dictionary (`Text`), the book they were
+//!   looked up in (`VolumeId`) and the dictionary suffix (`-en`, `-de`, ...)
+//! - `Bookmark`: highlights, notes and dog-ears with the highlighted `Text`, the
+//!   note in `Annotation` and where it starts (`StartContainerPath`)
+//! - `content`: books (`ContentType` 6) with `Title` and `Attribution` (author)
+//!
+//! Lookups map onto the vocab.db records and bookmarks onto clippings, so Kobo
+//! imports go through the same flow as Kindle ones. Dates are ISO 8601 in UTC.
+
+use crate::kindle::clippings::{Clipping, ClippingKind};
+use crate::kindle::vocab::{query, with_temp_db, Book, Lookup, Vocab, Word};
+use crate::kindle::KindleError;
+use rusqlite::{Connection, OpenFlags};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+pub const DB_PATH: &str = ".kobo/KoboReader.sqlite";
+
+/// Dictionary lookups and annotations from a Kobo
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KoboLibrary {
+    pub vocab: Vocab,
+    pub clippings: Vec<Clipping>,
+}
+
+/// Opens KoboReader.sqlite read-only and loads lookups and annotations
+pub fn open(path: &Path) -> Result<KoboLibrary, KindleError> {
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    Ok(KoboLibrary {
+        vocab: read_word_list(&conn)?,
+        clippings: read_bookmarks(&conn)?,
+    })
+}
+
+/// Loads records from the bytes returned by `read_device_file(&KOBO_DB, ..)`
+pub fn from_bytes(data: &[u8]) -> Result<KoboLibrary, KindleError> {
+    with_temp_db(data, open)
+}
+
+fn read_word_list(conn: &Connection) -> Result<Vocab, KindleError> {
+    let rows = query(
+        conn,
+        "SELECT w.Text, w.VolumeId, w.DictSuffix, w.DateCreated, c.Title, c.Attribution \
+         FROM WordList w LEFT JOIN content c ON c.ContentID = w.VolumeId AND c.ContentType = 6 \
+         ORDER BY w.DateCreated DESC",
+        |row| {
+            Ok((
+                row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+                row.get::<_, Option<String>>(5)?,
+            ))
+        },
+    )?;
+
+    let mut books = BTreeMap::new();
+    let mut vocab = Vocab::default();
+    for (text, volume, dict_suffix, created, title, author) in rows {
+        let word = text.trim();
+        if word.is_empty() {
+            continue;
+        }
+        let lang = dict_suffix
+            .as_deref()
+            .map(|suffix| suffix.trim_start_matches('-').to_string())
+            .filter(|lang| !lang.is_empty());
+        let word_key = match &lang {
+            Some(lang) => format!("{}:{}", lang, word),
+            None => word.to_string(),
+        };
+        let timestamp = created.as_deref().and_then(parse_iso_millis);
+
+        if let Some(volume) = &volume {
+            books.entry(volume.clone()).or_insert_with(|| Book {
+                id: volume.clone(),
+                asin: None,
+                guid: None,
+                lang: lang.clone(),
+                title,
+                authors: author,
+            });
+        }
+        vocab.words.push(Word {
+            id: word_key.clone(),
+            word: word.to_string(),
+            stem: None,
+            lang,
+            category: 0,
+            timestamp,
+        });
+        vocab.lookups.push(Lookup {
+            id: format!("kobo:{}", word_key),
+            word_key,
+            book_key: volume,
+            dict_key: dict_suffix,
+            // Kobo does not keep the sentence a word was looked up in
+            usage: None,
+            timestamp,
+        });
+    }
+
+    vocab.books = books.into_values().collect();
+    Ok(vocab)
+}
+
+fn read_bookmarks(conn: &Connection) -> Result<Vec<Clipping>, KindleError> {
+    // `Type` only exists since firmware 4.x; older bookmarks are told apart by content
+    let has_type = query(
+        conn,
+        "SELECT name FROM pragma_table_info('Bookmark')",
+        |row| row.get::<_, String>(0),
+    )?
+    .iter()
+    .any(|name| name == "Type");
+
+    let sql = format!(
+        "SELECT {}, b.Text, b.Annotation, b.StartContainerPath, b.DateCreated, \
+                c.Title, c.Attribution, b.VolumeID \
+         FROM Bookmark b LEFT JOIN content c ON c.ContentID = b.VolumeID AND c.ContentType = 6 \
+         WHERE b.Hidden IS NULL OR b.Hidden NOT IN ('true', 1) \
+         ORDER BY b.DateCreated",
+        if has_type { "b.Type" } else { "NULL" }
+    );
+    let rows = query(conn, &sql, |row| {
+        Ok((
+            row.get::<_, Option<String>>(0)?,
+            row.get::<_, Option<String>>(1)?,
+            row.get::<_, Option<String>>(2)?,
+            row.get::<_, Option<String>>(3)?,
+            row.get::<_, Option<String>>(4)?,
+            row.get::<_, Option<String>>(5)?,
+            row.get::<_, Option<String>>(6)?,
+            row.get::<_, Option<String>>(7)?,
+        ))
+    })?;
+
+    let mut clippings = Vec::new();
+    for (kind, text, annotation, location, created, title, author, volume) in rows {
+        let text = text.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+        let annotation = annotation
+            .map(|a| a.trim().to_string())
+            .filter(|a| !a.is_empty());
+        let clipping = |kind, content: String| Clipping {
+            // Sideloaded books may be missing from `content`; the volume path still names them
+            title: title.clone().or_else(|| volume.clone()).unwrap_or_default(),
+            author: author.clone(),
+            kind,
+            page: None,
+            location: location.clone(),
+            added_on: created.clone(),
+            content,
+        };
+
+        if kind.as_deref() == Some("dogear") || (text.is_none() && annotation.is_none()) {
+            clippings.push(clipping(ClippingKind::Bookmark, String::new()));
+            continue;
+        }
+        // A Kobo note is a highlight with a note attached; Kindle lists them as two clippings
+        if let Some(text) = text {
+            clippings.push(clipping(ClippingKind::Highlight, text));
+        }
+        if let Some(annotation) = annotation {
+            clippings.push(clipping(ClippingKind::Note, annotation));
+        }
+    }
+    Ok(clippings)
+}
+
+/// Parses `2024-01-20T10:30:00.000` (optionally with `Z`) as UTC milliseconds
+fn parse_iso_millis(value: &str) -> Option<i64> {
+    let value = value.trim().trim_end_matches('Z');
+    let (date, time) = value.split_once('T').unwrap_or((value, "00:00:00"));
+
+    let mut date = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let (time, fraction) = time.split_once('.').unwrap_or((time, "0"));
+    let mut time = time.splitn(3, ':').map(|p| p.parse::<i64>().ok());
+    let (hour, minute) = (time.next()??, time.next()??);
+    let second = time.next().flatten().unwrap_or(0);
+    let millis = format!("{:0<3}", &fraction[..fraction.len().min(3)])
+        .parse::<i64>()
+        .ok()?;
+
+    // Days since the epoch from a proleptic Gregorian date (Howard Hinnant's algorithm)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    Some(((days * 24 + hour) * 60 + minute) * 60_000 + second * 1000 + millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kindle::vocab::sqlite_bytes;
+
+    fn sample_kobo_db() -> Vec<u8> {
+        sqlite_bytes(
+            "CREATE TABLE content (ContentID TEXT NOT NULL, ContentType TEXT NOT NULL,
+                     Title TEXT, Attribution TEXT, PRIMARY KEY (ContentID));
+                 CREATE TABLE WordList (Text TEXT NOT NULL, VolumeId TEXT, DictSuffix TEXT,
+                     DateCreated TEXT, PRIMARY KEY (Text));
+                 CREATE TABLE Bookmark (BookmarkID TEXT NOT NULL, VolumeID TEXT NOT NULL,
+                     ContentID TEXT NOT NULL, StartContainerPath TEXT NOT NULL, Text TEXT,
+                     Annotation TEXT, DateCreated TEXT, Hidden BOOL DEFAULT 'false', Type TEXT,
+                     PRIMARY KEY (BookmarkID));
+                 INSERT INTO content VALUES ('file:///mnt/onboard/moby.epub', '6', 'Moby Dick',
+                     'Herman Melville');
+                 INSERT INTO content VALUES ('file:///mnt/onboard/moby.epub#ch1', '9',
+                     'Chapter 1', NULL);
+                 INSERT INTO WordList VALUES ('leviathan', 'file:///mnt/onboard/moby.epub', '-en',
+                     '2024-01-20T10:30:00.000');
+                 INSERT INTO WordList VALUES ('Wal', NULL, '-de', '2024-01-21T08:00:00Z');
+                 INSERT INTO Bookmark VALUES ('b1', 'file:///mnt/onboard/moby.epub',
+                     'moby.epub#ch1', 'span#kobo\\.1\\.1', 'Call me Ishmael.', NULL,
+                     '2024-01-20T10:31:00.000', 'false', 'highlight');
+                 INSERT INTO Bookmark VALUES ('b2', 'file:///mnt/onboard/moby.epub',
+                     'moby.epub#ch1', 'span#kobo\\.2\\.1', 'Some years ago', 'Never mind how long',
+                     '2024-01-20T10:32:00.000', 'false', 'note');
+                 INSERT INTO Bookmark VALUES ('b3', 'file:///mnt/onboard/moby.epub',
+                     'moby.epub#ch1', 'span#kobo\\.3\\.1', NULL, NULL,
+                     '2024-01-20T10:33:00.000', 'false', 'dogear');
+                 INSERT INTO Bookmark VALUES ('b4', 'file:///mnt/onboard/moby.epub',
+                     'moby.epub#ch1', 'span#kobo\\.4\\.1', 'Deleted', NULL,
+                     '2024-01-20T10:34:00.000', 'true', 'highlight');",
+        )
+    }
+
+    #[test]
+    fn maps_word_list_to_lookups_with_their_books() {
+        let library = from_bytes(&sample_kobo_db()).unwrap();
+        let vocab = &library.vocab;
+
+        assert_eq!(vocab.words.len(), 2);
+        assert_eq!(vocab.words[0].id, "de:Wal");
+        assert_eq!(vocab.words[1].lang.as_deref(), Some("en"));
+
+        let leviathan = &vocab.lookups[1];
+        assert_eq!(leviathan.word_key, "en:leviathan");
+        assert_eq!(leviathan.timestamp, Some(1_705_746_600_000));
+        assert_eq!(vocab.lookups[0].timestamp, Some(1_705_824_000_000));
+        assert_eq!(
+            leviathan.book_key.as_deref(),
+            Some("file:///mnt/onboard/moby.epub")
+        );
+
+        assert_eq!(vocab.books.len(), 1);
+        assert_eq!(vocab.books[0].title.as_deref(), Some("Moby Dick"));
+        assert_eq!(vocab.books[0].authors.as_deref(), Some("Herman Melville"));
+    }
+
+    #[test]
+    fn maps_bookmarks_to_clippings() {
+        let clippings = from_bytes(&sample_kobo_db()).unwrap().clippings;
+        let summary: Vec<_> = clippings
+            .iter()
+            .map(|c| (c.kind, c.content.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (ClippingKind::Highlight, "Call me Ishmael."),
+                (ClippingKind::Highlight, "Some years ago"),
+                (ClippingKind::Note, "Never mind how long"),
+                (ClippingKind::Bookmark, ""),
+            ]
+        );
+        assert_eq!(clippings[0].title, "Moby Dick");
+        assert_eq!(clippings[0].author.as_deref(), Some("Herman Melville"));
+        assert_eq!(clippings[1].location, clippings[2].location);
+    }
+
+    #[test]
+    fn parses_kobo_dates() {
+        assert_eq!(parse_iso_millis("1970-01-01T00:00:00"), Some(0));
+        assert_eq!(
+            parse_iso_millis("2000-03-01T12:00:00.5Z"),
+            Some(951_912_000_500)
+        );
+        assert_eq!(parse_iso_millis("not a date"), None);
+    }
+}
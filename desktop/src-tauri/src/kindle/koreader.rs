@@ -0,0 +1,234 @@
+//! Reader for KOReader's vocabulary builder (`vocabulary_builder.sqlite3`)
+//!
+//! KOReader keeps one row per word rather than one per lookup:
+//!
+//! - `vocabulary`: the word, the text around it (`prev_context`, `next_context`),
+//!   when it was added (`create_time`) and last reviewed (`review_time`), both in
+//!   seconds since the Unix epoch
+//! - `title`: books, referenced by `vocabulary.title_id`; older databases store
+//!   the title inline in `vocabulary.book_title` instead
+//!
+//! Rows are mapped onto the same records as vocab.db so both sources import alike:
+//! each word becomes one lookup timestamped when it was added, and the word's own
+//! timestamp is its last review.
+
+use crate::kindle::vocab::{has_table, query, with_temp_db, Book, Lookup, Vocab, Word};
+use crate::kindle::KindleError;
+use rusqlite::{Connection, OpenFlags};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Location on a Kindle; Kobos keep KOReader under `.adds/`
+pub const KINDLE_PATH: &str = "koreader/settings/vocabulary_builder.sqlite3";
+pub const KOBO_PATH: &str = ".adds/koreader/settings/vocabulary_builder.sqlite3";
+
+/// Opens the database read-only and maps its words to vocab.db records
+pub fn open(path: &Path) -> Result<Vocab, KindleError> {
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    read(&conn)
+}
+
+/// Loads records from the bytes returned by `read_koreader_vocab_content`
+pub fn from_bytes(data: &[u8]) -> Result<Vocab, KindleError> {
+    with_temp_db(data, open)
+}
+
+struct Entry {
+    word: String,
+    highlight: Option<String>,
+    prev_context: Option<String>,
+    next_context: Option<String>,
+    create_time: Option<i64>,
+    review_time: Option<i64>,
+    book: Option<String>,
+}
+
+fn read(conn: &Connection) -> Result<Vocab, KindleError> {
+    let columns = query(
+        conn,
+        "SELECT name FROM pragma_table_info('vocabulary')",
+        |row| row.get::<_, String>(0),
+    )?;
+    if columns.is_empty() {
+        return Err(KindleError::CorruptDatabase(
+            "no vocabulary table in vocabulary_builder.sqlite3".to_string(),
+        ));
+    }
+    let has_column = |name: &str| columns.iter().any(|c| c == name);
+    let column = |name: &str| {
+        if has_column(name) {
+            format!("v.{}", name)
+        } else {
+            "NULL".to_string()
+        }
+    };
+
+    let (book, join) = if has_column("title_id") && has_table(conn, "title")? {
+        ("t.name", "LEFT JOIN title t ON v.title_id = t.id")
+    } else if has_column("book_title") {
+        ("NULLIF(v.book_title, '')", "")
+    } else {
+        ("NULL", "")
+    };
+
+    let sql = format!(
+        "SELECT v.word, {}, {}, {}, {}, {}, {} FROM vocabulary v {} \
+         ORDER BY v.create_time DESC",
+        column("highlight"),
+        column("prev_context"),
+        column("next_context"),
+        column("create_time"),
+        column("review_time"),
+        book,
+        join
+    );
+    let rows = query(conn, &sql, |row| {
+        Ok(Entry {
+            word: row.get(0)?,
+            highlight: row.get(1)?,
+            prev_context: row.get(2)?,
+            next_context: row.get(3)?,
+            create_time: row.get(4)?,
+            review_time: row.get(5)?,
+            book: row.get(6)?,
+        })
+    })?;
+
+    Ok(to_vocab(rows))
+}
+
+fn to_vocab(rows: Vec<Entry>) -> Vocab {
+    // Books are keyed by title, which KOReader keeps unique
+    let mut books = BTreeMap::new();
+    let mut vocab = Vocab::default();
+
+    for row in rows {
+        let word = row.word.trim();
+        if word.is_empty() {
+            continue;
+        }
+        let created = millis(row.create_time);
+        let book_key = row.book.filter(|t| !t.trim().is_empty()).map(|title| {
+            let key = format!("koreader:{}", title);
+            books.entry(key.clone()).or_insert_with(|| Book {
+                id: key.clone(),
+                asin: None,
+                guid: None,
+                lang: None,
+                title: Some(title),
+                authors: None,
+            });
+            key
+        });
+
+        vocab.words.push(Word {
+            id: word.to_string(),
+            word: word.to_string(),
+            stem: None,
+            lang: None,
+            category: 0,
+            timestamp: millis(row.review_time).or(created),
+        });
+        vocab.lookups.push(Lookup {
+            id: format!("koreader:{}", word),
+            word_key: word.to_string(),
+            book_key,
+            dict_key: None,
+            usage: usage(
+                row.prev_context.as_deref(),
+                row.highlight.as_deref().unwrap_or(word),
+                row.next_context.as_deref(),
+            ),
+            timestamp: created,
+        });
+    }
+
+    vocab.books = books.into_values().collect();
+    vocab
+}
+
+/// Rebuilds the sentence; KOReader stores the context with its own spacing
+fn usage(prev: Option<&str>, word: &str, next: Option<&str>) -> Option<String> {
+    let (prev, next) = (prev.unwrap_or_default(), next.unwrap_or_default());
+    if prev.trim().is_empty() && next.trim().is_empty() {
+        return None;
+    }
+    Some(format!("{}{}{}", prev, word, next).trim().to_string())
+}
+
+fn millis(seconds: Option<i64>) -> Option<i64> {
+    seconds.filter(|s| *s > 0).map(|s| s * 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kindle::vocab::sqlite_bytes;
+
+    #[test]
+    fn maps_words_context_and_titles_to_lookups() {
+        let data = sqlite_bytes(
+            "CREATE TABLE title (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT UNIQUE,
+                 filter INTEGER NOT NULL DEFAULT 1);
+             CREATE TABLE vocabulary (word TEXT NOT NULL UNIQUE PRIMARY KEY, title_id INTEGER,
+                 create_time INTEGER NOT NULL, review_time INTEGER, due_time INTEGER NOT NULL,
+                 review_count INTEGER NOT NULL DEFAULT 0, prev_context TEXT, next_context TEXT,
+                 streak_count INTEGER NOT NULL DEFAULT 0, highlight TEXT);
+             INSERT INTO title (name) VALUES ('Moby Dick');
+             INSERT INTO vocabulary VALUES ('harpoon', 1, 1700000000, 1700100000, 1700200000, 2,
+                 'Queequeg ', ' the whale.', 1, 'harpooned');
+             INSERT INTO vocabulary VALUES ('leviathan', NULL, 1700000500, NULL, 1700200000, 0,
+                 NULL, NULL, 0, NULL);",
+        );
+        let vocab = from_bytes(&data).unwrap();
+
+        assert_eq!(vocab.words.len(), 2);
+        assert_eq!(vocab.lookups[0].word_key, "leviathan");
+        assert_eq!(vocab.lookups[0].usage, None);
+        assert_eq!(vocab.lookups[0].book_key, None);
+
+        let harpoon = &vocab.lookups[1];
+        assert_eq!(
+            harpoon.usage.as_deref(),
+            Some("Queequeg harpooned the whale.")
+        );
+        assert_eq!(harpoon.timestamp, Some(1_700_000_000_000));
+        assert_eq!(vocab.words[1].timestamp, Some(1_700_100_000_000));
+
+        assert_eq!(vocab.books.len(), 1);
+        assert_eq!(
+            harpoon.book_key.as_deref(),
+            Some(vocab.books[0].id.as_str())
+        );
+        assert_eq!(vocab.books[0].title.as_deref(), Some("Moby Dick"));
+    }
+
+    #[test]
+    fn reads_older_schema_with_inline_book_titles() {
+        let data = sqlite_bytes(
+            "CREATE TABLE vocabulary (word TEXT NOT NULL UNIQUE PRIMARY KEY,
+                 book_title TEXT DEFAULT '', create_time INTEGER NOT NULL, review_time INTEGER,
+                 due_time INTEGER NOT NULL, review_count INTEGER NOT NULL DEFAULT 0,
+                 prev_context TEXT, next_context TEXT);
+             INSERT INTO vocabulary VALUES ('quay', 'Moby Dick', 1700000000, NULL, 1700000000, 0,
+                 'along the ', ', watching');
+             INSERT INTO vocabulary VALUES ('ahoy', '', 1600000000, NULL, 1600000000, 0,
+                 NULL, NULL);",
+        );
+        let vocab = from_bytes(&data).unwrap();
+
+        assert_eq!(
+            vocab.lookups[0].usage.as_deref(),
+            Some("along the quay, watching")
+        );
+        assert_eq!(vocab.books.len(), 1);
+        assert_eq!(vocab.lookups[1].book_key, None);
+        assert!(matches!(
+            from_bytes(&sqlite_bytes("CREATE TABLE other (id INTEGER);")),
+            Err(KindleError::CorruptDatabase(_))
+        ));
+    }
+}
@@ -1,287 +1,396 @@
-//! Kindle detection and vocab.db access
+//! Kindle detection and access to vocab.db, My Clippings.txt and KOReader's vocabulary
 //!
 //! Supports all Kindle e-reader models:
-//! - Older models (pre-2024): Mount as USB mass storage, vocab.db at system/vocabulary/
-//! - Newer models (2024+): Use MTP protocol via pure Rust implementation (requires admin privileges)
+//! - Older models (pre-2024): Mount as USB mass storage, vocab.db at system/vocabulary/,
+//!   clippings at documents/
+//! - Newer models (2024+): Use MTP protocol via pure Rust implementation. On Linux the
+//!   udev rule (70-mastery-kindle.rules) grants access; only macOS needs admin privileges,
+//!   which the privileged helper is started with
+//!
+//! Kobos mount as USB mass storage with their library in .kobo/KoboReader.sqlite.
 
+pub mod auto_sync;
+pub mod clippings;
+mod error;
+#[cfg(unix)]
+pub mod helper;
+pub mod history;
+pub mod kobo;
+pub mod koreader;
+#[cfg(target_os = "linux")]
+mod mounts;
 mod mtp;
+pub mod outbox;
+pub mod preview;
+pub mod readers;
+pub mod sync_state;
+pub mod upload;
+mod verify;
+pub mod vocab;
+pub mod watcher;
+
+pub use error::KindleError;
+pub use mtp::KindleIdentity;
 
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::fs;
-use std::process::Command;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+const VOCAB_DB_PATH: &str = "system/vocabulary/vocab.db";
+
+/// A file read off the reader besides vocab.db
+pub struct DeviceFile {
+    /// Paths relative to a mounted volume, tried in order
+    mounted_paths: &'static [&'static str],
+    /// Path on the Kindle's MTP storage; empty for files only found on mounted readers
+    mtp_path: &'static [&'static str],
+    not_found: KindleError,
+    /// What the privileged helper is asked for to read this file
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    source: readers::Source,
+}
+
+pub static CLIPPINGS: DeviceFile = DeviceFile {
+    mounted_paths: &["documents/My Clippings.txt"],
+    mtp_path: &["documents", "My Clippings.txt"],
+    not_found: KindleError::ClippingsNotFound,
+    source: readers::Source::Clippings,
+};
+
+pub static KOREADER_VOCAB: DeviceFile = DeviceFile {
+    mounted_paths: &[koreader::KINDLE_PATH, koreader::KOBO_PATH],
+    mtp_path: &["koreader", "settings", "vocabulary_builder.sqlite3"],
+    not_found: KindleError::KoreaderVocabNotFound,
+    source: readers::Source::KoreaderVocab,
+};
 
-const VOCAB_DB_FILE: &str = "vocab.db";
-const VOCAB_PATH: &str = "system/vocabulary";
+pub static KOBO_DB: DeviceFile = DeviceFile {
+    mounted_paths: &[kobo::DB_PATH],
+    mtp_path: &[],
+    not_found: KindleError::KoboDbNotFound,
+    source: readers::Source::KoboDb,
+};
 
-fn find_vocab_at_path(base_path: &Path) -> Option<PathBuf> {
-    let vocab_path = base_path.join(VOCAB_PATH).join(VOCAB_DB_FILE);
-    if vocab_path.exists() {
-        return Some(vocab_path);
+/// Bytes copied per progress update when reading from a mounted volume
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+fn find_file_at_path(base_path: &Path, relative_path: &str) -> Option<PathBuf> {
+    let path = base_path.join(relative_path);
+    if path.exists() {
+        return Some(path);
     }
     None
 }
 
 fn find_vocab_on_mounted_volumes() -> Option<PathBuf> {
-    #[cfg(target_os = "macos")]
+    find_on_mounted_volumes(VOCAB_DB_PATH)
+}
+
+fn find_device_file_on_mounted_volumes(file: &DeviceFile) -> Option<PathBuf> {
+    file.mounted_paths
+        .iter()
+        .find_map(|path| find_on_mounted_volumes(path))
+}
+
+/// Finds `relative_path` on any mounted volume that may be a Kindle
+fn find_on_mounted_volumes(relative_path: &str) -> Option<PathBuf> {
+    #[cfg(target_os = "linux")]
     {
-        let volumes_dir = Path::new("/Volumes");
-        if !volumes_dir.exists() {
-            return None;
+        if let Some(path) = mounts::candidate_mount_points()
+            .iter()
+            .find_map(|mount_point| find_file_at_path(mount_point, relative_path))
+        {
+            return Some(path);
         }
+        
+        // udisksctl is a process spawn per status poll, so only ask when a Kindle is on the bus
+        mtp::kindle_usb_location()?;
+        mounts::udisks_kindle_mount_points()
+            .iter()
+            .find_map(|mount_point| find_file_at_path(mount_point, relative_path))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        mounted_volumes()
+            .iter()
+            .find_map(|volume| find_file_at_path(volume, relative_path))
+    }
+}
 
-        if let Ok(entries) = fs::read_dir(volumes_dir) {
+/// Roots of every mounted volume that may be an e-reader
+fn mounted_volumes() -> Vec<PathBuf> {
+    #[cfg(target_os = "macos")]
+    {
+        let mut volumes = Vec::new();
+        if let Ok(entries) = fs::read_dir("/Volumes") {
             for entry in entries.flatten() {
                 let volume_path = entry.path();
                 
-                if volume_path.is_symlink() {
+                if volume_path.is_symlink() || !volume_path.is_dir() {
                     continue;
                 }
                 
-                if volume_path.is_dir() {
-                    if let Some(path) = find_vocab_at_path(&volume_path) {
-                        return Some(path);
-                    }
-                    
-                    let internal_storage = volume_path.join("Internal storage");
-                    if internal_storage.exists() {
-                        if let Some(path) = find_vocab_at_path(&internal_storage) {
-                            return Some(path);
-                        }
-                    }
+                let internal_storage = volume_path.join("Internal storage");
+                volumes.push(volume_path);
+                if internal_storage.exists() {
+                    volumes.push(internal_storage);
+                }
+            }
+        }
+        volumes
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut volumes = mounts::candidate_mount_points();
+        if mtp::kindle_usb_location().is_some() {
+            for mount_point in mounts::udisks_kindle_mount_points() {
+                if !volumes.contains(&mount_point) {
+                    volumes.push(mount_point);
                 }
             }
         }
-        None
+        volumes
     }
 
-    #[cfg(not(target_os = "macos"))]
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
     {
-        None
+        Vec::new()
     }
 }
 
-#[cfg(target_os = "macos")]
-fn is_kindle_mtp_device_present() -> bool {
-    let output = Command::new("ioreg")
-        .args(["-p", "IOUSB", "-l", "-w", "0"])
-        .output();
-    
-    match output {
-        Ok(out) => {
-            let stdout = String::from_utf8_lossy(&out.stdout);
-            stdout.contains("idVendor\" = 6473") || 
-            (stdout.to_lowercase().contains("amazon") && stdout.to_lowercase().contains("kindle"))
-        }
-        Err(_) => false,
+/// USB bus number and address
+type UsbLocation = (u8, u8);
+
+/// Identities of the Kindles seen on USB, keyed by location so status polling
+/// doesn't re-run MTP transactions against a device it already knows
+static IDENTITY_CACHE: Mutex<BTreeMap<UsbLocation, Option<KindleIdentity>>> =
+    Mutex::new(BTreeMap::new());
+
+fn identity_at(location: UsbLocation) -> Option<KindleIdentity> {
+    let mut cache = IDENTITY_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(identity) = cache.get(&location) {
+        return identity.clone();
     }
+    
+    let identity = mtp::identify_kindle_at(location).ok();
+    // Addresses are reused after a replug, so forget devices that have left the bus
+    let attached: Vec<_> = mtp::usb_kindles().iter().map(|k| k.location).collect();
+    cache.retain(|location, _| attached.contains(location));
+    cache.insert(location, identity.clone());
+    identity
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+/// Payload of the `kindle-download-progress` event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct KindleStatus {
-    pub connected: bool,
-    pub connection_type: Option<String>,
+pub struct DownloadProgress {
+    pub bytes_done: u64,
+    pub total: u64,
 }
 
-#[allow(dead_code)]
-pub fn is_kindle_connected() -> bool {
-    get_kindle_status().connected
-}
+/// Flag shared with the UI so an in-flight import can be stopped between chunks
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
 
-pub fn get_kindle_status() -> KindleStatus {
-    if find_vocab_on_mounted_volumes().is_some() {
-        return KindleStatus {
-            connected: true,
-            connection_type: Some("mounted".to_string()),
-        };
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
     }
     
-    #[cfg(target_os = "macos")]
-    {
-        if is_kindle_mtp_device_present() {
-            return KindleStatus {
-                connected: true,
-                connection_type: Some("mtp".to_string()),
-            };
-        }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+    
+    /// Re-arms the token for the next import
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
     }
     
-    KindleStatus {
-        connected: false,
-        connection_type: None,
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
     }
+    
+    /// `Err(Cancelled)` once the user has asked to stop
+    pub fn check(&self) -> Result<(), KindleError> {
+        if self.is_cancelled() {
+            return Err(KindleError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+pub fn read_vocab_db_content(
+    cancel: &CancelToken,
+    on_progress: &mut dyn FnMut(DownloadProgress),
+) -> Result<Vec<u8>, KindleError> {
+    let mut content = Vec::new();
+    stream_vocab_db(&mut content, cancel, on_progress)?;
+    verify::verify_sqlite(&content)?;
+    Ok(content)
 }
 
-pub fn read_vocab_db_content() -> Result<Vec<u8>, String> {
+/// Streams vocab.db from the connected Kindle into `out`, reporting progress per chunk
+pub fn stream_vocab_db(
+    out: &mut dyn Write,
+    cancel: &CancelToken,
+    on_progress: &mut dyn FnMut(DownloadProgress),
+) -> Result<u64, KindleError> {
     if let Some(source_path) = find_vocab_on_mounted_volumes() {
-        return fs::read(&source_path)
-            .map_err(|e| format!("Failed to read vocab.db: {}", e));
+        return copy_with_progress(&source_path, out, cancel, on_progress);
     }
     
     #[cfg(target_os = "macos")]
     {
-        read_vocab_via_mtp_privileged()
+        // Only root can claim the MTP interface, so a helper reads it for us
+        helper::read_privileged(readers::Source::VocabDb, None, out, cancel, on_progress)
     }
     
-    #[cfg(not(target_os = "macos"))]
+    #[cfg(target_os = "linux")]
     {
-        Err("Kindle not found. Please connect your Kindle via USB.".to_string())
+        // With the udev rule installed the user can claim the interface directly
+        mtp::MtpDevice::find_kindle()?.download_vocab_db_to(out, cancel, on_progress)
+    }
+    
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        Err(KindleError::NoDevice)
     }
 }
 
-#[cfg(target_os = "macos")]
-fn read_vocab_via_mtp_privileged() -> Result<Vec<u8>, String> {
-    use std::env::temp_dir;
-    
-    let temp_path = temp_dir().join("mastery_vocab_temp.db");
-    
-    let current_exe = std::env::current_exe()
-        .map_err(|e| format!("Failed to get current exe: {}", e))?;
-    
-    let script = format!(
-        r#"do shell script "{} --sync-vocab '{}'" with administrator privileges"#,
-        current_exe.display(),
-        temp_path.display()
-    );
-    
-    let output = Command::new("osascript")
-        .arg("-e")
-        .arg(&script)
-        .output()
-        .map_err(|e| format!("Failed to request admin privileges: {}", e))?;
-    
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    
-    if !output.status.success() {
-        if stderr.contains("User canceled") || stderr.contains("(-128)") {
-            return Err("Import cancelled by user".to_string());
+/// Reads vocab.db from the reader attached over `transport`, checking it is a complete database
+pub fn read_reader_vocab_db(
+    transport: &readers::Transport,
+    cancel: &CancelToken,
+    on_progress: &mut dyn FnMut(DownloadProgress),
+) -> Result<Vec<u8>, KindleError> {
+    let mut content = Vec::new();
+    match transport {
+        readers::Transport::Mounted { path } => {
+            let source_path =
+                find_file_at_path(path, VOCAB_DB_PATH).ok_or(KindleError::VocabNotFound)?;
+            copy_with_progress(&source_path, &mut content, cancel, on_progress)?;
+        }
+        readers::Transport::Mtp { bus, address } => {
+            download_vocab_db_at((*bus, *address), &mut content, cancel, on_progress)?;
         }
-        return Err(format!("MTP access failed: {}", stderr.trim()));
-    }
-    
-    if !temp_path.exists() {
-        return Err("Failed to read from Kindle via MTP".to_string());
     }
-    
-    let content = fs::read(&temp_path)
-        .map_err(|e| format!("Failed to read temp file: {}", e))?;
-    
-    let _ = fs::remove_file(&temp_path);
-    
+    verify::verify_sqlite(&content)?;
     Ok(content)
 }
 
-pub fn sync_vocab_db(output_path: &Path) -> Result<u64, String> {
-    if let Some(source_path) = find_vocab_on_mounted_volumes() {
-        fs::copy(&source_path, output_path)
-            .map_err(|e| format!("Failed to copy vocab.db: {}", e))?;
-        
-        let size = fs::metadata(output_path)
-            .map(|m| m.len())
-            .unwrap_or(0);
-        
-        return Ok(size);
-    }
-    
+/// Streams vocab.db over MTP from the Kindle at `location`
+fn download_vocab_db_at(
+    location: UsbLocation,
+    out: &mut dyn Write,
+    cancel: &CancelToken,
+    on_progress: &mut dyn FnMut(DownloadProgress),
+) -> Result<u64, KindleError> {
     #[cfg(target_os = "macos")]
     {
-        sync_vocab_via_mtp(output_path)
+        helper::read_privileged(readers::Source::VocabDb, Some(location), out, cancel, on_progress)
     }
-    
-    #[cfg(not(target_os = "macos"))]
+
+    #[cfg(target_os = "linux")]
     {
-        Err("Kindle not found".to_string())
+        mtp::MtpDevice::find_kindle_at(location)?.download_vocab_db_to(out, cancel, on_progress)
     }
-}
 
-#[cfg(target_os = "macos")]
-fn sync_vocab_via_mtp(output_path: &Path) -> Result<u64, String> {
-    mtp::sync_vocab_via_mtp(output_path)
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        let _ = (location, out, cancel, on_progress);
+        Err(KindleError::NoDevice)
+    }
 }
 
-#[allow(dead_code)]
-pub fn sync_vocab_with_privileges(output_path: &Path) -> Result<String, String> {
-    if let Some(source_path) = find_vocab_on_mounted_volumes() {
-        fs::copy(&source_path, output_path)
-            .map_err(|e| format!("Failed to copy vocab.db: {}", e))?;
-        
-        let size = fs::metadata(output_path)
-            .map(|m| m.len())
-            .unwrap_or(0);
-        
-        return Ok(format!("Copied from mounted volume ({} bytes)", size));
+/// Reads `file` from the connected reader
+pub fn read_device_file(
+    file: &DeviceFile,
+    cancel: &CancelToken,
+    on_progress: &mut dyn FnMut(DownloadProgress),
+) -> Result<Vec<u8>, KindleError> {
+    let mut content = Vec::new();
+    if let Some(source_path) = find_device_file_on_mounted_volumes(file) {
+        copy_with_progress(&source_path, &mut content, cancel, on_progress)?;
+        return Ok(content);
     }
-    
+    if file.mtp_path.is_empty() {
+        return Err(file.not_found.clone());
+    }
+
     #[cfg(target_os = "macos")]
     {
-        let current_exe = std::env::current_exe()
-            .map_err(|e| format!("Failed to get current exe: {}", e))?;
-        
-        let script = format!(
-            r#"do shell script "{} --sync-vocab '{}'" with administrator privileges"#,
-            current_exe.display(),
-            output_path.display()
-        );
-        
-        let output = Command::new("osascript")
-            .arg("-e")
-            .arg(&script)
-            .output()
-            .map_err(|e| format!("Failed to request admin privileges: {}", e))?;
-        
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        
-        if output.status.success() {
-            let stdout_str = stdout.trim();
-            for line in stdout_str.lines() {
-                if let Some((size_str, msg)) = line.split_once('|') {
-                    if let Ok(size) = size_str.trim().parse::<u64>() {
-                        return Ok(format!("{} ({} bytes)", msg, size));
-                    }
-                }
-            }
-            let size = fs::metadata(output_path).map(|m| m.len()).unwrap_or(0);
-            if size > 0 {
-                Ok(format!("Downloaded vocab.db ({} bytes)", size))
-            } else {
-                Err(format!("Sync may have failed. stdout: {}", stdout_str))
-            }
-        } else {
-            if stderr.contains("User canceled") || stderr.contains("(-128)") {
-                Err("Sync cancelled by user".to_string())
-            } else {
-                Err(format!("Sync failed: {}", stderr.trim()))
-            }
-        }
+        helper::read_privileged(file.source, None, &mut content, cancel, on_progress)?;
+        Ok(content)
     }
-    
-    #[cfg(not(target_os = "macos"))]
+
+    #[cfg(target_os = "linux")]
     {
-        Err("Platform not supported".to_string())
+        mtp::MtpDevice::find_kindle()?.download_file_to(
+            file.mtp_path,
+            file.not_found.clone(),
+            &mut content,
+            cancel,
+            on_progress,
+        )?;
+        Ok(content)
     }
+
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        Err(KindleError::NoDevice)
+    }
+}
+
+/// Reads KOReader's vocabulary_builder.sqlite3, checking it is a complete database
+pub fn read_koreader_vocab_content(
+    cancel: &CancelToken,
+    on_progress: &mut dyn FnMut(DownloadProgress),
+) -> Result<Vec<u8>, KindleError> {
+    let content = read_device_file(&KOREADER_VOCAB, cancel, on_progress)?;
+    verify::verify_sqlite(&content)?;
+    Ok(content)
+}
+
+/// Reads KoboReader.sqlite from a mounted Kobo, checking it is a complete database
+pub fn read_kobo_db_content(
+    cancel: &CancelToken,
+    on_progress: &mut dyn FnMut(DownloadProgress),
+) -> Result<Vec<u8>, KindleError> {
+    let content = read_device_file(&KOBO_DB, cancel, on_progress)?;
+    verify::verify_sqlite(&content)?;
+    Ok(content)
 }
 
-pub fn handle_sync_vocab_cli(output_path: &str) {
-    let path = Path::new(output_path);
+fn copy_with_progress(
+    source_path: &Path,
+    out: &mut dyn Write,
+    cancel: &CancelToken,
+    on_progress: &mut dyn FnMut(DownloadProgress),
+) -> Result<u64, KindleError> {
+    let mut file = fs::File::open(source_path)?;
+    let total = file.metadata()?.len();
+    let mut buf = vec![0u8; COPY_CHUNK_SIZE];
+    let mut bytes_done = 0u64;
     
-    if let Some(parent) = path.parent() {
-        if !parent.exists() {
-            if let Err(e) = fs::create_dir_all(parent) {
-                eprintln!("Failed to create directory: {}", e);
-                std::process::exit(1);
-            }
+    on_progress(DownloadProgress { bytes_done, total });
+    loop {
+        cancel.check()?;
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
         }
+        out.write_all(&buf[..n])?;
+        bytes_done += n as u64;
+        on_progress(DownloadProgress { bytes_done, total });
     }
-    
-    match sync_vocab_db(path) {
-        Ok(size) => {
-            println!("{}|Downloaded via MTP", size);
-            std::process::exit(0);
-        }
-        Err(e) => {
-            eprintln!("{}", e);
-            std::process::exit(1);
-        }
+    if bytes_done < total {
+        return Err(KindleError::IncompleteTransfer { expected: total, received: bytes_done });
     }
+    Ok(bytes_done)
 }
+
@@ -0,0 +1,216 @@
+//! Linux discovery of Kindles mounted as USB mass storage
+//!
+//! Desktop automounters put removable volumes under `/media/$USER` (Debian/Ubuntu)
+//! or `/run/media/$USER` (Fedora/Arch), but users may also mount the Kindle anywhere
+//! by hand. `/proc/self/mountinfo` lists all of them; udisks2 (via `udisksctl`, its
+//! D-Bus client) is the fallback because it knows which drive is a Kindle.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Filesystems a Kindle's user storage can be formatted with
+const KINDLE_FS_TYPES: &[&str] = &["vfat", "msdos", "exfat", "fuseblk"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MountEntry {
+    mount_point: PathBuf,
+    fs_type: String,
+}
+
+/// Mount points from the mount table that may hold a Kindle, most likely first
+pub fn candidate_mount_points() -> Vec<PathBuf> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_default();
+
+    rank_mounts(parse_mountinfo(&mountinfo), &user)
+}
+
+/// Parses the mount point and filesystem type of each line (proc(5), /proc/pid/mountinfo)
+fn parse_mountinfo(content: &str) -> Vec<MountEntry> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let mount_point = fields.get(4)?;
+            // Optional fields end with a lone "-", followed by the filesystem type
+            let separator = fields.iter().skip(6).position(|f| *f == "-")? + 6;
+            let fs_type = fields.get(separator + 1)?;
+            Some(MountEntry {
+                mount_point: PathBuf::from(unescape_mount_path(mount_point)),
+                fs_type: fs_type.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Undoes the octal escaping of spaces, tabs, newlines and backslashes in mount paths
+fn unescape_mount_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() {
+            let octal = std::str::from_utf8(&bytes[i + 1..i + 4]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(octal, 8) {
+                out.push(byte);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Keeps mounts with a Kindle-compatible filesystem or under the user's media
+/// directories, with the automounter locations first
+fn rank_mounts(mounts: Vec<MountEntry>, user: &str) -> Vec<PathBuf> {
+    let media_dirs = [
+        PathBuf::from("/media").join(user),
+        PathBuf::from("/run/media").join(user),
+    ];
+    let in_media_dir =
+        |path: &Path| !user.is_empty() && media_dirs.iter().any(|dir| path.starts_with(dir));
+
+    let (mut preferred, mut others): (Vec<PathBuf>, Vec<PathBuf>) = (Vec::new(), Vec::new());
+    for mount in mounts {
+        if in_media_dir(&mount.mount_point) {
+            preferred.push(mount.mount_point);
+        } else if KINDLE_FS_TYPES.contains(&mount.fs_type.as_str()) {
+            others.push(mount.mount_point);
+        }
+    }
+    preferred.append(&mut others);
+    preferred.dedup();
+    preferred
+}
+
+/// Mount points of filesystems udisks2 attributes to a Kindle drive
+pub fn udisks_kindle_mount_points() -> Vec<PathBuf> {
+    match Command::new("udisksctl").arg("dump").output() {
+        Ok(output) if output.status.success() => {
+            parse_udisks_dump(&String::from_utf8_lossy(&output.stdout))
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Reads `udisksctl dump`, pairing each block device's `Drive` with its `MountPoints`
+fn parse_udisks_dump(dump: &str) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let mut is_kindle = false;
+    let mut mount_points: Vec<PathBuf> = Vec::new();
+    let mut in_mount_points = false;
+
+    let mut flush = |is_kindle: bool, mount_points: &mut Vec<PathBuf>| {
+        if is_kindle {
+            found.append(mount_points);
+        }
+        mount_points.clear();
+    };
+
+    for line in dump.lines() {
+        if !line.starts_with(' ') && line.ends_with(':') {
+            flush(is_kindle, &mut mount_points);
+            is_kindle = false;
+            in_mount_points = false;
+            continue;
+        }
+
+        let trimmed = line.trim();
+        if let Some((key, value)) = trimmed
+            .split_once(':')
+            .filter(|(key, _)| !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric()))
+        {
+            let value = value.trim();
+            in_mount_points = key == "MountPoints";
+            match key {
+                "Drive" => is_kindle = value.to_lowercase().contains("kindle"),
+                "MountPoints" if !value.is_empty() => mount_points.push(PathBuf::from(value)),
+                _ => {}
+            }
+        } else if trimmed.ends_with(':') {
+            // Next D-Bus interface of the same object
+            in_mount_points = false;
+        } else if in_mount_points && !trimmed.is_empty() {
+            // Further mount points continue on their own indented lines
+            mount_points.push(PathBuf::from(trimmed));
+        }
+    }
+    flush(is_kindle, &mut mount_points);
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw
+30 22 259:1 / /boot/efi rw,relatime shared:5 - vfat /dev/nvme0n1p1 rw,fmask=0077
+91 22 8:17 / /mnt/my\\040kindle rw,nosuid,nodev - vfat /dev/sdc1 rw,uid=1000
+95 22 8:1 / /media/alex/Kindle rw,nosuid,nodev,relatime shared:300 master:1 - vfat /dev/sdb1 rw
+98 22 8:33 / /run/media/alex/KOBOeReader rw,nosuid - vfat /dev/sdd1 rw
+99 22 0:52 / /run/user/1000/doc rw - fuse.portal portal rw";
+
+    #[test]
+    fn parses_mountinfo_with_optional_fields_and_escapes() {
+        let mounts = parse_mountinfo(MOUNTINFO);
+        assert_eq!(mounts.len(), 6);
+        assert_eq!(
+            mounts[2],
+            MountEntry {
+                mount_point: PathBuf::from("/mnt/my kindle"),
+                fs_type: "vfat".to_string(),
+            }
+        );
+        assert_eq!(mounts[3].mount_point, PathBuf::from("/media/alex/Kindle"));
+        assert_eq!(mounts[3].fs_type, "vfat");
+    }
+
+    #[test]
+    fn ranks_user_media_mounts_before_other_vfat_volumes() {
+        assert_eq!(
+            rank_mounts(parse_mountinfo(MOUNTINFO), "alex"),
+            vec![
+                PathBuf::from("/media/alex/Kindle"),
+                PathBuf::from("/run/media/alex/KOBOeReader"),
+                PathBuf::from("/boot/efi"),
+                PathBuf::from("/mnt/my kindle"),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_kindle_mount_points_in_udisks_dump() {
+        let dump = "\
+/org/freedesktop/UDisks2/block_devices/sda1:
+  org.freedesktop.UDisks2.Block:
+    Device:                     /dev/sda1
+    Drive:                      '/org/freedesktop/UDisks2/drives/Samsung_SSD'
+  org.freedesktop.UDisks2.Filesystem:
+    MountPoints:                /home
+
+/org/freedesktop/UDisks2/block_devices/sdb:
+  org.freedesktop.UDisks2.Block:
+    Device:                     /dev/sdb
+    Drive:                      '/org/freedesktop/UDisks2/drives/Kindle_Internal_Storage_G000TEST'
+    IdType:                     vfat
+  org.freedesktop.UDisks2.Filesystem:
+    MountPoints:                /srv/kindle
+                                /media/alex/Kindle
+    Size:                       7000000000
+";
+        assert_eq!(
+            parse_udisks_dump(dump),
+            vec![
+                PathBuf::from("/srv/kindle"),
+                PathBuf::from("/media/alex/Kindle")
+            ]
+        );
+    }
+}
@@ -0,0 +1,481 @@
+//! In-memory MTP responder for exercising `MtpDevice` without hardware
+//!
+//! `FakeKindle` implements `UsbTransport` and answers PTP commands from a virtual
+//! object tree. Responses can be scripted per operation to simulate device errors.
+
+use super::transport::UsbTransport;
+use super::*;
+use std::collections::{HashMap, VecDeque};
+
+const PTP_RC_GENERAL_ERROR: u16 = 0x2002;
+const PTP_RC_OPERATION_NOT_SUPPORTED: u16 = 0x2005;
+const PTP_RC_INVALID_STORAGE_ID: u16 = 0x2008;
+const PTP_RC_INVALID_OBJECT_HANDLE: u16 = 0x2009;
+
+const PTP_OFC_UNDEFINED: u16 = 0x3000;
+const PTP_OFC_ASSOCIATION: u16 = 0x3001;
+
+/// Parent handle of objects that live in the storage root
+const ROOT: u32 = 0;
+
+const FAKE_CAPACITY: u64 = 8 * 1024 * 1024 * 1024;
+
+struct FakeObject {
+    storage_id: u32,
+    parent: u32,
+    name: String,
+    format: u16,
+    data: Vec<u8>,
+    /// Size advertised in ObjectInfo when it differs from `data`
+    reported_size: Option<u32>,
+}
+
+pub struct FakeKindle {
+    pub manufacturer: String,
+    pub model: String,
+    pub serial_number: String,
+    pub firmware_version: String,
+    /// Operations advertised in DeviceInfo
+    pub operations_supported: Vec<u16>,
+    storages: Vec<u32>,
+    objects: HashMap<u32, FakeObject>,
+    next_handle: u32,
+    session_open: bool,
+    scripted: HashMap<u16, VecDeque<u16>>,
+    interrupted: HashMap<u16, u32>,
+    outgoing: VecDeque<Vec<u8>>,
+    /// Operation codes received from the host, in order
+    pub operations: Vec<u16>,
+    /// Still Image class requests received from the host, in order
+    pub class_requests: Vec<u8>,
+    /// Keeps reporting DeviceBusy after a Cancel Request until the device is reset
+    pub ignores_cancel: bool,
+    busy: bool,
+}
+
+impl FakeKindle {
+    pub fn new() -> Self {
+        Self {
+            manufacturer: "Amazon".to_string(),
+            model: "Kindle".to_string(),
+            serial_number: "G000TEST00000001".to_string(),
+            firmware_version: "5.16.2".to_string(),
+            operations_supported: vec![
+                PTP_OC_GET_DEVICE_INFO,
+                PTP_OC_OPEN_SESSION,
+                PTP_OC_CLOSE_SESSION,
+                PTP_OC_GET_STORAGE_IDS,
+                PTP_OC_GET_STORAGE_INFO,
+                PTP_OC_GET_OBJECT_HANDLES,
+                PTP_OC_GET_OBJECT_INFO,
+                PTP_OC_GET_OBJECT,
+                PTP_OC_GET_PARTIAL_OBJECT,
+                MTP_OC_GET_PARTIAL_OBJECT_64,
+            ],
+            storages: Vec::new(),
+            objects: HashMap::new(),
+            next_handle: 1,
+            session_open: false,
+            scripted: HashMap::new(),
+            interrupted: HashMap::new(),
+            outgoing: VecDeque::new(),
+            operations: Vec::new(),
+            class_requests: Vec::new(),
+            ignores_cancel: false,
+            busy: false,
+        }
+    }
+
+    /// A Kindle with one storage holding `system/vocabulary/vocab.db`
+    pub fn with_vocab_db(data: &[u8]) -> Self {
+        let mut kindle = Self::new();
+        let storage = kindle.add_storage(0x0001_0001);
+        let system = kindle.add_folder(storage, ROOT, "system");
+        let vocabulary = kindle.add_folder(storage, system, "vocabulary");
+        kindle.add_file(storage, vocabulary, "vocab.db", data);
+        kindle
+    }
+
+    pub fn add_storage(&mut self, storage_id: u32) -> u32 {
+        self.storages.push(storage_id);
+        storage_id
+    }
+
+    /// Adds a folder under `parent` (`0` for the storage root) and returns its handle
+    pub fn add_folder(&mut self, storage_id: u32, parent: u32, name: &str) -> u32 {
+        self.add_object(storage_id, parent, name, PTP_OFC_ASSOCIATION, Vec::new())
+    }
+
+    /// Adds a file under `parent` (`0` for the storage root) and returns its handle
+    pub fn add_file(&mut self, storage_id: u32, parent: u32, name: &str, data: &[u8]) -> u32 {
+        self.add_object(storage_id, parent, name, PTP_OFC_UNDEFINED, data.to_vec())
+    }
+
+    fn add_object(
+        &mut self,
+        storage_id: u32,
+        parent: u32,
+        name: &str,
+        format: u16,
+        data: Vec<u8>,
+    ) -> u32 {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.objects.insert(
+            handle,
+            FakeObject {
+                storage_id,
+                parent,
+                name: name.to_string(),
+                format,
+                data,
+                reported_size: None,
+            },
+        );
+        handle
+    }
+
+    /// Answers the next `operation` with `response_code` instead of executing it
+    pub fn script_response(&mut self, operation: u16, response_code: u16) {
+        self.scripted
+            .entry(operation)
+            .or_default()
+            .push_back(response_code);
+    }
+
+    /// Cuts the data phase of the next `times` `operation`s short, as if the cable was jiggled
+    pub fn interrupt(&mut self, operation: u16, times: u32) {
+        *self.interrupted.entry(operation).or_default() += times;
+    }
+
+    /// Makes ObjectInfo report `size` bytes for `handle` regardless of its content
+    pub fn report_size(&mut self, handle: u32, size: u32) {
+        if let Some(object) = self.objects.get_mut(&handle) {
+            object.reported_size = Some(size);
+        }
+    }
+
+    /// Whether the host currently holds an open session
+    pub fn session_open(&self) -> bool {
+        self.session_open
+    }
+
+    fn handle_command(&mut self, code: u16, transaction_id: u32, params: &[u32]) {
+        self.operations.push(code);
+
+        if let Some(response_code) = self.scripted.get_mut(&code).and_then(|q| q.pop_front()) {
+            self.queue_response(response_code, transaction_id);
+            return;
+        }
+
+        if code == PTP_OC_GET_DEVICE_INFO {
+            let payload = self.device_info();
+            self.queue_data(code, transaction_id, &payload);
+            self.queue_response(PTP_RC_OK, transaction_id);
+            return;
+        }
+
+        if code == PTP_OC_OPEN_SESSION {
+            let response_code = if self.session_open {
+                PTP_RC_SESSION_ALREADY_OPEN
+            } else {
+                self.session_open = true;
+                PTP_RC_OK
+            };
+            self.queue_response(response_code, transaction_id);
+            return;
+        }
+
+        if !self.session_open {
+            self.queue_response(PTP_RC_SESSION_NOT_OPEN, transaction_id);
+            return;
+        }
+
+        if code == PTP_OC_CLOSE_SESSION {
+            self.session_open = false;
+            self.queue_response(PTP_RC_OK, transaction_id);
+            return;
+        }
+
+        let param = |i: usize| params.get(i).copied().unwrap_or(0);
+        let result = match code {
+            PTP_OC_GET_STORAGE_IDS => Ok(u32_array(&self.storages)),
+            PTP_OC_GET_STORAGE_INFO => self.storage_info(param(0)),
+            PTP_OC_GET_OBJECT_HANDLES => self.object_handles(param(0), param(2)),
+            PTP_OC_GET_OBJECT_INFO => self.object_info(param(0)),
+            PTP_OC_GET_OBJECT => self
+                .objects
+                .get(&param(0))
+                .map(|object| object.data.clone())
+                .ok_or(PTP_RC_INVALID_OBJECT_HANDLE),
+            PTP_OC_GET_PARTIAL_OBJECT => self.partial_object(param(0), param(1) as u64, param(2)),
+            MTP_OC_GET_PARTIAL_OBJECT_64 => self.partial_object(
+                param(0),
+                param(1) as u64 | (param(2) as u64) << 32,
+                param(3),
+            ),
+            _ => Err(PTP_RC_OPERATION_NOT_SUPPORTED),
+        };
+
+        match result {
+            Ok(payload) if self.take_interruption(code) => {
+                // Header promises the full payload but only half of it ever arrives
+                let mut buf =
+                    container_header(12 + payload.len(), PTP_CONTAINER_DATA, code, transaction_id);
+                buf.extend_from_slice(&payload[..payload.len() / 2]);
+                self.outgoing.push_back(buf);
+            }
+            Ok(payload) => {
+                self.queue_data(code, transaction_id, &payload);
+                self.queue_response(PTP_RC_OK, transaction_id);
+            }
+            Err(response_code) => self.queue_response(response_code, transaction_id),
+        }
+    }
+
+    fn take_interruption(&mut self, code: u16) -> bool {
+        match self.interrupted.get_mut(&code) {
+            Some(remaining) if *remaining > 0 => {
+                *remaining -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn device_info(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.write_u16::<LittleEndian>(100).unwrap(); // standard version
+        buf.write_u32::<LittleEndian>(6).unwrap(); // vendor extension: MTP
+        buf.write_u16::<LittleEndian>(100).unwrap();
+        write_ptp_string(&mut buf, "microsoft.com: 1.0;");
+        buf.write_u16::<LittleEndian>(0).unwrap(); // functional mode
+        buf.extend(u16_array(&self.operations_supported));
+        buf.extend(u16_array(&[])); // events
+        buf.extend(u16_array(&[])); // device properties
+        buf.extend(u16_array(&[])); // capture formats
+        buf.extend(u16_array(&[PTP_OFC_UNDEFINED, PTP_OFC_ASSOCIATION]));
+        write_ptp_string(&mut buf, &self.manufacturer);
+        write_ptp_string(&mut buf, &self.model);
+        write_ptp_string(&mut buf, &self.firmware_version);
+        write_ptp_string(&mut buf, &self.serial_number);
+        buf
+    }
+
+    fn storage_info(&self, storage_id: u32) -> Result<Vec<u8>, u16> {
+        if !self.storages.contains(&storage_id) {
+            return Err(PTP_RC_INVALID_STORAGE_ID);
+        }
+        let used: u64 = self
+            .objects
+            .values()
+            .filter(|object| object.storage_id == storage_id)
+            .map(|object| object.data.len() as u64)
+            .sum();
+
+        let mut buf = Vec::new();
+        buf.write_u16::<LittleEndian>(3).unwrap(); // fixed RAM
+        buf.write_u16::<LittleEndian>(2).unwrap(); // hierarchical filesystem
+        buf.write_u16::<LittleEndian>(0).unwrap(); // read-write
+        buf.write_u64::<LittleEndian>(FAKE_CAPACITY).unwrap();
+        buf.write_u64::<LittleEndian>(FAKE_CAPACITY - used).unwrap();
+        buf.write_u32::<LittleEndian>(0xFFFFFFFF).unwrap();
+        write_ptp_string(&mut buf, "Internal Storage");
+        write_ptp_string(&mut buf, "Kindle");
+        Ok(buf)
+    }
+
+    fn object_handles(&self, storage_id: u32, parent: u32) -> Result<Vec<u8>, u16> {
+        if storage_id != 0xFFFFFFFF && !self.storages.contains(&storage_id) {
+            return Err(PTP_RC_INVALID_STORAGE_ID);
+        }
+        let mut handles: Vec<u32> = self
+            .objects
+            .iter()
+            .filter(|(_, object)| storage_id == 0xFFFFFFFF || object.storage_id == storage_id)
+            .filter(|(_, object)| match parent {
+                0 => true,
+                0xFFFFFFFF => object.parent == ROOT,
+                _ => object.parent == parent,
+            })
+            .map(|(handle, _)| *handle)
+            .collect();
+        handles.sort_unstable();
+        Ok(u32_array(&handles))
+    }
+
+    fn object_info(&self, handle: u32) -> Result<Vec<u8>, u16> {
+        let object = self
+            .objects
+            .get(&handle)
+            .ok_or(PTP_RC_INVALID_OBJECT_HANDLE)?;
+
+        let mut buf = Vec::new();
+        buf.write_u32::<LittleEndian>(object.storage_id).unwrap();
+        buf.write_u16::<LittleEndian>(object.format).unwrap();
+        buf.write_u16::<LittleEndian>(0).unwrap(); // protection
+        buf.write_u32::<LittleEndian>(object.reported_size.unwrap_or(object.data.len() as u32))
+            .unwrap();
+        // Thumbnail and image fields are unused by the Kindle
+        buf.resize(38, 0);
+        buf.write_u32::<LittleEndian>(object.parent).unwrap();
+        buf.write_u16::<LittleEndian>(0).unwrap(); // association type
+        buf.write_u32::<LittleEndian>(0).unwrap(); // association desc
+        buf.write_u32::<LittleEndian>(0).unwrap(); // sequence number
+        write_ptp_string(&mut buf, &object.name);
+        write_ptp_string(&mut buf, ""); // date created
+        write_ptp_string(&mut buf, ""); // date modified
+        write_ptp_string(&mut buf, ""); // keywords
+        Ok(buf)
+    }
+
+    fn partial_object(&self, handle: u32, offset: u64, max_bytes: u32) -> Result<Vec<u8>, u16> {
+        let object = self
+            .objects
+            .get(&handle)
+            .ok_or(PTP_RC_INVALID_OBJECT_HANDLE)?;
+        let start = (offset as usize).min(object.data.len());
+        let end = (start + max_bytes as usize).min(object.data.len());
+        Ok(object.data[start..end].to_vec())
+    }
+
+    fn queue_data(&mut self, code: u16, transaction_id: u32, payload: &[u8]) {
+        let mut buf =
+            container_header(12 + payload.len(), PTP_CONTAINER_DATA, code, transaction_id);
+        buf.extend_from_slice(payload);
+        self.outgoing.push_back(buf);
+    }
+
+    fn queue_response(&mut self, code: u16, transaction_id: u32) {
+        let buf = container_header(12, PTP_CONTAINER_RESPONSE, code, transaction_id);
+        self.outgoing.push_back(buf);
+    }
+}
+
+impl UsbTransport for FakeKindle {
+    fn write_bulk(&mut self, data: &[u8], _timeout: Duration) -> Result<usize, KindleError> {
+        if data.len() < 12 {
+            return Err(KindleError::Usb(format!(
+                "short container ({} bytes)",
+                data.len()
+            )));
+        }
+        let mut cursor = Cursor::new(data);
+        let _ = cursor.read_u32::<LittleEndian>().unwrap();
+        let container_type = cursor.read_u16::<LittleEndian>().unwrap();
+        let code = cursor.read_u16::<LittleEndian>().unwrap();
+        let transaction_id = cursor.read_u32::<LittleEndian>().unwrap();
+
+        if container_type != PTP_CONTAINER_COMMAND {
+            self.queue_response(PTP_RC_GENERAL_ERROR, transaction_id);
+            return Ok(data.len());
+        }
+
+        let mut params = Vec::new();
+        while cursor.position() + 4 <= data.len() as u64 {
+            params.push(cursor.read_u32::<LittleEndian>().unwrap());
+        }
+        self.handle_command(code, transaction_id, &params);
+        Ok(data.len())
+    }
+
+    fn read_bulk(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize, KindleError> {
+        let Some(transfer) = self.outgoing.front_mut() else {
+            return Err(KindleError::Timeout);
+        };
+        let n = transfer.len().min(buf.len());
+        buf[..n].copy_from_slice(&transfer[..n]);
+        transfer.drain(..n);
+        if transfer.is_empty() {
+            self.outgoing.pop_front();
+        }
+        Ok(n)
+    }
+
+    fn clear_halt(&mut self) -> Result<(), KindleError> {
+        self.outgoing.clear();
+        Ok(())
+    }
+
+    fn class_request_out(
+        &mut self,
+        request: u8,
+        _data: &[u8],
+        _timeout: Duration,
+    ) -> Result<(), KindleError> {
+        self.class_requests.push(request);
+        match request {
+            SIC_REQUEST_CANCEL => {
+                self.outgoing.clear();
+                self.busy = self.ignores_cancel;
+            }
+            SIC_REQUEST_DEVICE_RESET => {
+                self.outgoing.clear();
+                self.session_open = false;
+                self.busy = false;
+                self.ignores_cancel = false;
+            }
+            _ => return Err(KindleError::Usb("Pipe error".to_string())),
+        }
+        Ok(())
+    }
+
+    fn class_request_in(
+        &mut self,
+        request: u8,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<usize, KindleError> {
+        self.class_requests.push(request);
+        if request != SIC_REQUEST_GET_DEVICE_STATUS {
+            return Err(KindleError::Usb("Pipe error".to_string()));
+        }
+        let code = if self.busy {
+            PTP_RC_DEVICE_BUSY
+        } else {
+            PTP_RC_OK
+        };
+        buf[..2].copy_from_slice(&4u16.to_le_bytes());
+        buf[2..4].copy_from_slice(&code.to_le_bytes());
+        Ok(4)
+    }
+}
+
+fn container_header(length: usize, container_type: u16, code: u16, transaction_id: u32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(length);
+    buf.write_u32::<LittleEndian>(length as u32).unwrap();
+    buf.write_u16::<LittleEndian>(container_type).unwrap();
+    buf.write_u16::<LittleEndian>(code).unwrap();
+    buf.write_u32::<LittleEndian>(transaction_id).unwrap();
+    buf
+}
+
+fn u32_array(values: &[u32]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(4 + values.len() * 4);
+    buf.write_u32::<LittleEndian>(values.len() as u32).unwrap();
+    for value in values {
+        buf.write_u32::<LittleEndian>(*value).unwrap();
+    }
+    buf
+}
+
+fn u16_array(values: &[u16]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(4 + values.len() * 2);
+    buf.write_u32::<LittleEndian>(values.len() as u32).unwrap();
+    for value in values {
+        buf.write_u16::<LittleEndian>(*value).unwrap();
+    }
+    buf
+}
+
+fn write_ptp_string(buf: &mut Vec<u8>, value: &str) {
+    if value.is_empty() {
+        buf.push(0);
+        return;
+    }
+    let chars: Vec<u16> = value.encode_utf16().chain(std::iter::once(0)).collect();
+    buf.push(chars.len() as u8);
+    for c in chars {
+        buf.write_u16::<LittleEndian>(c).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+//! DeviceInfo and StorageInfo datasets (PTP 1.1, sections 5.5.1 and 5.5.3)
+
+use crate::kindle::KindleError;
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::Cursor;
+
+/// Who the connected device says it is
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceInfo {
+    pub manufacturer: String,
+    pub model: String,
+    pub serial_number: String,
+    pub firmware_version: String,
+    #[serde(skip)]
+    pub operations_supported: Vec<u16>,
+}
+
+/// Capacity and labels of one storage on the device
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageInfo {
+    pub storage_id: u32,
+    pub description: String,
+    pub volume_label: String,
+    pub max_capacity: u64,
+    pub free_space: u64,
+}
+
+/// Identity of a connected Kindle together with its storages
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KindleIdentity {
+    #[serde(flatten)]
+    pub device: DeviceInfo,
+    pub storages: Vec<StorageInfo>,
+}
+
+impl DeviceInfo {
+    pub fn parse(data: &[u8]) -> Result<Self, KindleError> {
+        let mut reader = DatasetReader::new(data, "DeviceInfo");
+        let _standard_version = reader.u16()?;
+        let _vendor_extension_id = reader.u32()?;
+        let _vendor_extension_version = reader.u16()?;
+        let _vendor_extension_desc = reader.string()?;
+        let _functional_mode = reader.u16()?;
+        let operations_supported = reader.u16_array()?;
+        let _events_supported = reader.u16_array()?;
+        let _device_properties_supported = reader.u16_array()?;
+        let _capture_formats = reader.u16_array()?;
+        let _playback_formats = reader.u16_array()?;
+        let manufacturer = reader.string()?;
+        let model = reader.string()?;
+        let firmware_version = reader.string()?;
+        let serial_number = reader.string()?;
+
+        Ok(Self {
+            manufacturer,
+            model,
+            serial_number,
+            firmware_version,
+            operations_supported,
+        })
+    }
+
+    pub fn supports(&self, operation: u16) -> bool {
+        self.operations_supported.contains(&operation)
+    }
+}
+
+impl StorageInfo {
+    pub fn parse(storage_id: u32, data: &[u8]) -> Result<Self, KindleError> {
+        let mut reader = DatasetReader::new(data, "StorageInfo");
+        let _storage_type = reader.u16()?;
+        let _filesystem_type = reader.u16()?;
+        let _access_capability = reader.u16()?;
+        let max_capacity = reader.u64()?;
+        let free_space = reader.u64()?;
+        let _free_space_in_objects = reader.u32()?;
+        let description = reader.string()?;
+        let volume_label = reader.string()?;
+
+        Ok(Self {
+            storage_id,
+            description,
+            volume_label,
+            max_capacity,
+            free_space,
+        })
+    }
+}
+
+struct DatasetReader<'a> {
+    cursor: Cursor<&'a [u8]>,
+    dataset: &'static str,
+}
+
+impl<'a> DatasetReader<'a> {
+    fn new(data: &'a [u8], dataset: &'static str) -> Self {
+        Self {
+            cursor: Cursor::new(data),
+            dataset,
+        }
+    }
+
+    fn truncated(&self) -> KindleError {
+        KindleError::InvalidResponse(format!("{} dataset truncated", self.dataset))
+    }
+
+    fn u16(&mut self) -> Result<u16, KindleError> {
+        self.cursor
+            .read_u16::<LittleEndian>()
+            .map_err(|_| self.truncated())
+    }
+
+    fn u32(&mut self) -> Result<u32, KindleError> {
+        self.cursor
+            .read_u32::<LittleEndian>()
+            .map_err(|_| self.truncated())
+    }
+
+    fn u64(&mut self) -> Result<u64, KindleError> {
+        self.cursor
+            .read_u64::<LittleEndian>()
+            .map_err(|_| self.truncated())
+    }
+
+    fn u16_array(&mut self) -> Result<Vec<u16>, KindleError> {
+        let count = self.u32()?;
+        (0..count).map(|_| self.u16()).collect()
+    }
+
+    /// PTP string: u8 character count (including the terminator) followed by UTF-16LE
+    fn string(&mut self) -> Result<String, KindleError> {
+        let len = self.cursor.read_u8().map_err(|_| self.truncated())?;
+        let mut chars = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            chars.push(self.u16()?);
+        }
+        while chars.last() == Some(&0) {
+            chars.pop();
+        }
+        String::from_utf16(&chars).map_err(|e| KindleError::InvalidResponse(e.to_string()))
+    }
+}
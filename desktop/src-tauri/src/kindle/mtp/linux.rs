@@ -0,0 +1,81 @@
+//! Linux specifics of opening the Kindle's MTP interface without root
+//!
+//! Access to `/dev/bus/usb` comes from the udev rule shipped with the package
+//! (`linux/70-mastery-kindle.rules`). The interface itself may be held by a kernel
+//! driver, by `mtp-probe` for a moment after plug-in, or by `gvfsd-mtp` when the
+//! desktop has mounted the Kindle.
+
+use crate::kindle::KindleError;
+use rusb::{Device, DeviceHandle, GlobalContext};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Claim attempts while udev's `mtp-probe` is still inspecting the device
+const CLAIM_ATTEMPTS: u32 = 5;
+const CLAIM_RETRY_DELAY: Duration = Duration::from_millis(400);
+
+/// Opens the device, telling a missing udev rule apart from other failures
+pub fn open(device: &Device<GlobalContext>) -> Result<DeviceHandle<GlobalContext>, KindleError> {
+    device.open().map_err(|e| match e {
+        rusb::Error::Access => KindleError::UdevRuleMissing,
+        e => e.into(),
+    })
+}
+
+/// Claims `interface`, letting libusb detach (and later reattach) any kernel driver
+pub fn claim_interface(
+    device: &Device<GlobalContext>,
+    handle: &mut DeviceHandle<GlobalContext>,
+    interface: u8,
+) -> Result<(), KindleError> {
+    let _ = handle.set_auto_detach_kernel_driver(true);
+
+    let mut attempts = 0;
+    loop {
+        match handle.claim_interface(interface) {
+            Ok(()) => return Ok(()),
+            Err(rusb::Error::Busy) if attempts + 1 < CLAIM_ATTEMPTS => {
+                attempts += 1;
+                std::thread::sleep(CLAIM_RETRY_DELAY);
+            }
+            Err(rusb::Error::Busy) => {
+                return Err(KindleError::InterfaceBusy(interface_holder(device)))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Name of another process with the device node open, e.g. `gvfsd-mtp`
+///
+/// Only processes of the current user are visible, which covers gvfs; root-owned
+/// holders such as `mtp-probe` are transient and handled by retrying the claim.
+fn interface_holder(device: &Device<GlobalContext>) -> Option<String> {
+    let node = format!(
+        "/dev/bus/usb/{:03}/{:03}",
+        device.bus_number(),
+        device.address()
+    );
+    let own_pid = std::process::id().to_string();
+
+    for entry in fs::read_dir("/proc").ok()?.flatten() {
+        let pid = entry.file_name();
+        let pid = pid.to_string_lossy();
+        if !pid.bytes().all(|b| b.is_ascii_digit()) || pid == own_pid {
+            continue;
+        }
+        let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        let holds_device = fds
+            .flatten()
+            .any(|fd| fs::read_link(fd.path()).is_ok_and(|target| target == Path::new(&node)));
+        if holds_device {
+            return fs::read_to_string(entry.path().join("comm"))
+                .ok()
+                .map(|comm| comm.trim().to_string());
+        }
+    }
+    None
+}
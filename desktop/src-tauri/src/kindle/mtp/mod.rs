@@ -0,0 +1,1254 @@
+//! Pure Rust MTP implementation for Kindle vocab.db sync
+//! No external dependencies beyond rusb
+
+#[cfg(test)]
+pub(crate) mod fake;
+mod info;
+#[cfg(target_os = "linux")]
+mod linux;
+mod transport;
+
+pub use info::{DeviceInfo, KindleIdentity, StorageInfo};
+pub use transport::{is_mtp_class, RusbTransport, UsbTransport};
+
+use crate::kindle::{CancelToken, DownloadProgress, KindleError};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use rusb::{Device, GlobalContext, Hotplug, HotplugBuilder, UsbContext};
+use std::io::{Cursor, Write};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const KINDLE_VID: u16 = 0x1949;
+const TIMEOUT_SHORT: Duration = Duration::from_secs(5);
+const TIMEOUT: Duration = Duration::from_secs(20);
+const TIMEOUT_TRANSFER: Duration = Duration::from_secs(30);
+/// A `CHUNK_SIZE` read takes well under a second over USB 2, so a stalled
+/// Kindle is noticed (and cancellable) long before `TIMEOUT_TRANSFER`
+const TIMEOUT_CHUNK: Duration = Duration::from_secs(5);
+
+const PTP_OC_GET_DEVICE_INFO: u16 = 0x1001;
+const PTP_OC_OPEN_SESSION: u16 = 0x1002;
+const PTP_OC_CLOSE_SESSION: u16 = 0x1003;
+const PTP_OC_GET_STORAGE_IDS: u16 = 0x1004;
+const PTP_OC_GET_STORAGE_INFO: u16 = 0x1005;
+const PTP_OC_GET_OBJECT_HANDLES: u16 = 0x1007;
+const PTP_OC_GET_OBJECT_INFO: u16 = 0x1008;
+const PTP_OC_GET_OBJECT: u16 = 0x1009;
+const PTP_OC_GET_PARTIAL_OBJECT: u16 = 0x101B;
+const MTP_OC_GET_PARTIAL_OBJECT_64: u16 = 0x95C1;
+
+const PTP_RC_OK: u16 = 0x2001;
+const PTP_RC_SESSION_NOT_OPEN: u16 = 0x2003;
+const PTP_RC_ACCESS_DENIED: u16 = 0x200F;
+const PTP_RC_DEVICE_BUSY: u16 = 0x2019;
+const PTP_RC_SESSION_ALREADY_OPEN: u16 = 0x201E;
+const PTP_RC_TRANSACTION_CANCELLED: u16 = 0x201F;
+
+const PTP_CONTAINER_COMMAND: u16 = 1;
+const PTP_CONTAINER_DATA: u16 = 2;
+const PTP_CONTAINER_RESPONSE: u16 = 3;
+
+const SESSION_ID: u32 = 1;
+
+// USB Still Image class requests (PIMA 15740 USB binding, section 5.2)
+const SIC_REQUEST_CANCEL: u8 = 0x64;
+const SIC_REQUEST_DEVICE_RESET: u8 = 0x66;
+const SIC_REQUEST_GET_DEVICE_STATUS: u8 = 0x67;
+const PTP_EC_CANCEL_TRANSACTION: u16 = 0x4001;
+
+/// Get Device Status polls before a cancelled device is reset instead
+const STATUS_POLLS: u32 = 10;
+
+/// Bytes requested per partial-object read
+const CHUNK_SIZE: u32 = 1024 * 1024;
+
+/// Times a failed partial read is re-sent from the same offset before giving up
+const RESUME_ATTEMPTS: u32 = 3;
+/// How long to wait for leftover bytes when draining the pipe after a failed transfer
+const TIMEOUT_DRAIN: Duration = Duration::from_millis(200);
+
+const VOCAB_DB_PATH: &[&str] = &["system", "vocabulary", "vocab.db"];
+
+/// How often an operation is re-sent while the device answers DeviceBusy
+#[derive(Debug, Clone, Copy)]
+struct RetryPolicy {
+    attempts: u32,
+    delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            delay: Duration::from_millis(500),
+        }
+    }
+}
+
+pub struct MtpDevice<T: UsbTransport = RusbTransport> {
+    transport: T,
+    transaction_id: u32,
+    session_open: bool,
+    busy_retry: RetryPolicy,
+    device_info: Option<DeviceInfo>,
+}
+
+struct ObjectInfo {
+    #[allow(dead_code)]
+    format: u16,
+    filename: String,
+    size: u32,
+}
+
+impl MtpDevice {
+    pub fn find_kindle() -> Result<Self, KindleError> {
+        Ok(Self::new(RusbTransport::open(find_kindle_usb_device()?)?))
+    }
+
+    /// Opens the Kindle at a USB bus number and address
+    pub fn find_kindle_at(location: (u8, u8)) -> Result<Self, KindleError> {
+        Ok(Self::new(RusbTransport::open(kindle_usb_device_at(
+            location,
+        )?)?))
+    }
+}
+
+impl<T: UsbTransport> MtpDevice<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            transaction_id: 0,
+            session_open: false,
+            busy_retry: RetryPolicy::default(),
+            device_info: None,
+        }
+    }
+
+    fn build_command(&mut self, code: u16, params: &[u32]) -> Vec<u8> {
+        self.transaction_id += 1;
+        let length = 12 + (params.len() * 4) as u32;
+
+        let mut buf = Vec::with_capacity(length as usize);
+        buf.write_u32::<LittleEndian>(length).unwrap();
+        buf.write_u16::<LittleEndian>(PTP_CONTAINER_COMMAND)
+            .unwrap();
+        buf.write_u16::<LittleEndian>(code).unwrap();
+        buf.write_u32::<LittleEndian>(self.transaction_id).unwrap();
+        for param in params {
+            buf.write_u32::<LittleEndian>(*param).unwrap();
+        }
+        buf
+    }
+
+    fn send_command(
+        &mut self,
+        code: u16,
+        params: &[u32],
+        timeout: Duration,
+    ) -> Result<(), KindleError> {
+        let cmd = self.build_command(code, params);
+        self.transport.write_bulk(&cmd, timeout)?;
+        Ok(())
+    }
+
+    fn read_response(&mut self, timeout: Duration) -> Result<(u16, Vec<u32>), KindleError> {
+        let mut buf = vec![0u8; 512];
+        let mut n = self.transport.read_bulk(&mut buf, timeout)?;
+        if n == 0 {
+            // Zero-length packet terminating a data phase that filled the last packet
+            n = self.transport.read_bulk(&mut buf, timeout)?;
+        }
+
+        if n < 12 {
+            return Err(KindleError::InvalidResponse(format!(
+                "Response too short: {} bytes",
+                n
+            )));
+        }
+
+        let mut cursor = Cursor::new(&buf[..n]);
+        let _ = cursor.read_u32::<LittleEndian>().unwrap();
+        let _ = cursor.read_u16::<LittleEndian>().unwrap();
+        let code = cursor.read_u16::<LittleEndian>().unwrap();
+        let _ = cursor.read_u32::<LittleEndian>().unwrap();
+
+        let mut params = Vec::new();
+        while cursor.position() + 4 <= n as u64 {
+            params.push(cursor.read_u32::<LittleEndian>().unwrap());
+        }
+        Ok((code, params))
+    }
+
+    /// Reads the optional data phase followed by the response phase of a transaction
+    fn read_data_and_response(&mut self, timeout: Duration) -> Result<(Vec<u8>, u16), KindleError> {
+        let mut buf = vec![0u8; 64 * 1024];
+        let n = self.transport.read_bulk(&mut buf, timeout)?;
+        if n < 12 {
+            return Err(KindleError::InvalidResponse(format!(
+                "Container too short: {} bytes",
+                n
+            )));
+        }
+
+        let mut cursor = Cursor::new(&buf[..12]);
+        let expected_len = cursor.read_u32::<LittleEndian>().unwrap() as usize;
+        let container_type = cursor.read_u16::<LittleEndian>().unwrap();
+        let code = cursor.read_u16::<LittleEndian>().unwrap();
+
+        match container_type {
+            // No data phase: the device answered straight away
+            PTP_CONTAINER_RESPONSE => return Ok((Vec::new(), code)),
+            PTP_CONTAINER_DATA => {}
+            other => {
+                return Err(KindleError::InvalidResponse(format!(
+                    "Unexpected container type {}",
+                    other
+                )))
+            }
+        }
+
+        let mut all_data = Vec::with_capacity(expected_len.saturating_sub(12));
+        all_data.extend_from_slice(&buf[12..n]);
+        while all_data.len() + 12 < expected_len {
+            let n = self.transport.read_bulk(&mut buf, timeout)?;
+            if n == 0 {
+                break;
+            }
+            all_data.extend_from_slice(&buf[..n]);
+        }
+
+        let (code, _) = self.read_response(timeout)?;
+        Ok((all_data, code))
+    }
+
+    /// Runs one operation, retrying while the device is busy and re-opening a lost session
+    fn transaction(
+        &mut self,
+        operation: &'static str,
+        code: u16,
+        params: &[u32],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, KindleError> {
+        let mut busy_attempts = 0;
+        let mut reopened = false;
+
+        loop {
+            self.send_command(code, params, timeout)?;
+            let (data, response_code) = self.read_data_and_response(timeout)?;
+
+            match response_code {
+                PTP_RC_OK => return Ok(data),
+                PTP_RC_DEVICE_BUSY if busy_attempts < self.busy_retry.attempts => {
+                    busy_attempts += 1;
+                    std::thread::sleep(self.busy_retry.delay);
+                }
+                PTP_RC_SESSION_NOT_OPEN if !reopened => {
+                    reopened = true;
+                    self.session_open = false;
+                    self.open_session()?;
+                }
+                _ => return Err(response_error(operation, response_code)),
+            }
+        }
+    }
+
+    fn open_session(&mut self) -> Result<(), KindleError> {
+        if self.session_open {
+            return Ok(());
+        }
+
+        let mut busy_attempts = 0;
+        loop {
+            self.send_command(PTP_OC_OPEN_SESSION, &[SESSION_ID], TIMEOUT_SHORT)?;
+            let (code, _) = self.read_response(TIMEOUT_SHORT)?;
+
+            match code {
+                PTP_RC_OK | PTP_RC_SESSION_ALREADY_OPEN => {
+                    self.session_open = true;
+                    return Ok(());
+                }
+                PTP_RC_DEVICE_BUSY if busy_attempts < self.busy_retry.attempts => {
+                    busy_attempts += 1;
+                    std::thread::sleep(self.busy_retry.delay);
+                }
+                _ => return Err(response_error("OpenSession", code)),
+            }
+        }
+    }
+
+    /// Closes the session so the Kindle leaves its locked MTP state
+    pub fn close_session(&mut self) -> Result<(), KindleError> {
+        if !self.session_open {
+            return Ok(());
+        }
+        self.session_open = false;
+
+        self.send_command(PTP_OC_CLOSE_SESSION, &[], TIMEOUT_SHORT)?;
+        let (code, _) = self.read_response(TIMEOUT_SHORT)?;
+        match code {
+            PTP_RC_OK | PTP_RC_SESSION_NOT_OPEN => Ok(()),
+            _ => Err(response_error("CloseSession", code)),
+        }
+    }
+
+    /// Asks the device who it is; the answer is cached for the lifetime of the connection
+    pub fn device_info(&mut self) -> Result<&DeviceInfo, KindleError> {
+        if self.device_info.is_none() {
+            let data =
+                self.transaction("GetDeviceInfo", PTP_OC_GET_DEVICE_INFO, &[], TIMEOUT_SHORT)?;
+            self.device_info = Some(DeviceInfo::parse(&data)?);
+        }
+        Ok(self.device_info.as_ref().unwrap())
+    }
+
+    fn device_supports(&mut self, operation: u16) -> bool {
+        self.device_info()
+            .map(|info| info.supports(operation))
+            .unwrap_or(false)
+    }
+
+    pub fn get_storage_info(&mut self, storage_id: u32) -> Result<StorageInfo, KindleError> {
+        self.open_session()?;
+        let data = self.transaction(
+            "GetStorageInfo",
+            PTP_OC_GET_STORAGE_INFO,
+            &[storage_id],
+            TIMEOUT,
+        )?;
+        StorageInfo::parse(storage_id, &data)
+    }
+
+    /// Device identity plus capacity and free space of every storage
+    pub fn identify(&mut self) -> Result<KindleIdentity, KindleError> {
+        let device = self.device_info()?.clone();
+        self.open_session()?;
+
+        let mut storages = Vec::new();
+        for storage_id in self.get_storage_ids()? {
+            storages.push(self.get_storage_info(storage_id)?);
+        }
+        Ok(KindleIdentity { device, storages })
+    }
+
+    fn get_storage_ids(&mut self) -> Result<Vec<u32>, KindleError> {
+        let data = self.transaction("GetStorageIDs", PTP_OC_GET_STORAGE_IDS, &[], TIMEOUT)?;
+        if data.len() < 4 {
+            return Err(KindleError::InvalidResponse("No storage data".to_string()));
+        }
+        Ok(read_u32_array(&data))
+    }
+
+    fn get_object_handles(
+        &mut self,
+        storage_id: u32,
+        parent: u32,
+    ) -> Result<Vec<u32>, KindleError> {
+        let data = self.transaction(
+            "GetObjectHandles",
+            PTP_OC_GET_OBJECT_HANDLES,
+            &[storage_id, 0, parent],
+            TIMEOUT,
+        )?;
+        if data.len() < 4 {
+            return Ok(Vec::new());
+        }
+        Ok(read_u32_array(&data))
+    }
+
+    fn get_object_info(&mut self, handle: u32) -> Result<ObjectInfo, KindleError> {
+        let data = self.transaction("GetObjectInfo", PTP_OC_GET_OBJECT_INFO, &[handle], TIMEOUT)?;
+
+        let mut cursor = Cursor::new(&data);
+        let _ = cursor.read_u32::<LittleEndian>().unwrap_or(0); // storage_id
+        let format = cursor.read_u16::<LittleEndian>().unwrap_or(0);
+        let _ = cursor.read_u16::<LittleEndian>().unwrap_or(0); // protection
+        let size = cursor.read_u32::<LittleEndian>().unwrap_or(0);
+
+        let filename = self
+            .read_ptp_string(&data, 52)
+            .unwrap_or_else(|_| "?".to_string());
+
+        Ok(ObjectInfo {
+            format,
+            filename,
+            size,
+        })
+    }
+
+    fn read_ptp_string(&self, data: &[u8], offset: usize) -> Result<String, KindleError> {
+        if offset >= data.len() {
+            return Ok(String::new());
+        }
+        let len = data[offset] as usize;
+        if len == 0 {
+            return Ok(String::new());
+        }
+
+        let mut chars = Vec::with_capacity(len);
+        let start = offset + 1;
+        for i in 0..len {
+            let pos = start + i * 2;
+            if pos + 1 >= data.len() {
+                break;
+            }
+            let c = u16::from_le_bytes([data[pos], data[pos + 1]]);
+            if c == 0 {
+                break;
+            }
+            chars.push(c);
+        }
+        String::from_utf16(&chars).map_err(|e| KindleError::InvalidResponse(e.to_string()))
+    }
+
+    /// Streams an object into `out`, in `CHUNK_SIZE` partial reads when the device supports them
+    fn read_object_to(
+        &mut self,
+        handle: u32,
+        size: u64,
+        out: &mut dyn Write,
+        cancel: &CancelToken,
+        on_progress: &mut dyn FnMut(DownloadProgress),
+    ) -> Result<u64, KindleError> {
+        if self.device_supports(MTP_OC_GET_PARTIAL_OBJECT_64) {
+            self.read_object_chunked(handle, size, true, out, cancel, on_progress)
+        } else if self.device_supports(PTP_OC_GET_PARTIAL_OBJECT) && size <= u32::MAX as u64 {
+            self.read_object_chunked(handle, size, false, out, cancel, on_progress)
+        } else {
+            cancel.check()?;
+            let data = self
+                .transaction("GetObject", PTP_OC_GET_OBJECT, &[handle], TIMEOUT_TRANSFER)
+                .inspect_err(|e| {
+                    if e.is_transient() {
+                        self.abort_transfer();
+                    }
+                })?;
+            out.write_all(&data)?;
+            let total = data.len() as u64;
+            on_progress(DownloadProgress {
+                bytes_done: total,
+                total,
+            });
+            Ok(total)
+        }
+    }
+
+    fn read_object_chunked(
+        &mut self,
+        handle: u32,
+        size: u64,
+        wide_offsets: bool,
+        out: &mut dyn Write,
+        cancel: &CancelToken,
+        on_progress: &mut dyn FnMut(DownloadProgress),
+    ) -> Result<u64, KindleError> {
+        let mut offset = 0u64;
+        on_progress(DownloadProgress {
+            bytes_done: 0,
+            total: size,
+        });
+
+        let mut failures = 0;
+        while offset < size {
+            cancel.check()?;
+            let want = (size - offset).min(CHUNK_SIZE as u64) as u32;
+            let chunk = match self.read_partial(handle, offset, want, wide_offsets) {
+                Ok(chunk) => chunk,
+                Err(_) if cancel.is_cancelled() => {
+                    self.abort_transfer();
+                    return Err(KindleError::Cancelled);
+                }
+                Err(e) if e.is_transient() && failures < RESUME_ATTEMPTS => {
+                    failures += 1;
+                    self.abort_transfer();
+                    continue;
+                }
+                Err(e) => {
+                    if e.is_transient() {
+                        self.abort_transfer();
+                    }
+                    return Err(e);
+                }
+            };
+
+            if chunk.is_empty() {
+                return Err(KindleError::IncompleteTransfer {
+                    expected: size,
+                    received: offset,
+                });
+            }
+            out.write_all(&chunk)?;
+            offset += chunk.len() as u64;
+            failures = 0;
+            on_progress(DownloadProgress {
+                bytes_done: offset,
+                total: size,
+            });
+        }
+
+        Ok(offset)
+    }
+
+    fn read_partial(
+        &mut self,
+        handle: u32,
+        offset: u64,
+        max_bytes: u32,
+        wide_offsets: bool,
+    ) -> Result<Vec<u8>, KindleError> {
+        if wide_offsets {
+            self.transaction(
+                "GetPartialObject64",
+                MTP_OC_GET_PARTIAL_OBJECT_64,
+                &[handle, offset as u32, (offset >> 32) as u32, max_bytes],
+                TIMEOUT_CHUNK,
+            )
+        } else {
+            self.transaction(
+                "GetPartialObject",
+                PTP_OC_GET_PARTIAL_OBJECT,
+                &[handle, offset as u32, max_bytes],
+                TIMEOUT_CHUNK,
+            )
+        }
+    }
+
+    /// Asks the device to abandon the current transaction (Cancel Request)
+    pub fn cancel_transaction(&mut self) -> Result<(), KindleError> {
+        let mut data = Vec::with_capacity(6);
+        data.write_u16::<LittleEndian>(PTP_EC_CANCEL_TRANSACTION)?;
+        data.write_u32::<LittleEndian>(self.transaction_id)?;
+        self.transport
+            .class_request_out(SIC_REQUEST_CANCEL, &data, TIMEOUT_SHORT)
+    }
+
+    /// Returns the PTP response code the device reports for its current state (Get Device Status)
+    pub fn device_status(&mut self) -> Result<u16, KindleError> {
+        let mut buf = [0u8; 64];
+        let n = self.transport.class_request_in(
+            SIC_REQUEST_GET_DEVICE_STATUS,
+            &mut buf,
+            TIMEOUT_SHORT,
+        )?;
+        if n < 4 {
+            return Err(KindleError::InvalidResponse(
+                "Device status too short".to_string(),
+            ));
+        }
+        Ok(u16::from_le_bytes([buf[2], buf[3]]))
+    }
+
+    /// Returns the device to its idle state, which also ends the session (Device Reset)
+    pub fn reset_device(&mut self) -> Result<(), KindleError> {
+        self.session_open = false;
+        self.transport
+            .class_request_out(SIC_REQUEST_DEVICE_RESET, &[], TIMEOUT_SHORT)
+    }
+
+    /// Stops a transfer that failed or was cancelled midway and leaves the device ready
+    /// for the next transaction, resetting it if it will not acknowledge the cancel
+    fn abort_transfer(&mut self) {
+        let cancelled = self.cancel_transaction().is_ok() && self.wait_until_idle();
+        if !cancelled {
+            let _ = self.reset_device();
+        }
+        self.recover_pipe();
+    }
+
+    fn wait_until_idle(&mut self) -> bool {
+        for _ in 0..STATUS_POLLS {
+            match self.device_status() {
+                Ok(PTP_RC_OK) => return true,
+                Ok(_) => std::thread::sleep(self.busy_retry.delay),
+                Err(_) => return false,
+            }
+        }
+        false
+    }
+
+    /// Gets the pipes back to a clean state after a transfer failed midway
+    fn recover_pipe(&mut self) {
+        let _ = self.transport.clear_halt();
+        let mut buf = vec![0u8; 64 * 1024];
+        while let Ok(n) = self.transport.read_bulk(&mut buf, TIMEOUT_DRAIN) {
+            if n == 0 {
+                break;
+            }
+        }
+    }
+
+    fn find_folder(
+        &mut self,
+        storage_id: u32,
+        parent: u32,
+        name: &str,
+    ) -> Result<Option<u32>, KindleError> {
+        let handles = self.get_object_handles(storage_id, parent)?;
+        for handle in handles {
+            let info = self.get_object_info(handle)?;
+            if info.filename.to_lowercase() == name.to_lowercase() {
+                return Ok(Some(handle));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads vocab.db content from Kindle as bytes, stopping early once `cancel` fires
+    pub fn read_vocab_db_bytes(&mut self, cancel: &CancelToken) -> Result<Vec<u8>, KindleError> {
+        let mut data = Vec::new();
+        self.download_vocab_db_to(&mut data, cancel, &mut |_| {})?;
+        Ok(data)
+    }
+
+    /// Streams vocab.db from Kindle into `out`, reporting progress after each chunk
+    pub fn download_vocab_db_to(
+        &mut self,
+        out: &mut dyn Write,
+        cancel: &CancelToken,
+        on_progress: &mut dyn FnMut(DownloadProgress),
+    ) -> Result<u64, KindleError> {
+        let (handle, info) = self
+            .find_object(VOCAB_DB_PATH)?
+            .ok_or(KindleError::VocabNotFound)?;
+        self.download_object_to(handle, &info, out, cancel, on_progress)
+    }
+
+    /// Streams the file at `path` from Kindle into `out`, or fails with `not_found`
+    pub fn download_file_to(
+        &mut self,
+        path: &[&str],
+        not_found: KindleError,
+        out: &mut dyn Write,
+        cancel: &CancelToken,
+        on_progress: &mut dyn FnMut(DownloadProgress),
+    ) -> Result<u64, KindleError> {
+        let (handle, info) = self.find_object(path)?.ok_or(not_found)?;
+        self.download_object_to(handle, &info, out, cancel, on_progress)
+    }
+
+    fn download_object_to(
+        &mut self,
+        handle: u32,
+        info: &ObjectInfo,
+        out: &mut dyn Write,
+        cancel: &CancelToken,
+        on_progress: &mut dyn FnMut(DownloadProgress),
+    ) -> Result<u64, KindleError> {
+        let expected = info.size as u64;
+        let received = self.read_object_to(handle, expected, out, cancel, on_progress)?;
+        if received != expected {
+            return Err(KindleError::IncompleteTransfer { expected, received });
+        }
+        Ok(received)
+    }
+
+    /// Finds the object at `path` (matched case-insensitively) on any storage
+    fn find_object(&mut self, path: &[&str]) -> Result<Option<(u32, ObjectInfo)>, KindleError> {
+        self.open_session()?;
+
+        let Some((file_name, folders)) = path.split_last() else {
+            return Ok(None);
+        };
+
+        'storages: for storage_id in self.get_storage_ids()? {
+            let mut parent = 0xFFFFFFFF;
+            for folder in folders {
+                match self.find_folder(storage_id, parent, folder)? {
+                    Some(handle) => parent = handle,
+                    None => continue 'storages,
+                }
+            }
+
+            for handle in self.get_object_handles(storage_id, parent)? {
+                let info = self.get_object_info(handle)?;
+                if info.filename.to_lowercase() == file_name.to_lowercase() {
+                    return Ok(Some((handle, info)));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl<T: UsbTransport> Drop for MtpDevice<T> {
+    fn drop(&mut self) {
+        let _ = self.close_session();
+    }
+}
+
+/// Maps a non-OK PTP response code to the error it stands for
+fn response_error(operation: &'static str, code: u16) -> KindleError {
+    match code {
+        PTP_RC_DEVICE_BUSY => KindleError::InterfaceBusy(None),
+        PTP_RC_ACCESS_DENIED => {
+            KindleError::PermissionDenied(format!("{} was refused by the Kindle", operation))
+        }
+        PTP_RC_TRANSACTION_CANCELLED => KindleError::Cancelled,
+        _ => KindleError::ProtocolError { operation, code },
+    }
+}
+
+/// Parses a PTP array of u32 (count followed by elements)
+fn read_u32_array(data: &[u8]) -> Vec<u32> {
+    let mut cursor = Cursor::new(data);
+    let count = cursor.read_u32::<LittleEndian>().unwrap_or(0);
+    let mut values = Vec::new();
+    for _ in 0..count {
+        if cursor.position() + 4 <= data.len() as u64 {
+            values.push(cursor.read_u32::<LittleEndian>().unwrap());
+        }
+    }
+    values
+}
+
+/// Whether libusb can start at all; the global context panics where it cannot,
+/// e.g. in a container without `/dev/bus/usb`
+fn usb_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| rusb::Context::new().is_ok())
+}
+
+fn kindle_usb_devices() -> Result<Vec<Device<GlobalContext>>, KindleError> {
+    if !usb_available() {
+        return Err(KindleError::NoDevice);
+    }
+    let mut found = Vec::new();
+    for device in rusb::devices()?.iter() {
+        let desc = device.device_descriptor()?;
+        if desc.vendor_id() == KINDLE_VID {
+            found.push(device);
+        }
+    }
+    Ok(found)
+}
+
+fn find_kindle_usb_device() -> Result<Device<GlobalContext>, KindleError> {
+    kindle_usb_devices()?
+        .into_iter()
+        .next()
+        .ok_or(KindleError::NoDevice)
+}
+
+fn kindle_usb_device_at(location: (u8, u8)) -> Result<Device<GlobalContext>, KindleError> {
+    kindle_usb_devices()?
+        .into_iter()
+        .find(|device| (device.bus_number(), device.address()) == location)
+        .ok_or(KindleError::NoDevice)
+}
+
+/// Bus number and address of the attached Kindle, without opening it
+pub fn kindle_usb_location() -> Option<(u8, u8)> {
+    find_kindle_usb_device()
+        .ok()
+        .map(|device| (device.bus_number(), device.address()))
+}
+
+/// A Kindle on the bus, seen from its descriptors only
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsbKindle {
+    pub location: (u8, u8),
+    /// False for older Kindles, which only offer USB mass storage
+    pub mtp: bool,
+}
+
+/// Every Kindle attached over USB, without opening any of them
+pub fn usb_kindles() -> Vec<UsbKindle> {
+    kindle_usb_devices()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|device| UsbKindle {
+            location: (device.bus_number(), device.address()),
+            mtp: has_mtp_interface(&device),
+        })
+        .collect()
+}
+
+struct HotplugCallback<F>(F);
+
+impl<F: FnMut() + Send> Hotplug<GlobalContext> for HotplugCallback<F> {
+    fn device_arrived(&mut self, _device: Device<GlobalContext>) {
+        (self.0)()
+    }
+
+    fn device_left(&mut self, _device: Device<GlobalContext>) {
+        (self.0)()
+    }
+}
+
+/// Calls `on_change` from a background thread whenever a Kindle arrives or leaves
+///
+/// Returns false when this libusb has no hotplug support (e.g. on Windows). The
+/// callback runs inside libusb's event handling, so it must not do USB I/O itself.
+pub fn watch_kindle_hotplug(on_change: impl FnMut() + Send + 'static) -> bool {
+    if !usb_available() || !rusb::has_hotplug() {
+        return false;
+    }
+    let registration = match HotplugBuilder::new().vendor_id(KINDLE_VID).register(
+        GlobalContext::default(),
+        Box::new(HotplugCallback(on_change)),
+    ) {
+        Ok(registration) => registration,
+        Err(_) => return false,
+    };
+    std::thread::spawn(move || {
+        let _registration = registration;
+        while GlobalContext::default().handle_events(None).is_ok() {}
+    });
+    true
+}
+
+fn has_mtp_interface(device: &Device<GlobalContext>) -> bool {
+    device.config_descriptor(0).is_ok_and(|config| {
+        config
+            .interfaces()
+            .flat_map(|interface| interface.descriptors())
+            .any(|desc| is_mtp_class(desc.class_code()))
+    })
+}
+
+/// Identifies the Kindle at `location` over MTP, falling back to the USB descriptor
+/// strings when the MTP interface can't be claimed (e.g. without admin rights)
+pub fn identify_kindle_at(location: (u8, u8)) -> Result<KindleIdentity, KindleError> {
+    identify_device(&kindle_usb_device_at(location)?)
+}
+
+fn identify_device(device: &Device<GlobalContext>) -> Result<KindleIdentity, KindleError> {
+    let via_mtp = RusbTransport::open(device.clone()).and_then(|t| MtpDevice::new(t).identify());
+    via_mtp.or_else(|_| identify_from_usb_descriptors(device))
+}
+
+fn identify_from_usb_descriptors(
+    device: &Device<GlobalContext>,
+) -> Result<KindleIdentity, KindleError> {
+    let desc = device.device_descriptor()?;
+    let handle = device.open()?;
+
+    Ok(KindleIdentity {
+        device: DeviceInfo {
+            manufacturer: handle
+                .read_manufacturer_string_ascii(&desc)
+                .unwrap_or_default(),
+            model: handle.read_product_string_ascii(&desc).unwrap_or_default(),
+            serial_number: handle
+                .read_serial_number_string_ascii(&desc)
+                .unwrap_or_default(),
+            firmware_version: String::new(),
+            operations_supported: Vec::new(),
+        },
+        storages: Vec::new(),
+    })
+}
+
+/// Read vocab.db content directly from Kindle via MTP (returns bytes)
+#[allow(dead_code)]
+pub fn read_vocab_db_via_mtp() -> Result<Vec<u8>, KindleError> {
+    let mut device = MtpDevice::find_kindle()?;
+    device.read_vocab_db_bytes(&CancelToken::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake::FakeKindle;
+    use super::*;
+
+    #[test]
+    fn reads_vocab_db_from_fake_kindle() {
+        let mut device = MtpDevice::new(FakeKindle::with_vocab_db(b"SQLite format 3\0vocab"));
+        assert_eq!(
+            device.read_vocab_db_bytes(&CancelToken::new()).unwrap(),
+            b"SQLite format 3\0vocab"
+        );
+    }
+
+    #[test]
+    fn reads_vocab_db_larger_than_one_bulk_transfer() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let mut device = MtpDevice::new(FakeKindle::with_vocab_db(&data));
+        assert_eq!(
+            device.read_vocab_db_bytes(&CancelToken::new()).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn finds_vocab_db_on_second_storage() {
+        let mut kindle = FakeKindle::new();
+        let first = kindle.add_storage(0x0001_0001);
+        kindle.add_folder(first, 0, "documents");
+        let second = kindle.add_storage(0x0002_0001);
+        let system = kindle.add_folder(second, 0, "System");
+        let vocabulary = kindle.add_folder(second, system, "Vocabulary");
+        kindle.add_file(second, vocabulary, "VOCAB.DB", b"vocab");
+
+        let mut device = MtpDevice::new(kindle);
+        assert_eq!(
+            device.read_vocab_db_bytes(&CancelToken::new()).unwrap(),
+            b"vocab"
+        );
+    }
+
+    #[test]
+    fn reports_missing_vocab_db() {
+        let mut kindle = FakeKindle::new();
+        let storage = kindle.add_storage(0x0001_0001);
+        kindle.add_folder(storage, 0, "system");
+
+        let mut device = MtpDevice::new(kindle);
+        assert_eq!(
+            device.read_vocab_db_bytes(&CancelToken::new()).unwrap_err(),
+            KindleError::VocabNotFound
+        );
+    }
+
+    #[test]
+    fn reads_file_by_path_on_any_storage() {
+        const CLIPPINGS: &[&str] = &["documents", "My Clippings.txt"];
+        let mut kindle = FakeKindle::with_vocab_db(b"vocab");
+        let storage = kindle.add_storage(0x0002_0001);
+        let documents = kindle.add_folder(storage, 0, "documents");
+        kindle.add_file(storage, documents, "My Clippings.txt", b"clippings");
+
+        let mut device = MtpDevice::new(kindle);
+        let mut out = Vec::new();
+        device
+            .download_file_to(
+                CLIPPINGS,
+                KindleError::ClippingsNotFound,
+                &mut out,
+                &CancelToken::new(),
+                &mut |_| {},
+            )
+            .unwrap();
+        assert_eq!(out, b"clippings");
+
+        let mut device = MtpDevice::new(FakeKindle::with_vocab_db(b"vocab"));
+        assert_eq!(
+            device
+                .download_file_to(
+                    CLIPPINGS,
+                    KindleError::ClippingsNotFound,
+                    &mut Vec::new(),
+                    &CancelToken::new(),
+                    &mut |_| {},
+                )
+                .unwrap_err(),
+            KindleError::ClippingsNotFound
+        );
+    }
+
+    fn no_delay(device: &mut MtpDevice<&mut FakeKindle>) {
+        device.busy_retry = RetryPolicy {
+            attempts: 3,
+            delay: Duration::ZERO,
+        };
+    }
+
+    #[test]
+    fn closes_session_on_drop() {
+        let mut kindle = FakeKindle::with_vocab_db(b"vocab");
+        {
+            let mut device = MtpDevice::new(&mut kindle);
+            device.read_vocab_db_bytes(&CancelToken::new()).unwrap();
+        }
+        assert!(!kindle.session_open());
+        assert_eq!(kindle.operations.first(), Some(&PTP_OC_OPEN_SESSION));
+        assert_eq!(kindle.operations.last(), Some(&PTP_OC_CLOSE_SESSION));
+    }
+
+    #[test]
+    fn retries_while_device_busy() {
+        let mut kindle = FakeKindle::with_vocab_db(b"vocab");
+        kindle.script_response(PTP_OC_OPEN_SESSION, PTP_RC_DEVICE_BUSY);
+        kindle.script_response(PTP_OC_GET_STORAGE_IDS, PTP_RC_DEVICE_BUSY);
+        kindle.script_response(PTP_OC_GET_STORAGE_IDS, PTP_RC_DEVICE_BUSY);
+
+        let mut device = MtpDevice::new(&mut kindle);
+        no_delay(&mut device);
+        assert_eq!(
+            device.read_vocab_db_bytes(&CancelToken::new()).unwrap(),
+            b"vocab"
+        );
+    }
+
+    #[test]
+    fn gives_up_when_device_stays_busy() {
+        let mut kindle = FakeKindle::with_vocab_db(b"vocab");
+        for _ in 0..4 {
+            kindle.script_response(PTP_OC_GET_STORAGE_IDS, PTP_RC_DEVICE_BUSY);
+        }
+
+        let mut device = MtpDevice::new(&mut kindle);
+        no_delay(&mut device);
+        assert_eq!(
+            device.read_vocab_db_bytes(&CancelToken::new()).unwrap_err(),
+            KindleError::InterfaceBusy(None)
+        );
+    }
+
+    #[test]
+    fn reopens_session_after_session_not_open() {
+        let mut kindle = FakeKindle::with_vocab_db(b"vocab");
+        kindle.script_response(MTP_OC_GET_PARTIAL_OBJECT_64, PTP_RC_SESSION_NOT_OPEN);
+
+        let mut device = MtpDevice::new(&mut kindle);
+        assert_eq!(
+            device.read_vocab_db_bytes(&CancelToken::new()).unwrap(),
+            b"vocab"
+        );
+        drop(device);
+
+        let opens = kindle
+            .operations
+            .iter()
+            .filter(|op| **op == PTP_OC_OPEN_SESSION)
+            .count();
+        assert_eq!(opens, 2);
+    }
+
+    #[test]
+    fn maps_response_codes_to_typed_errors() {
+        let mut kindle = FakeKindle::with_vocab_db(b"vocab");
+        kindle.script_response(PTP_OC_GET_STORAGE_IDS, 0x2002);
+        let mut device = MtpDevice::new(kindle);
+        assert_eq!(
+            device.read_vocab_db_bytes(&CancelToken::new()).unwrap_err(),
+            KindleError::ProtocolError {
+                operation: "GetStorageIDs",
+                code: 0x2002
+            }
+        );
+
+        let mut kindle = FakeKindle::with_vocab_db(b"vocab");
+        kindle.script_response(MTP_OC_GET_PARTIAL_OBJECT_64, PTP_RC_ACCESS_DENIED);
+        let mut device = MtpDevice::new(kindle);
+        assert!(matches!(
+            device.read_vocab_db_bytes(&CancelToken::new()).unwrap_err(),
+            KindleError::PermissionDenied(_)
+        ));
+
+        let mut kindle = FakeKindle::new();
+        kindle.script_response(PTP_OC_OPEN_SESSION, 0x2002);
+        let mut device = MtpDevice::new(kindle);
+        assert_eq!(
+            device.read_vocab_db_bytes(&CancelToken::new()).unwrap_err(),
+            KindleError::ProtocolError {
+                operation: "OpenSession",
+                code: 0x2002
+            }
+        );
+    }
+
+    #[test]
+    fn identifies_device_and_storages() {
+        let mut kindle = FakeKindle::with_vocab_db(b"vocab");
+        kindle.model = "Kindle Paperwhite".to_string();
+        kindle.add_storage(0x0002_0001);
+
+        let identity = MtpDevice::new(kindle).identify().unwrap();
+        assert_eq!(identity.device.manufacturer, "Amazon");
+        assert_eq!(identity.device.model, "Kindle Paperwhite");
+        assert_eq!(identity.device.serial_number, "G000TEST00000001");
+        assert_eq!(identity.device.firmware_version, "5.16.2");
+        assert!(identity
+            .device
+            .operations_supported
+            .contains(&PTP_OC_GET_OBJECT));
+        assert_eq!(identity.storages.len(), 2);
+        assert_eq!(identity.storages[0].storage_id, 0x0001_0001);
+        assert_eq!(identity.storages[0].description, "Internal Storage");
+        assert_eq!(
+            identity.storages[0].max_capacity - identity.storages[0].free_space,
+            5
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_device_info() {
+        assert_eq!(
+            DeviceInfo::parse(&[0x64, 0x00, 0x06]).unwrap_err(),
+            KindleError::InvalidResponse("DeviceInfo dataset truncated".to_string())
+        );
+    }
+
+    fn downloaded_with(kindle: &mut FakeKindle) -> (Vec<u8>, Vec<DownloadProgress>) {
+        let mut out = Vec::new();
+        let mut progress = Vec::new();
+        MtpDevice::new(kindle)
+            .download_vocab_db_to(&mut out, &CancelToken::new(), &mut |p| progress.push(p))
+            .unwrap();
+        (out, progress)
+    }
+
+    #[test]
+    fn streams_in_chunks_with_partial_object_64() {
+        let data: Vec<u8> = (0..(CHUNK_SIZE * 2 + 10)).map(|i| i as u8).collect();
+        let mut kindle = FakeKindle::with_vocab_db(&data);
+
+        let (out, progress) = downloaded_with(&mut kindle);
+        assert_eq!(out, data);
+        let total = data.len() as u64;
+        assert_eq!(
+            progress.iter().map(|p| p.bytes_done).collect::<Vec<_>>(),
+            vec![0, CHUNK_SIZE as u64, CHUNK_SIZE as u64 * 2, total]
+        );
+        assert!(progress.iter().all(|p| p.total == total));
+        let partial_reads = kindle
+            .operations
+            .iter()
+            .filter(|op| **op == MTP_OC_GET_PARTIAL_OBJECT_64)
+            .count();
+        assert_eq!(partial_reads, 3);
+    }
+
+    #[test]
+    fn falls_back_to_32_bit_partial_reads() {
+        let data: Vec<u8> = (0..(CHUNK_SIZE + 1)).map(|i| i as u8).collect();
+        let mut kindle = FakeKindle::with_vocab_db(&data);
+        kindle
+            .operations_supported
+            .retain(|op| *op != MTP_OC_GET_PARTIAL_OBJECT_64);
+
+        let (out, _) = downloaded_with(&mut kindle);
+        assert_eq!(out, data);
+        assert!(kindle.operations.contains(&PTP_OC_GET_PARTIAL_OBJECT));
+        assert!(!kindle.operations.contains(&MTP_OC_GET_PARTIAL_OBJECT_64));
+    }
+
+    #[test]
+    fn falls_back_to_get_object_without_partial_reads() {
+        let mut kindle = FakeKindle::with_vocab_db(b"vocab");
+        kindle
+            .operations_supported
+            .retain(|op| *op != MTP_OC_GET_PARTIAL_OBJECT_64 && *op != PTP_OC_GET_PARTIAL_OBJECT);
+
+        let (out, progress) = downloaded_with(&mut kindle);
+        assert_eq!(out, b"vocab");
+        assert_eq!(
+            progress,
+            vec![DownloadProgress {
+                bytes_done: 5,
+                total: 5
+            }]
+        );
+        assert!(kindle.operations.contains(&PTP_OC_GET_OBJECT));
+    }
+
+    #[test]
+    fn resumes_interrupted_chunk_from_last_good_offset() {
+        let data: Vec<u8> = (0..(CHUNK_SIZE * 2 + 10)).map(|i| i as u8).collect();
+        let mut kindle = FakeKindle::with_vocab_db(&data);
+        kindle.interrupt(MTP_OC_GET_PARTIAL_OBJECT_64, 2);
+
+        let (out, progress) = downloaded_with(&mut kindle);
+        assert_eq!(out, data);
+        assert_eq!(progress.last().unwrap().bytes_done, data.len() as u64);
+        let partial_reads = kindle
+            .operations
+            .iter()
+            .filter(|op| **op == MTP_OC_GET_PARTIAL_OBJECT_64)
+            .count();
+        assert_eq!(partial_reads, 5);
+        assert_eq!(
+            kindle.class_requests,
+            vec![
+                SIC_REQUEST_CANCEL,
+                SIC_REQUEST_GET_DEVICE_STATUS,
+                SIC_REQUEST_CANCEL,
+                SIC_REQUEST_GET_DEVICE_STATUS,
+            ]
+        );
+    }
+
+    #[test]
+    fn gives_up_when_transfer_keeps_failing() {
+        let mut kindle = FakeKindle::with_vocab_db(b"vocab");
+        kindle.interrupt(MTP_OC_GET_PARTIAL_OBJECT_64, RESUME_ATTEMPTS + 1);
+
+        let mut out = Vec::new();
+        let result = MtpDevice::new(&mut kindle).download_vocab_db_to(
+            &mut out,
+            &CancelToken::new(),
+            &mut |_| {},
+        );
+        assert_eq!(result, Err(KindleError::Timeout));
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn rejects_download_shorter_than_object_info() {
+        let mut kindle = FakeKindle::new();
+        let storage = kindle.add_storage(0x0001_0001);
+        let system = kindle.add_folder(storage, 0, "system");
+        let vocabulary = kindle.add_folder(storage, system, "vocabulary");
+        let vocab_db = kindle.add_file(storage, vocabulary, "vocab.db", b"vocab");
+        kindle.report_size(vocab_db, 8);
+
+        let result = MtpDevice::new(&mut kindle).read_vocab_db_bytes(&CancelToken::new());
+        assert_eq!(
+            result,
+            Err(KindleError::IncompleteTransfer {
+                expected: 8,
+                received: 5
+            })
+        );
+
+        kindle
+            .operations_supported
+            .retain(|op| *op != MTP_OC_GET_PARTIAL_OBJECT_64 && *op != PTP_OC_GET_PARTIAL_OBJECT);
+        let result = MtpDevice::new(&mut kindle).read_vocab_db_bytes(&CancelToken::new());
+        assert_eq!(
+            result,
+            Err(KindleError::IncompleteTransfer {
+                expected: 8,
+                received: 5
+            })
+        );
+    }
+
+    #[test]
+    fn cancels_between_chunks_and_leaves_device_usable() {
+        let data: Vec<u8> = (0..(CHUNK_SIZE * 3)).map(|i| i as u8).collect();
+        let mut kindle = FakeKindle::with_vocab_db(&data);
+        let mut device = MtpDevice::new(&mut kindle);
+
+        let cancel = CancelToken::new();
+        let mut out = Vec::new();
+        let result = device.download_vocab_db_to(&mut out, &cancel, &mut |p| {
+            if p.bytes_done >= CHUNK_SIZE as u64 {
+                cancel.cancel();
+            }
+        });
+        assert_eq!(result, Err(KindleError::Cancelled));
+        assert_eq!(out.len(), CHUNK_SIZE as usize);
+
+        assert_eq!(
+            device.read_vocab_db_bytes(&CancelToken::new()).unwrap(),
+            data
+        );
+        drop(device);
+        assert!(!kindle.session_open());
+    }
+
+    #[test]
+    fn resets_device_that_ignores_cancel() {
+        let mut kindle = FakeKindle::with_vocab_db(b"vocab");
+        kindle.ignores_cancel = true;
+        kindle.interrupt(MTP_OC_GET_PARTIAL_OBJECT_64, 1);
+        let mut device = MtpDevice::new(&mut kindle);
+        no_delay(&mut device);
+
+        assert_eq!(
+            device.read_vocab_db_bytes(&CancelToken::new()).unwrap(),
+            b"vocab"
+        );
+        drop(device);
+
+        assert!(kindle.class_requests.contains(&SIC_REQUEST_DEVICE_RESET));
+        let opened = kindle
+            .operations
+            .iter()
+            .filter(|op| **op == PTP_OC_OPEN_SESSION)
+            .count();
+        assert_eq!(opened, 2);
+    }
+}
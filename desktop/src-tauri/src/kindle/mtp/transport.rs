@@ -0,0 +1,206 @@
+//! USB transport behind `MtpDevice`
+//!
+//! PTP containers only need a bulk-in and a bulk-out pipe, so the protocol code
+//! talks to this trait instead of `rusb` directly. The real implementation claims
+//! the MTP interface of a Kindle; tests plug in the in-memory `FakeKindle`.
+
+use crate::kindle::KindleError;
+use rusb::{Device, DeviceHandle, GlobalContext};
+use std::time::Duration;
+
+pub trait UsbTransport {
+    /// Writes one bulk transfer to the device, returning the number of bytes sent
+    fn write_bulk(&mut self, data: &[u8], timeout: Duration) -> Result<usize, KindleError>;
+
+    /// Reads one bulk transfer from the device into `buf`, returning the number of bytes read
+    fn read_bulk(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, KindleError>;
+
+    /// Clears a stall on both bulk pipes after a failed transfer
+    fn clear_halt(&mut self) -> Result<(), KindleError>;
+
+    /// Sends a Still Image class request with an optional data stage to the MTP interface
+    fn class_request_out(
+        &mut self,
+        request: u8,
+        data: &[u8],
+        timeout: Duration,
+    ) -> Result<(), KindleError>;
+
+    /// Sends a Still Image class request and reads its data stage into `buf`
+    fn class_request_in(
+        &mut self,
+        request: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, KindleError>;
+}
+
+impl<T: UsbTransport + ?Sized> UsbTransport for &mut T {
+    fn write_bulk(&mut self, data: &[u8], timeout: Duration) -> Result<usize, KindleError> {
+        (**self).write_bulk(data, timeout)
+    }
+
+    fn read_bulk(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, KindleError> {
+        (**self).read_bulk(buf, timeout)
+    }
+
+    fn clear_halt(&mut self) -> Result<(), KindleError> {
+        (**self).clear_halt()
+    }
+
+    fn class_request_out(
+        &mut self,
+        request: u8,
+        data: &[u8],
+        timeout: Duration,
+    ) -> Result<(), KindleError> {
+        (**self).class_request_out(request, data, timeout)
+    }
+
+    fn class_request_in(
+        &mut self,
+        request: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, KindleError> {
+        (**self).class_request_in(request, buf, timeout)
+    }
+}
+
+/// Still Image (PTP) class, or the vendor-specific class some Kindles report for MTP
+pub fn is_mtp_class(class_code: u8) -> bool {
+    class_code == 6 || class_code == 0xff
+}
+
+/// Bulk endpoints of a claimed MTP interface on a physical device
+pub struct RusbTransport {
+    handle: DeviceHandle<GlobalContext>,
+    interface: u8,
+    ep_in: u8,
+    ep_out: u8,
+}
+
+impl RusbTransport {
+    pub fn open(device: Device<GlobalContext>) -> Result<Self, KindleError> {
+        let config_desc = device.config_descriptor(0)?;
+
+        let mut ep_in = 0u8;
+        let mut ep_out = 0u8;
+        let mut interface_num = 0u8;
+
+        for interface in config_desc.interfaces() {
+            for desc in interface.descriptors() {
+                if is_mtp_class(desc.class_code()) {
+                    interface_num = desc.interface_number();
+                    for endpoint in desc.endpoint_descriptors() {
+                        match (endpoint.direction(), endpoint.transfer_type()) {
+                            (rusb::Direction::In, rusb::TransferType::Bulk) => {
+                                ep_in = endpoint.address()
+                            }
+                            (rusb::Direction::Out, rusb::TransferType::Bulk) => {
+                                ep_out = endpoint.address()
+                            }
+                            _ => {}
+                        }
+                    }
+                    if ep_in != 0 && ep_out != 0 {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if ep_in == 0 || ep_out == 0 {
+            return Err(KindleError::Usb("Could not find MTP endpoints".to_string()));
+        }
+
+        #[cfg(target_os = "linux")]
+        let handle = {
+            let mut handle = super::linux::open(&device)?;
+            super::linux::claim_interface(&device, &mut handle, interface_num)?;
+            handle
+        };
+
+        #[cfg(not(target_os = "linux"))]
+        let handle = {
+            let handle = device.open()?;
+
+            // Try to claim interface - if it fails, try to detach kernel driver first
+            if handle.claim_interface(interface_num).is_err() {
+                // Check if kernel driver is active and try to detach
+                if let Ok(true) = handle.kernel_driver_active(interface_num) {
+                    let _ = handle.detach_kernel_driver(interface_num);
+                }
+                // Try claiming again - continue anyway if it fails
+                let _ = handle.claim_interface(interface_num);
+            }
+            handle
+        };
+
+        Ok(Self {
+            handle,
+            interface: interface_num,
+            ep_in,
+            ep_out,
+        })
+    }
+}
+
+impl UsbTransport for RusbTransport {
+    fn write_bulk(&mut self, data: &[u8], timeout: Duration) -> Result<usize, KindleError> {
+        Ok(self.handle.write_bulk(self.ep_out, data, timeout)?)
+    }
+
+    fn read_bulk(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, KindleError> {
+        Ok(self.handle.read_bulk(self.ep_in, buf, timeout)?)
+    }
+
+    fn clear_halt(&mut self) -> Result<(), KindleError> {
+        self.handle.clear_halt(self.ep_in)?;
+        self.handle.clear_halt(self.ep_out)?;
+        Ok(())
+    }
+
+    fn class_request_out(
+        &mut self,
+        request: u8,
+        data: &[u8],
+        timeout: Duration,
+    ) -> Result<(), KindleError> {
+        let request_type = rusb::request_type(
+            rusb::Direction::Out,
+            rusb::RequestType::Class,
+            rusb::Recipient::Interface,
+        );
+        self.handle.write_control(
+            request_type,
+            request,
+            0,
+            self.interface as u16,
+            data,
+            timeout,
+        )?;
+        Ok(())
+    }
+
+    fn class_request_in(
+        &mut self,
+        request: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, KindleError> {
+        let request_type = rusb::request_type(
+            rusb::Direction::In,
+            rusb::RequestType::Class,
+            rusb::Recipient::Interface,
+        );
+        Ok(self.handle.read_control(
+            request_type,
+            request,
+            0,
+            self.interface as u16,
+            buf,
+            timeout,
+        )?)
+    }
+}
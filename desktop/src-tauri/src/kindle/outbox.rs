@@ -0,0 +1,436 @@
+//! Durable queue of import batches that could not be uploaded yet
+//!
+//! When `parse-vocab` can't be reached, the batches it never received are kept in
+//! SQLite with the sync state to record once each is sent, keyed by device and
+//! the watermark that state moves to. A device's batches go out in the order
+//! they were queued. Only a batch the server never received is kept for another
+//! attempt: `parse-vocab` is not idempotent, so one lost after it was sent is
+//! dropped with the rest of its device instead of replayed, and the device's
+//! next import offers their lookups again.
+//!
+//! A new import of a device replaces its queued batches. Its delta is computed
+//! from the same sync state, so it contains every lookup they hold.
+
+use crate::kindle::sync_state::{sent_state, DeviceSyncState, VocabDelta};
+use crate::kindle::upload::UploadClient;
+use crate::kindle::vocab::{query, Vocab};
+use crate::kindle::KindleError;
+use rusqlite::{params, Connection};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS pending_batches (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        device_id TEXT NOT NULL,
+        watermark INTEGER NOT NULL,
+        lookups INTEGER NOT NULL,
+        queued_at INTEGER NOT NULL,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        last_error TEXT,
+        payload TEXT NOT NULL,
+        state TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS pending_batches_device
+        ON pending_batches (device_id, watermark);
+";
+
+/// A queued batch, listed for the UI without its payload
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingBatch {
+    pub id: i64,
+    /// Serial number of the Kindle; readers without one are never queued
+    pub device_id: String,
+    /// Watermark the device's sync state moves to once the batch is sent
+    pub watermark: i64,
+    /// Number of lookups in the batch
+    pub lookups: usize,
+    /// When the batch was queued (ms since the Unix epoch)
+    pub queued_at: i64,
+    /// Failed attempts to send the batch since it was queued
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// Request body `delta`, as `{ lookups, books }`
+    #[serde(skip)]
+    pub payload: serde_json::Value,
+    /// Sync state to record once the batch is sent
+    #[serde(skip)]
+    pub state: DeviceSyncState,
+}
+
+pub struct Outbox {
+    conn: Connection,
+}
+
+impl Outbox {
+    /// Opens the queue, creating the database on first use
+    pub fn open(path: &Path) -> Result<Self, KindleError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path).map_err(in_outbox)?;
+        conn.execute_batch(SCHEMA).map_err(in_outbox)?;
+        Ok(Self { conn })
+    }
+
+    /// Queues the lookups of `delta` not in `sent` in place of the device's queued batches
+    ///
+    /// `sent` holds the lookups the server already accepted from this delta.
+    /// Returns the number of lookups queued.
+    pub fn queue_unsent(
+        &mut self,
+        device_id: &str,
+        client: &UploadClient,
+        vocab: &Vocab,
+        previous: &DeviceSyncState,
+        delta: &VocabDelta,
+        sent: &BTreeSet<String>,
+    ) -> Result<usize, KindleError> {
+        let queued_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as i64)
+            .unwrap_or_default();
+        let unsent = VocabDelta {
+            total_lookups: delta.total_lookups,
+            lookups: delta
+                .lookups
+                .iter()
+                .filter(|l| !sent.contains(&l.id))
+                .cloned()
+                .collect(),
+            books: delta.books.clone(),
+        };
+
+        let tx = self.conn.transaction().map_err(in_outbox)?;
+        tx.execute(
+            "DELETE FROM pending_batches WHERE device_id = ?1",
+            [device_id],
+        )
+        .map_err(in_outbox)?;
+        let mut covered = sent.clone();
+        for batch in client.batches(&unsent)? {
+            covered.extend(batch.lookups.iter().map(|l| l.id.clone()));
+            let state = sent_state(vocab, previous, &covered);
+            tx.execute(
+                "INSERT INTO pending_batches \
+                 (device_id, watermark, lookups, queued_at, payload, state) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    device_id,
+                    state.watermark,
+                    batch.lookups.len() as i64,
+                    queued_at,
+                    to_json(&batch)?,
+                    to_json(&state)?,
+                ],
+            )
+            .map_err(in_outbox)?;
+        }
+        tx.commit().map_err(in_outbox)?;
+        Ok(unsent.lookups.len())
+    }
+
+    /// Queued batches, by device and then in the order they must be sent
+    pub fn list(&self) -> Result<Vec<PendingBatch>, KindleError> {
+        let rows = query(
+            &self.conn,
+            "SELECT id, device_id, watermark, lookups, queued_at, attempts, last_error, \
+             payload, state FROM pending_batches ORDER BY device_id, id",
+            |row| {
+                Ok((
+                    PendingBatch {
+                        id: row.get(0)?,
+                        device_id: row.get(1)?,
+                        watermark: row.get(2)?,
+                        lookups: row.get::<_, i64>(3)? as usize,
+                        queued_at: row.get(4)?,
+                        attempts: row.get(5)?,
+                        last_error: row.get(6)?,
+                        payload: serde_json::Value::Null,
+                        state: DeviceSyncState::default(),
+                    },
+                    row.get::<_, String>(7)?,
+                    row.get::<_, String>(8)?,
+                ))
+            },
+        )
+        .map_err(in_outbox)?;
+        rows.into_iter()
+            .map(|(batch, payload, state)| {
+                Ok(PendingBatch {
+                    payload: from_json(&payload)?,
+                    state: from_json(&state)?,
+                    ..batch
+                })
+            })
+            .collect()
+    }
+
+    pub fn discard(&self, id: i64) -> Result<(), KindleError> {
+        self.conn
+            .execute("DELETE FROM pending_batches WHERE id = ?1", [id])
+            .map_err(in_outbox)?;
+        Ok(())
+    }
+
+    /// Drops the device's queued batches, e.g. once a full import of it succeeded
+    pub fn discard_device(&self, device_id: &str) -> Result<(), KindleError> {
+        self.conn
+            .execute(
+                "DELETE FROM pending_batches WHERE device_id = ?1",
+                [device_id],
+            )
+            .map_err(in_outbox)?;
+        Ok(())
+    }
+
+    /// Passes queued batches to `send` in order, removing each one it accepts
+    ///
+    /// Failures that every other batch would hit too, the server not having
+    /// received the batch or the session having expired, are recorded on it and
+    /// stop the drain. Any other failure drops the batch and the rest of its
+    /// device, as the server may have imported it. Returns the number of batches
+    /// sent.
+    pub fn drain(
+        &mut self,
+        send: &mut dyn FnMut(&PendingBatch) -> Result<(), KindleError>,
+    ) -> Result<usize, KindleError> {
+        let mut dropped = BTreeSet::new();
+        let mut sent = 0;
+        for batch in self.list()? {
+            if dropped.contains(&batch.device_id) {
+                continue;
+            }
+            match send(&batch) {
+                Ok(()) => {
+                    self.discard(batch.id)?;
+                    sent += 1;
+                }
+                Err(e)
+                    if e.not_processed()
+                        || matches!(e, KindleError::SessionExpired | KindleError::Cancelled) =>
+                {
+                    self.conn
+                        .execute(
+                            "UPDATE pending_batches SET attempts = attempts + 1, last_error = ?2 \
+                             WHERE id = ?1",
+                            params![batch.id, e.to_string()],
+                        )
+                        .map_err(in_outbox)?;
+                    break;
+                }
+                Err(_) => {
+                    self.discard_device(&batch.device_id)?;
+                    dropped.insert(batch.device_id);
+                }
+            }
+        }
+        Ok(sent)
+    }
+}
+
+fn to_json(value: &impl serde::Serialize) -> Result<String, KindleError> {
+    serde_json::to_string(value).map_err(|e| KindleError::Io(e.to_string()))
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(json: &str) -> Result<T, KindleError> {
+    serde_json::from_str(json)
+        .map_err(|e| KindleError::Io(format!("Import outbox is damaged: {}", e)))
+}
+
+/// Names the outbox in SQLite errors, which would otherwise read like a problem
+/// with the reader's database
+fn in_outbox(e: impl Into<KindleError>) -> KindleError {
+    match e.into() {
+        KindleError::CorruptDatabase(msg) => {
+            KindleError::Io(format!("Import outbox is damaged: {}", msg))
+        }
+        KindleError::PermissionDenied(msg) => {
+            KindleError::PermissionDenied(format!("import outbox: {}", msg))
+        }
+        KindleError::Io(msg) => KindleError::Io(format!("Import outbox: {}", msg)),
+        e => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kindle::sync_state::vocab_delta;
+    use crate::kindle::upload::{Session, UploadAuth};
+    use crate::kindle::vocab::sample_vocab_db;
+
+    /// An empty outbox, deleted with the returned directory
+    fn outbox() -> (tempfile::TempDir, Outbox) {
+        let dir = tempfile::tempdir().unwrap();
+        let outbox = Outbox::open(&dir.path().join("outbox.sqlite")).unwrap();
+        (dir, outbox)
+    }
+
+    /// Client whose batches hold a single lookup each
+    fn client() -> UploadClient {
+        UploadClient::new(UploadAuth {
+            url: "http://127.0.0.1:9".to_string(),
+            anon_key: "anon".to_string(),
+            session: Session {
+                access_token: "access".to_string(),
+                refresh_token: "refresh".to_string(),
+            },
+            native_language_code: None,
+        })
+        .with_max_body_bytes(0)
+    }
+
+    fn vocab() -> Vocab {
+        Vocab::from_bytes(&sample_vocab_db(&[
+            ("whale", "whale", "", 1000),
+            ("sea", "sea", "", 2000),
+            ("ship", "ship", "", 3000),
+        ]))
+        .unwrap()
+    }
+
+    /// Queues the lookups of `vocab()` after `sent`, one per batch
+    fn queue(outbox: &mut Outbox, device_id: &str, sent: &[&str]) -> usize {
+        let vocab = vocab();
+        let previous = DeviceSyncState::default();
+        let (delta, _) = vocab_delta(&vocab, &previous);
+        let sent = delta
+            .lookups
+            .iter()
+            .filter(|l| sent.contains(&l.word.as_str()))
+            .map(|l| l.id.clone())
+            .collect();
+        outbox
+            .queue_unsent(device_id, &client(), &vocab, &previous, &delta, &sent)
+            .unwrap()
+    }
+
+    fn words(batch: &PendingBatch) -> Vec<&str> {
+        batch.payload["lookups"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|l| l["word"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn queues_unsent_lookups_with_the_state_each_batch_moves_to() {
+        let (_dir, mut outbox) = outbox();
+        assert_eq!(queue(&mut outbox, "G000", &["whale"]), 2);
+
+        let pending = outbox.list().unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(words(&pending[0]), vec!["sea"]);
+        assert_eq!(pending[0].watermark, 2000);
+        assert_eq!(pending[0].state.watermark, 2000);
+        assert_eq!(words(&pending[1]), vec!["ship"]);
+        assert_eq!(pending[1].watermark, 3000);
+        assert!(pending[1].queued_at > 0);
+        assert_eq!(pending[1].payload["books"][0]["title"], "Moby Dick");
+    }
+
+    #[test]
+    fn requeueing_a_device_replaces_its_batches() {
+        let (_dir, mut outbox) = outbox();
+        queue(&mut outbox, "G000", &[]);
+        queue(&mut outbox, "G111", &[]);
+        queue(&mut outbox, "G000", &["whale", "sea"]);
+
+        let pending = outbox.list().unwrap();
+        let devices: Vec<_> = pending.iter().map(|b| b.device_id.as_str()).collect();
+        assert_eq!(devices, vec!["G000", "G111", "G111", "G111"]);
+        assert_eq!(words(&pending[0]), vec!["ship"]);
+    }
+
+    #[test]
+    fn batch_the_server_may_have_imported_drops_the_rest_of_its_device() {
+        let (_dir, mut outbox) = outbox();
+        queue(&mut outbox, "G000", &[]);
+        queue(&mut outbox, "G111", &[]);
+
+        let mut sent = Vec::new();
+        let count = outbox
+            .drain(&mut |batch| {
+                if batch.device_id == "G000" && batch.watermark == 2000 {
+                    return Err(KindleError::Network("connection reset".to_string()));
+                }
+                sent.push((batch.device_id.clone(), batch.watermark));
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(count, 4);
+        assert_eq!(
+            sent,
+            vec![
+                ("G000".to_string(), 1000),
+                ("G111".to_string(), 1000),
+                ("G111".to_string(), 2000),
+                ("G111".to_string(), 3000),
+            ]
+        );
+        assert!(outbox.list().unwrap().is_empty());
+
+        // Nor is a batch replayed after a gateway timeout
+        queue(&mut outbox, "G000", &[]);
+        let mut calls = 0;
+        outbox
+            .drain(&mut |_| {
+                calls += 1;
+                Err(KindleError::Server {
+                    status: 504,
+                    message: "Gateway Timeout".to_string(),
+                })
+            })
+            .unwrap();
+        assert_eq!(calls, 1);
+        assert!(outbox.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn unreachable_server_stops_the_drain() {
+        let (_dir, mut outbox) = outbox();
+        queue(&mut outbox, "G000", &[]);
+        queue(&mut outbox, "G111", &[]);
+
+        let mut calls = 0;
+        let count = outbox
+            .drain(&mut |_| {
+                calls += 1;
+                Err(KindleError::Unreachable("offline".to_string()))
+            })
+            .unwrap();
+
+        assert_eq!((count, calls), (0, 1));
+        let pending = outbox.list().unwrap();
+        assert_eq!(pending.len(), 6);
+        assert_eq!(pending[0].attempts, 1);
+        assert_eq!(
+            pending[0].last_error.as_deref(),
+            Some("Could not reach the server: offline")
+        );
+        assert_eq!(pending[1].attempts, 0);
+    }
+
+    #[test]
+    fn discards_single_batches_and_whole_devices() {
+        let (_dir, mut outbox) = outbox();
+        queue(&mut outbox, "G000", &[]);
+        queue(&mut outbox, "G111", &[]);
+
+        let first = outbox.list().unwrap()[0].id;
+        outbox.discard(first).unwrap();
+        outbox.discard_device("G111").unwrap();
+
+        let pending = outbox.list().unwrap();
+        assert_eq!(pending.len(), 2);
+        assert!(pending
+            .iter()
+            .all(|b| b.device_id == "G000" && b.id != first));
+    }
+}
@@ -0,0 +1,277 @@
+//! Dry run of an import: what `parse-vocab` would do with each lookup
+//!
+//! Lookups are classified against the user's vocabulary the way `classifyEntries`
+//! in `parse-vocab` does it: the newest lookup of a word the user doesn't have yet
+//! adds it, or restores it when it was soft-deleted, and every other lookup only
+//! records an encounter. Nothing is uploaded and the sync state is left alone, so
+//! the user can deselect words or whole books before importing.
+
+use crate::kindle::sync_state::{DeltaLookup, VocabDelta};
+use crate::kindle::upload::KnownWord;
+use crate::kindle::vocab::Book;
+use std::collections::BTreeSet;
+
+/// What importing a lookup would do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Classification {
+    /// Adds the word to the vocabulary
+    New,
+    /// Restores the word the user deleted
+    Reactivated,
+    /// Only records an encounter with a word the user has, or that a newer lookup adds
+    Existing,
+}
+
+/// The user's vocabulary, by normalized word
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KnownVocabulary {
+    pub active: BTreeSet<String>,
+    /// Soft-deleted words that aren't also active
+    pub deleted: BTreeSet<String>,
+}
+
+impl KnownVocabulary {
+    pub fn new(words: Vec<KnownWord>) -> Self {
+        let mut known = Self::default();
+        for word in words {
+            if word.deleted_at.is_some() {
+                known.deleted.insert(word.word);
+            } else {
+                known.active.insert(word.word);
+            }
+        }
+        known.deleted = &known.deleted - &known.active;
+        known
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewLookup {
+    #[serde(flatten)]
+    pub lookup: DeltaLookup,
+    /// The word as `parse-vocab` stores it
+    pub normalized: String,
+    pub classification: Classification,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewCounts {
+    pub new: usize,
+    pub reactivated: usize,
+    pub existing: usize,
+}
+
+/// Lookups an import would upload, newest first, and what each would do
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportPreview {
+    /// Number of lookups in vocab.db, including those already uploaded
+    pub total_lookups: usize,
+    pub lookups: Vec<PreviewLookup>,
+    pub books: Vec<Book>,
+    pub counts: PreviewCounts,
+}
+
+/// Normalizes a Kindle word like `parse-vocab`: drops a trailing `()`, trims, lowercases
+pub fn normalize_word(word: &str) -> String {
+    let mut word = word.trim_end();
+    if let Some(rest) = word.strip_suffix(')') {
+        if let Some(rest) = rest.trim_end().strip_suffix('(') {
+            word = rest;
+        }
+    }
+    word.trim().to_lowercase()
+}
+
+/// Classifies the lookups of `delta` against the user's vocabulary
+pub fn classify(delta: &VocabDelta, known: &KnownVocabulary) -> ImportPreview {
+    // parse-vocab skips lookups without a word
+    let mut lookups: Vec<&DeltaLookup> = delta
+        .lookups
+        .iter()
+        .filter(|l| !l.word.is_empty())
+        .collect();
+    // and goes through the rest newest first, those without a timestamp last
+    lookups.sort_by_key(|l| std::cmp::Reverse(l.timestamp));
+
+    let mut seen = BTreeSet::new();
+    let mut counts = PreviewCounts::default();
+    let lookups = lookups
+        .into_iter()
+        .map(|lookup| {
+            let normalized = normalize_word(&lookup.word);
+            let classification =
+                if known.active.contains(&normalized) || !seen.insert(normalized.clone()) {
+                    counts.existing += 1;
+                    Classification::Existing
+                } else if known.deleted.contains(&normalized) {
+                    counts.reactivated += 1;
+                    Classification::Reactivated
+                } else {
+                    counts.new += 1;
+                    Classification::New
+                };
+            PreviewLookup {
+                lookup: lookup.clone(),
+                normalized,
+                classification,
+            }
+        })
+        .collect();
+
+    ImportPreview {
+        total_lookups: delta.total_lookups,
+        lookups,
+        books: delta.books.clone(),
+        counts,
+    }
+}
+
+/// `delta` without the lookups the user deselected, and without books left with none
+pub fn deselect(delta: &VocabDelta, excluded: &BTreeSet<String>) -> VocabDelta {
+    let lookups: Vec<DeltaLookup> = delta
+        .lookups
+        .iter()
+        .filter(|l| !excluded.contains(&l.id))
+        .cloned()
+        .collect();
+    let book_keys: BTreeSet<&str> = lookups
+        .iter()
+        .filter_map(|l| l.book_key.as_deref())
+        .collect();
+    VocabDelta {
+        total_lookups: delta.total_lookups,
+        books: delta
+            .books
+            .iter()
+            .filter(|b| book_keys.contains(b.id.as_str()))
+            .cloned()
+            .collect(),
+        lookups,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(id: &str, word: &str, timestamp: Option<i64>, book_key: &str) -> DeltaLookup {
+        DeltaLookup {
+            id: id.to_string(),
+            word: word.to_string(),
+            stem: None,
+            lang: Some("en".to_string()),
+            usage: None,
+            timestamp,
+            book_key: Some(book_key.to_string()),
+        }
+    }
+
+    fn book(id: &str) -> Book {
+        Book {
+            id: id.to_string(),
+            asin: None,
+            guid: None,
+            lang: Some("en".to_string()),
+            title: Some(format!("Title of {}", id)),
+            authors: None,
+        }
+    }
+
+    fn known(active: &[&str], deleted: &[&str]) -> KnownVocabulary {
+        let word = |word: &&str, deleted_at: Option<&str>| KnownWord {
+            word: word.to_string(),
+            deleted_at: deleted_at.map(str::to_string),
+        };
+        KnownVocabulary::new(
+            active
+                .iter()
+                .map(|w| word(w, None))
+                .chain(
+                    deleted
+                        .iter()
+                        .map(|w| word(w, Some("2024-01-01T00:00:00Z"))),
+                )
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn normalizes_like_parse_vocab() {
+        assert_eq!(normalize_word("  Whale ( ) "), "whale");
+        assert_eq!(normalize_word("Leviathan()"), "leviathan");
+        assert_eq!(normalize_word("(sic)"), "(sic)");
+    }
+
+    #[test]
+    fn classifies_like_parse_vocab() {
+        let delta = VocabDelta {
+            total_lookups: 10,
+            lookups: vec![
+                lookup("a", "Whale", Some(1000), "moby"),
+                lookup("b", "whale()", Some(3000), "moby"),
+                lookup("c", "sea", Some(2000), "moby"),
+                lookup("d", "Harpoon", None, "moby"),
+                lookup("e", "spice", Some(4000), "dune"),
+                lookup("f", "", Some(5000), "dune"),
+            ],
+            books: vec![book("moby"), book("dune")],
+        };
+
+        let preview = classify(&delta, &known(&["sea", "spice"], &["harpoon", "spice"]));
+
+        let classified: Vec<_> = preview
+            .lookups
+            .iter()
+            .map(|l| {
+                (
+                    l.lookup.id.as_str(),
+                    l.normalized.as_str(),
+                    l.classification,
+                )
+            })
+            .collect();
+        assert_eq!(
+            classified,
+            vec![
+                ("e", "spice", Classification::Existing),
+                ("b", "whale", Classification::New),
+                ("c", "sea", Classification::Existing),
+                ("a", "whale", Classification::Existing),
+                ("d", "harpoon", Classification::Reactivated),
+            ]
+        );
+        assert_eq!(
+            preview.counts,
+            PreviewCounts {
+                new: 1,
+                reactivated: 1,
+                existing: 3,
+            }
+        );
+        assert_eq!(preview.total_lookups, 10);
+    }
+
+    #[test]
+    fn deselected_books_are_dropped_with_their_lookups() {
+        let delta = VocabDelta {
+            total_lookups: 3,
+            lookups: vec![
+                lookup("a", "whale", Some(1000), "moby"),
+                lookup("b", "sea", Some(2000), "moby"),
+                lookup("c", "spice", Some(3000), "dune"),
+            ],
+            books: vec![book("moby"), book("dune")],
+        };
+
+        let kept = deselect(&delta, &BTreeSet::from(["a".to_string(), "c".to_string()]));
+
+        let ids: Vec<_> = kept.lookups.iter().map(|l| l.id.as_str()).collect();
+        assert_eq!(ids, vec!["b"]);
+        assert_eq!(kept.books, vec![book("moby")]);
+        assert_eq!(kept.total_lookups, 3);
+    }
+}
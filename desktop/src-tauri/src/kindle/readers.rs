@@ -0,0 +1,295 @@
+//! Registry of every e-reader currently connected
+//!
+//! Mounted volumes are recognised by the files on them; Kindles that only speak
+//! MTP are found on the USB bus. Each reader reports what can be imported from
+//! it, so two Kindles, or a Kindle and a Kobo, can be attached at once.
+
+use crate::kindle::sync_state::SyncStateStore;
+use crate::kindle::{
+    identity_at, mounted_volumes, mtp, KindleError, KindleIdentity, CLIPPINGS, KOBO_DB,
+    KOREADER_VOCAB, VOCAB_DB_PATH,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Kind of e-reader, so the UI can name it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DeviceFamily {
+    Kindle,
+    Kobo,
+}
+
+impl DeviceFamily {
+    pub fn name(self) -> &'static str {
+        match self {
+            DeviceFamily::Kindle => "Kindle",
+            DeviceFamily::Kobo => "Kobo",
+        }
+    }
+}
+
+/// How the reader is attached
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Transport {
+    /// USB mass storage, mounted at `path`
+    Mounted { path: PathBuf },
+    /// MTP, at a USB bus number and address
+    Mtp { bus: u8, address: u8 },
+}
+
+impl Transport {
+    /// Id of a reader that has no serial number to go by
+    fn id(&self) -> String {
+        match self {
+            Transport::Mounted { path } => format!("mounted:{}", path.display()),
+            Transport::Mtp { bus, address } => format!("usb:{}-{}", bus, address),
+        }
+    }
+}
+
+/// Something that can be imported from a reader
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Source {
+    VocabDb,
+    Clippings,
+    KoreaderVocab,
+    KoboDb,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReaderStatus {
+    /// Serial number when known, otherwise derived from the transport
+    pub id: String,
+    pub family: DeviceFamily,
+    pub transport: Transport,
+    pub sources: Vec<Source>,
+    pub device: Option<KindleIdentity>,
+    /// When an import from this reader last completed (ms since the Unix epoch)
+    pub last_synced: Option<i64>,
+}
+
+impl ReaderStatus {
+    /// Model the device reported, or its family when it reported none
+    pub fn display_name(&self) -> String {
+        self.device
+            .as_ref()
+            .map(|identity| identity.device.model.clone())
+            .filter(|model| !model.is_empty())
+            .unwrap_or_else(|| self.family.name().to_string())
+    }
+
+    /// Key of the reader's sync state: its serial number, or none when the id only
+    /// names where it is attached, which another reader may be next time
+    pub fn sync_key(&self) -> Option<String> {
+        (self.id != self.transport.id()).then(|| self.id.clone())
+    }
+}
+
+/// Every reader attached right now, mounted ones first
+pub fn connected_readers() -> Vec<ReaderStatus> {
+    let usb = mtp::usb_kindles();
+    let mut readers: Vec<_> = mounted_volumes()
+        .iter()
+        .filter_map(|volume| mounted_reader(volume))
+        .collect();
+
+    // Mass-storage Kindles only name themselves on USB; with more than one there
+    // is no telling which volume belongs to which device
+    let mass_storage: Vec<_> = usb.iter().filter(|kindle| !kindle.mtp).collect();
+    let mut mounted_kindles = readers
+        .iter_mut()
+        .filter(|reader| reader.family == DeviceFamily::Kindle);
+    if let (Some(reader), None, [kindle]) = (
+        mounted_kindles.next(),
+        mounted_kindles.next(),
+        mass_storage.as_slice(),
+    ) {
+        if let Some(identity) = identity_at(kindle.location) {
+            if !identity.device.serial_number.is_empty() {
+                reader.id = identity.device.serial_number.clone();
+            }
+            reader.device = Some(identity);
+        }
+    }
+
+    for kindle in usb.iter().filter(|kindle| kindle.mtp) {
+        let (bus, address) = kindle.location;
+        let transport = Transport::Mtp { bus, address };
+        let device = identity_at(kindle.location);
+        let id = device
+            .as_ref()
+            .map(|identity| identity.device.serial_number.clone())
+            .filter(|serial| !serial.is_empty())
+            .unwrap_or_else(|| transport.id());
+        readers.push(ReaderStatus {
+            id,
+            family: DeviceFamily::Kindle,
+            transport,
+            // Every Kindle has both; looking for KOReader would need an MTP session,
+            // which on macOS means asking for admin rights on every status check
+            sources: vec![Source::VocabDb, Source::Clippings],
+            device,
+            last_synced: None,
+        });
+    }
+
+    readers
+}
+
+/// The connected reader with `id`, or the first one with a vocab.db when no id is given
+pub fn find_reader(id: Option<&str>) -> Result<ReaderStatus, KindleError> {
+    connected_readers()
+        .into_iter()
+        .find(|reader| match id {
+            Some(id) => reader.id == id,
+            None => reader.sources.contains(&Source::VocabDb),
+        })
+        .ok_or(KindleError::NoDevice)
+}
+
+/// Sets when each of `readers` was last imported from
+pub fn fill_last_synced(readers: &mut [ReaderStatus], store: &SyncStateStore) {
+    for reader in readers {
+        reader.last_synced = store.device(&reader.id).and_then(|state| state.last_synced);
+    }
+}
+
+/// Recognises a Kindle or Kobo from the files on a mounted volume
+fn mounted_reader(volume: &Path) -> Option<ReaderStatus> {
+    let has = |relative: &str| volume.join(relative).exists();
+
+    let mut sources = Vec::new();
+    if has(VOCAB_DB_PATH) {
+        sources.push(Source::VocabDb);
+    }
+    for (file, source) in [
+        (&CLIPPINGS, Source::Clippings),
+        (&KOREADER_VOCAB, Source::KoreaderVocab),
+        (&KOBO_DB, Source::KoboDb),
+    ] {
+        if file.mounted_paths.iter().any(|path| has(path)) {
+            sources.push(source);
+        }
+    }
+
+    let family = if has(".kobo") {
+        DeviceFamily::Kobo
+    } else if sources.contains(&Source::VocabDb) || sources.contains(&Source::Clippings) {
+        DeviceFamily::Kindle
+    } else {
+        return None;
+    };
+    let transport = Transport::Mounted {
+        path: volume.to_path_buf(),
+    };
+    let id = match family {
+        DeviceFamily::Kobo => kobo_serial(volume),
+        DeviceFamily::Kindle => None,
+    }
+    .unwrap_or_else(|| transport.id());
+
+    Some(ReaderStatus {
+        id,
+        family,
+        transport,
+        sources,
+        device: None,
+        last_synced: None,
+    })
+}
+
+/// Serial number from `.kobo/version`, the first of its comma-separated fields
+fn kobo_serial(volume: &Path) -> Option<String> {
+    let version = fs::read_to_string(volume.join(".kobo/version")).ok()?;
+    let serial = version.split(',').next()?.trim();
+    (!serial.is_empty()).then(|| serial.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kindle::sync_state::DeviceSyncState;
+
+    /// A volume holding `files`, deleted with the returned directory
+    fn volume(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for (path, content) in files {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn recognises_kindle_volumes_and_their_sources() {
+        let dir = volume(&[
+            ("system/vocabulary/vocab.db", ""),
+            ("documents/My Clippings.txt", ""),
+            ("koreader/settings/vocabulary_builder.sqlite3", ""),
+        ]);
+        let root = dir.path();
+        let reader = mounted_reader(root).unwrap();
+        assert_eq!(reader.family, DeviceFamily::Kindle);
+        assert_eq!(
+            reader.sources,
+            vec![Source::VocabDb, Source::Clippings, Source::KoreaderVocab]
+        );
+        assert_eq!(reader.id, format!("mounted:{}", root.display()));
+        assert_eq!(reader.sync_key(), None);
+        assert_eq!(
+            serde_json::to_value(&reader.transport).unwrap(),
+            serde_json::json!({ "type": "mounted", "path": root }),
+        );
+    }
+
+    #[test]
+    fn recognises_kobo_volumes_by_serial() {
+        let dir = volume(&[
+            (".kobo/KoboReader.sqlite", ""),
+            (".kobo/version", "N249110000001,4.1.15,4.38.21908,4.1.15\n"),
+            (".adds/koreader/settings/vocabulary_builder.sqlite3", ""),
+        ]);
+        let reader = mounted_reader(dir.path()).unwrap();
+        assert_eq!(reader.family, DeviceFamily::Kobo);
+        assert_eq!(reader.display_name(), "Kobo");
+        assert_eq!(reader.id, "N249110000001");
+        assert_eq!(reader.sync_key().as_deref(), Some("N249110000001"));
+        assert_eq!(reader.sources, vec![Source::KoreaderVocab, Source::KoboDb]);
+
+        let other = volume(&[("photos/cat.jpg", "")]);
+        assert!(mounted_reader(other.path()).is_none());
+    }
+
+    #[test]
+    fn fills_in_when_each_reader_was_last_synced() {
+        let reader = |id: &str| ReaderStatus {
+            id: id.to_string(),
+            family: DeviceFamily::Kindle,
+            transport: Transport::Mtp { bus: 1, address: 4 },
+            sources: vec![Source::VocabDb, Source::Clippings],
+            device: None,
+            last_synced: None,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SyncStateStore::load(&dir.path().join("sync-state.json"));
+        store.record(
+            "G000KA0000000001",
+            DeviceSyncState {
+                last_synced: Some(1_700_000_000_000),
+                ..Default::default()
+            },
+        );
+
+        let mut readers = vec![reader("G000KA0000000001"), reader("usb:1-4")];
+        fill_last_synced(&mut readers, &store);
+
+        assert_eq!(readers[0].last_synced, Some(1_700_000_000_000));
+        assert_eq!(readers[1].last_synced, None);
+        assert_eq!(readers[1].sync_key(), None);
+    }
+}
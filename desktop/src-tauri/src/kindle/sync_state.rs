@@ -0,0 +1,370 @@
+//! Per-device record of which vocab.db lookups were already uploaded
+//!
+//! Each Kindle (keyed by serial number) gets a watermark: the newest
+//! `LOOKUPS.timestamp` up to which everything was sent. Lookups at or past it,
+//! or without a timestamp, cannot be told apart by time alone, so the IDs of
+//! those sent are kept too. A lookup deselected in a preview holds the watermark
+//! back, so it is offered again while the ones sent after it are skipped.
+//! An import then only uploads what is newer, as a compact JSON delta for the
+//! `parse-vocab` edge function.
+
+use crate::kindle::vocab::{Book, Lookup, Vocab};
+use crate::kindle::KindleError;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceSyncState {
+    /// Newest `LOOKUPS.timestamp` uploaded so far (ms since the Unix epoch)
+    pub watermark: i64,
+    /// Uploaded lookups at or after `watermark`, or without a timestamp
+    pub sent_ids: BTreeSet<String>,
+    /// When the last upload from this device was confirmed (ms since the Unix epoch)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_synced: Option<i64>,
+}
+
+impl DeviceSyncState {
+    /// Whether an earlier upload already sent the lookup
+    fn covers(&self, id: &str, timestamp: Option<i64>) -> bool {
+        match timestamp {
+            Some(ts) if ts < self.watermark => true,
+            _ => self.sent_ids.contains(id),
+        }
+    }
+}
+
+/// Sync state of every Kindle seen on this machine, persisted as JSON
+#[derive(Debug)]
+pub struct SyncStateStore {
+    path: PathBuf,
+    devices: BTreeMap<String, DeviceSyncState>,
+}
+
+impl SyncStateStore {
+    /// Loads the store, starting empty when the file is missing or unreadable
+    ///
+    /// Losing the state only costs a full re-upload; the server skips words it has.
+    pub fn load(path: &Path) -> Self {
+        let devices = fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path: path.to_path_buf(),
+            devices,
+        }
+    }
+
+    pub fn device(&self, key: &str) -> Option<&DeviceSyncState> {
+        self.devices.get(key)
+    }
+
+    pub fn record(&mut self, key: &str, state: DeviceSyncState) {
+        self.devices.insert(key.to_string(), state);
+    }
+
+    pub fn save(&self) -> Result<(), KindleError> {
+        write_json(&self.path, &self.devices)
+    }
+}
+
+/// Writes `value` atomically so a crash never leaves half a file behind
+pub(crate) fn write_json(path: &Path, value: &impl serde::Serialize) -> Result<(), KindleError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_vec_pretty(value).map_err(|e| KindleError::Io(e.to_string()))?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// A lookup flattened with its word, as uploaded to `parse-vocab`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeltaLookup {
+    pub id: String,
+    pub word: String,
+    pub stem: Option<String>,
+    pub lang: Option<String>,
+    pub usage: Option<String>,
+    pub timestamp: Option<i64>,
+    pub book_key: Option<String>,
+}
+
+/// Lookups not yet uploaded from a Kindle, plus the books they reference
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VocabDelta {
+    /// Number of lookups in vocab.db, for reporting how much was skipped
+    pub total_lookups: usize,
+    pub lookups: Vec<DeltaLookup>,
+    pub books: Vec<Book>,
+}
+
+/// Picks the lookups newer than `previous` and the state to record once they are uploaded
+pub fn vocab_delta(vocab: &Vocab, previous: &DeviceSyncState) -> (VocabDelta, DeviceSyncState) {
+    let words: BTreeMap<&str, _> = vocab.words.iter().map(|w| (w.id.as_str(), w)).collect();
+
+    let mut lookups = Vec::new();
+    let mut book_keys = BTreeSet::new();
+    for lookup in &vocab.lookups {
+        if previous.covers(&lookup.id, lookup.timestamp) {
+            continue;
+        }
+        let Some(word) = words.get(lookup.word_key.as_str()) else {
+            continue;
+        };
+        if let Some(book_key) = &lookup.book_key {
+            book_keys.insert(book_key.as_str());
+        }
+        lookups.push(DeltaLookup {
+            id: lookup.id.clone(),
+            word: word.word.clone(),
+            stem: word.stem.clone(),
+            lang: word.lang.clone(),
+            usage: lookup.usage.clone(),
+            timestamp: lookup.timestamp,
+            book_key: lookup.book_key.clone(),
+        });
+    }
+
+    let watermark = lookups
+        .iter()
+        .filter_map(|l| l.timestamp)
+        .fold(previous.watermark, i64::max);
+    let sending: BTreeSet<&str> = lookups.iter().map(|l| l.id.as_str()).collect();
+    let sent_ids = vocab
+        .lookups
+        .iter()
+        .filter(|l| l.timestamp.is_none_or(|ts| ts >= watermark))
+        .filter(|l| previous.sent_ids.contains(&l.id) || sending.contains(l.id.as_str()))
+        .map(|l| l.id.clone())
+        .collect();
+
+    let delta = VocabDelta {
+        total_lookups: vocab.lookups.len(),
+        books: vocab
+            .books
+            .iter()
+            .filter(|b| book_keys.contains(b.id.as_str()))
+            .cloned()
+            .collect(),
+        lookups,
+    };
+    (
+        delta,
+        DeviceSyncState {
+            watermark,
+            sent_ids,
+            last_synced: previous.last_synced,
+        },
+    )
+}
+
+/// New lookups `vocab_delta` leaves out because vocab.db has no `WORDS` row for them
+///
+/// There is no word to upload, and once newer lookups are sent the watermark
+/// passes them, so they are reported as skipped rather than dropped silently.
+pub fn lookups_without_word(vocab: &Vocab, previous: &DeviceSyncState) -> usize {
+    let words: BTreeSet<&str> = vocab.words.iter().map(|w| w.id.as_str()).collect();
+    vocab
+        .lookups
+        .iter()
+        .filter(|l| !previous.covers(&l.id, l.timestamp))
+        .filter(|l| !words.contains(l.word_key.as_str()))
+        .count()
+}
+
+/// State to record when only the lookups in `sent` of a delta reached the server
+///
+/// The watermark stops short of the oldest lookup left unsent: one after an
+/// interrupted upload, or one deselected in a preview. Lookups sent after it are
+/// kept by ID. Lookups without a word are never sent and don't hold it back.
+pub fn sent_state(
+    vocab: &Vocab,
+    previous: &DeviceSyncState,
+    sent: &BTreeSet<String>,
+) -> DeviceSyncState {
+    let words: BTreeSet<&str> = vocab.words.iter().map(|w| w.id.as_str()).collect();
+    let uploaded = |l: &Lookup| sent.contains(&l.id) || previous.covers(&l.id, l.timestamp);
+    let unsent_from = vocab
+        .lookups
+        .iter()
+        .filter(|l| !uploaded(l) && words.contains(l.word_key.as_str()))
+        .filter_map(|l| l.timestamp)
+        .min();
+    let watermark = vocab
+        .lookups
+        .iter()
+        .filter(|l| uploaded(l))
+        .filter_map(|l| l.timestamp)
+        .filter(|&ts| unsent_from.is_none_or(|from| ts < from))
+        .fold(previous.watermark, i64::max);
+    let sent_ids = vocab
+        .lookups
+        .iter()
+        .filter(|l| uploaded(l) && l.timestamp.is_none_or(|ts| ts >= watermark))
+        .map(|l| l.id.clone())
+        .collect();
+    DeviceSyncState {
+        watermark,
+        sent_ids,
+        last_synced: previous.last_synced,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kindle::vocab::sample_vocab_db;
+
+    fn vocab(lookups: &[(&str, &str, &str, i64)]) -> Vocab {
+        Vocab::from_bytes(&sample_vocab_db(lookups)).unwrap()
+    }
+
+    #[test]
+    fn first_sync_sends_everything() {
+        let vocab = vocab(&[("whale", "whale", "A whale.", 1000), ("sea", "sea", "", 2000)]);
+        let (delta, state) = vocab_delta(&vocab, &DeviceSyncState::default());
+
+        assert_eq!(delta.lookups.len(), 2);
+        assert_eq!(delta.total_lookups, 2);
+        assert_eq!(delta.books.len(), 1);
+        assert_eq!(delta.books[0].authors.as_deref(), Some("Herman Melville"));
+        assert_eq!(state.watermark, 2000);
+    }
+
+    #[test]
+    fn resync_sends_only_new_lookups() {
+        let mut lookups: Vec<(String, i64)> =
+            (0..5000).map(|i| (format!("word{}", i), 1000 + i)).collect();
+        let before: Vec<(&str, &str, &str, i64)> = lookups
+            .iter()
+            .map(|(w, ts)| (w.as_str(), w.as_str(), "", *ts))
+            .collect();
+        let (_, state) = vocab_delta(&vocab(&before), &DeviceSyncState::default());
+
+        lookups.extend([
+            ("new1".to_string(), 9000),
+            ("new2".to_string(), 9001),
+            ("new3".to_string(), 9001),
+        ]);
+        let after: Vec<(&str, &str, &str, i64)> = lookups
+            .iter()
+            .map(|(w, ts)| (w.as_str(), w.as_str(), "", *ts))
+            .collect();
+        let (delta, next) = vocab_delta(&vocab(&after), &state);
+
+        let mut words: Vec<_> = delta.lookups.iter().map(|l| l.word.as_str()).collect();
+        words.sort_unstable();
+        assert_eq!(words, vec!["new1", "new2", "new3"]);
+        assert_eq!(delta.total_lookups, 5003);
+        assert_eq!(next.watermark, 9001);
+        assert_eq!(next.sent_ids.len(), 2);
+
+        let (again, _) = vocab_delta(&vocab(&after), &next);
+        assert!(again.lookups.is_empty());
+        assert!(again.books.is_empty());
+    }
+
+    #[test]
+    fn counts_lookups_without_a_word_as_skipped() {
+        let mut vocab = vocab(&[("whale", "whale", "", 1000), ("sea", "sea", "", 2000)]);
+        vocab.words.retain(|w| w.word != "whale");
+
+        let (delta, state) = vocab_delta(&vocab, &DeviceSyncState::default());
+        assert_eq!(delta.lookups.len(), 1);
+        assert_eq!(lookups_without_word(&vocab, &DeviceSyncState::default()), 1);
+        assert_eq!(state.watermark, 2000);
+        assert_eq!(lookups_without_word(&vocab, &state), 0);
+    }
+
+    #[test]
+    fn keeps_lookups_sharing_the_watermark_apart() {
+        let (_, state) = vocab_delta(
+            &vocab(&[("whale", "whale", "", 1000)]),
+            &DeviceSyncState::default(),
+        );
+        let (delta, _) = vocab_delta(
+            &vocab(&[("whale", "whale", "", 1000), ("sea", "sea", "", 1000)]),
+            &state,
+        );
+        assert_eq!(delta.lookups.len(), 1);
+        assert_eq!(delta.lookups[0].word, "sea");
+    }
+
+    #[test]
+    fn partial_upload_resumes_after_the_last_sent_lookup() {
+        let vocab = vocab(&[
+            ("whale", "whale", "", 1000),
+            ("sea", "sea", "", 2000),
+            ("ship", "ship", "", 3000),
+        ]);
+        let (delta, _) = vocab_delta(&vocab, &DeviceSyncState::default());
+        let sent = delta
+            .lookups
+            .iter()
+            .filter(|l| l.timestamp < Some(3000))
+            .map(|l| l.id.clone())
+            .collect();
+        let state = sent_state(&vocab, &DeviceSyncState::default(), &sent);
+        assert_eq!(state.watermark, 2000);
+
+        let (delta, _) = vocab_delta(&vocab, &state);
+        let words: Vec<_> = delta.lookups.iter().map(|l| l.word.as_str()).collect();
+        assert_eq!(words, vec!["ship"]);
+    }
+
+    #[test]
+    fn deselected_lookups_are_offered_again() {
+        let vocab = vocab(&[
+            ("whale", "whale", "", 1000),
+            ("sea", "sea", "", 2000),
+            ("ship", "ship", "", 3000),
+        ]);
+        let (delta, _) = vocab_delta(&vocab, &DeviceSyncState::default());
+        let id = |word: &str| {
+            delta
+                .lookups
+                .iter()
+                .find(|l| l.word == word)
+                .map(|l| l.id.clone())
+                .unwrap()
+        };
+        let sent = BTreeSet::from([id("whale"), id("ship")]);
+        let state = sent_state(&vocab, &DeviceSyncState::default(), &sent);
+        assert_eq!(state.watermark, 1000);
+
+        let (delta, _) = vocab_delta(&vocab, &state);
+        let words: Vec<_> = delta.lookups.iter().map(|l| l.word.as_str()).collect();
+        assert_eq!(words, vec!["sea"]);
+
+        let state = sent_state(&vocab, &state, &BTreeSet::from([id("sea")]));
+        assert_eq!(state.watermark, 3000);
+        assert_eq!(state.sent_ids, BTreeSet::from([id("ship")]));
+        assert!(vocab_delta(&vocab, &state).0.lookups.is_empty());
+    }
+
+    #[test]
+    fn store_round_trips_through_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sync-state.json");
+        let mut store = SyncStateStore::load(&path);
+        assert_eq!(store.device("G000TEST"), None);
+
+        let state = DeviceSyncState {
+            watermark: 42,
+            sent_ids: BTreeSet::from(["lookup1".to_string()]),
+            last_synced: Some(1_700_000_000_000),
+        };
+        store.record("G000TEST", state.clone());
+        store.save().unwrap();
+
+        assert_eq!(SyncStateStore::load(&path).device("G000TEST"), Some(&state));
+    }
+}
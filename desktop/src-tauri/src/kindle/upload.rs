@@ -0,0 +1,817 @@
+//! Uploads vocab.db deltas to the `parse-vocab` edge function
+//!
+//! The webview hands over its Supabase session and the upload runs here, next to
+//! the parsing. A delta over the edge function's 6 MB request limit goes out in
+//! batches, oldest lookups first, so the sync state can be recorded after every
+//! batch the server accepted. An expired access token is refreshed once. An import
+//! is not idempotent, so a request is only retried with backoff when it cannot
+//! have been processed: the connection was never made, or the server answered
+//! 429 or 503. The same client reads the user's vocabulary for previews.
+
+use crate::kindle::sync_state::{DeltaLookup, VocabDelta};
+use crate::kindle::vocab::Book;
+use crate::kindle::{CancelToken, KindleError};
+use std::time::Duration;
+
+/// Request body limit of Supabase edge functions
+const MAX_BODY_BYTES: usize = 6 * 1024 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Importing a full batch takes the edge function a while
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+/// Rows per `vocabulary` request, within PostgREST's default cap of 1000
+const VOCABULARY_PAGE_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// Project and session to upload with, as handed over by the frontend
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadAuth {
+    /// Supabase project URL
+    pub url: String,
+    /// Publishable key, sent as `apikey`
+    pub anon_key: String,
+    pub session: Session,
+    /// Language new words are translated into
+    #[serde(default)]
+    pub native_language_code: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Tries per request, including the first
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for each one after it
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 4,
+            base_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Totals over every batch, as reported by `parse-vocab`
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadSummary {
+    pub total_parsed: u64,
+    pub imported: u64,
+    pub skipped: u64,
+    pub encounters: u64,
+    pub errors: Vec<String>,
+    pub batches: usize,
+    /// `import_sessions` rows the batches created
+    pub session_ids: Vec<String>,
+}
+
+impl UploadSummary {
+    pub fn add(&mut self, other: UploadSummary) {
+        self.total_parsed += other.total_parsed;
+        self.imported += other.imported;
+        self.skipped += other.skipped;
+        self.encounters += other.encounters;
+        self.errors.extend(other.errors);
+        self.batches += other.batches;
+        self.session_ids.extend(other.session_ids);
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct ParseVocabResponse {
+    total_parsed: u64,
+    imported: u64,
+    skipped: u64,
+    encounters: u64,
+    errors: Option<Vec<String>>,
+    session_id: Option<String>,
+}
+
+/// A row of the user's `vocabulary` table
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct KnownWord {
+    /// Normalized as by `parse-vocab`
+    pub word: String,
+    /// Set when the word was soft-deleted
+    pub deleted_at: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: String,
+}
+
+/// Lookups and the books they reference, sent as one request
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Batch<'a> {
+    pub lookups: Vec<&'a DeltaLookup>,
+    pub books: Vec<&'a Book>,
+}
+
+#[derive(serde::Serialize)]
+struct RequestBody<'a, T> {
+    delta: &'a T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    native_language_code: Option<&'a str>,
+}
+
+pub struct UploadClient {
+    auth: UploadAuth,
+    agent: ureq::Agent,
+    retry: RetryPolicy,
+    max_body_bytes: usize,
+    can_refresh: bool,
+    refreshed: bool,
+}
+
+impl UploadClient {
+    pub fn new(auth: UploadAuth) -> Self {
+        Self {
+            auth,
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(CONNECT_TIMEOUT)
+                .timeout(REQUEST_TIMEOUT)
+                .build(),
+            retry: RetryPolicy::default(),
+            max_body_bytes: MAX_BODY_BYTES,
+            can_refresh: true,
+            refreshed: false,
+        }
+    }
+
+    /// The session after a refresh, which the webview must adopt: the old
+    /// refresh token is spent
+    pub fn refreshed_session(&self) -> Option<&Session> {
+        self.refreshed.then_some(&self.auth.session)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    /// Leaves the session as handed over, for uploads made without the webview
+    /// watching: a refresh would spend the refresh token it holds
+    pub fn without_refresh(mut self) -> Self {
+        self.can_refresh = false;
+        self
+    }
+
+    /// Uploads `delta`, calling `on_batch` with the lookups of each batch the
+    /// server accepted and its response
+    pub fn upload(
+        &mut self,
+        delta: &VocabDelta,
+        cancel: &CancelToken,
+        on_batch: &mut dyn FnMut(&[&DeltaLookup], &UploadSummary),
+    ) -> Result<UploadSummary, KindleError> {
+        let mut summary = UploadSummary::default();
+        for batch in self.batches(delta)? {
+            cancel.check()?;
+            let accepted = self.send(&batch)?;
+            on_batch(&batch.lookups, &accepted);
+            summary.add(accepted);
+        }
+        Ok(summary)
+    }
+
+    /// Splits `delta` into batches that fit in one request
+    pub fn batches<'a>(&self, delta: &'a VocabDelta) -> Result<Vec<Batch<'a>>, KindleError> {
+        let language = self.auth.native_language_code.as_deref();
+        let envelope = request_body(&Batch::default(), language)?.len();
+        Ok(batches(delta, self.max_body_bytes.saturating_sub(envelope)))
+    }
+
+    /// Uploads one batch, serialized as `{ lookups, books }`
+    pub fn send(&mut self, batch: &impl serde::Serialize) -> Result<UploadSummary, KindleError> {
+        let body = request_body(batch, self.auth.native_language_code.as_deref())?;
+        let response: ParseVocabResponse = parse_json(&self.call_parse_vocab(&body)?)?;
+        Ok(UploadSummary {
+            total_parsed: response.total_parsed,
+            imported: response.imported,
+            skipped: response.skipped,
+            encounters: response.encounters,
+            errors: response.errors.unwrap_or_default(),
+            batches: 1,
+            session_ids: response.session_id.into_iter().collect(),
+        })
+    }
+
+    /// Rows of the user's `vocabulary` table, soft-deleted ones included
+    pub fn known_words(&mut self) -> Result<Vec<KnownWord>, KindleError> {
+        let mut words = Vec::new();
+        // PostgREST caps every response, so page through the table
+        loop {
+            let url = format!(
+                "{}/rest/v1/vocabulary?select=word,deleted_at&order=id&limit={}&offset={}",
+                self.base_url(),
+                VOCABULARY_PAGE_SIZE,
+                words.len()
+            );
+            let page: Vec<KnownWord> = parse_json(&self.as_user(|client| client.get(&url))?)?;
+            let last = page.len() < VOCABULARY_PAGE_SIZE;
+            words.extend(page);
+            if last {
+                return Ok(words);
+            }
+        }
+    }
+
+    fn call_parse_vocab(&mut self, body: &[u8]) -> Result<String, KindleError> {
+        let url = format!("{}/functions/v1/parse-vocab", self.base_url());
+        self.as_user(|client| client.post(&url, true, body))
+    }
+
+    /// Sends `request` with the user's session, refreshing it once if it expired
+    fn as_user(
+        &mut self,
+        mut request: impl FnMut(&Self) -> Result<ureq::Response, Box<ureq::Error>>,
+    ) -> Result<String, KindleError> {
+        let result = match self.with_retries(&mut request) {
+            Err(KindleError::Server { status: 401, .. }) if self.can_refresh && !self.refreshed => {
+                self.refresh()?;
+                self.with_retries(request)
+            }
+            result => result,
+        };
+        // The session is refreshed at most once, so any 401 left means it is gone
+        result.map_err(|e| match e {
+            KindleError::Server { status: 401, .. } => KindleError::SessionExpired,
+            e => e,
+        })
+    }
+
+    /// Trades the refresh token for a new session
+    fn refresh(&mut self) -> Result<(), KindleError> {
+        let url = format!("{}/auth/v1/token?grant_type=refresh_token", self.base_url());
+        let body = serde_json::to_vec(&serde_json::json!({
+            "refresh_token": self.auth.session.refresh_token,
+        }))
+        .map_err(|e| KindleError::Io(e.to_string()))?;
+        let response = self
+            .with_retries(|client| client.post(&url, false, &body))
+            .map_err(|e| match e {
+                KindleError::Server {
+                    status: 400 | 401, ..
+                } => KindleError::SessionExpired,
+                e => e,
+            })?;
+        let tokens: TokenResponse = parse_json(&response)?;
+        self.auth.session = Session {
+            access_token: tokens.access_token,
+            refresh_token: tokens.refresh_token,
+        };
+        self.refreshed = true;
+        Ok(())
+    }
+
+    /// Sends `request` until it succeeds, fails in a way a retry could double, or
+    /// runs out of attempts, and returns the response body
+    fn with_retries(
+        &self,
+        mut request: impl FnMut(&Self) -> Result<ureq::Response, Box<ureq::Error>>,
+    ) -> Result<String, KindleError> {
+        let mut attempt = 1;
+        loop {
+            match response_body(request(self).map_err(|e| *e)) {
+                Err(e) if e.not_processed() && attempt < self.retry.attempts => {
+                    std::thread::sleep(self.retry.base_delay * 2u32.pow(attempt - 1));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// POSTs JSON, as the user when `authorized`
+    fn post(
+        &self,
+        url: &str,
+        authorized: bool,
+        body: &[u8],
+    ) -> Result<ureq::Response, Box<ureq::Error>> {
+        self.request("POST", url, authorized)
+            .set("Content-Type", "application/json")
+            .send_bytes(body)
+            .map_err(Box::new)
+    }
+
+    /// GETs `url` as the user
+    fn get(&self, url: &str) -> Result<ureq::Response, Box<ureq::Error>> {
+        self.request("GET", url, true).call().map_err(Box::new)
+    }
+
+    fn request(&self, method: &str, url: &str, authorized: bool) -> ureq::Request {
+        let request = self
+            .agent
+            .request(method, url)
+            .set("apikey", &self.auth.anon_key);
+        if !authorized {
+            return request;
+        }
+        request.set(
+            "Authorization",
+            &format!("Bearer {}", self.auth.session.access_token),
+        )
+    }
+
+    fn base_url(&self) -> &str {
+        self.auth.url.trim_end_matches('/')
+    }
+}
+
+/// Splits `delta` into batches whose lookups and books serialize to at most
+/// `max_bytes`, oldest lookups first
+pub fn batches(delta: &VocabDelta, max_bytes: usize) -> Vec<Batch<'_>> {
+    let mut lookups: Vec<_> = delta.lookups.iter().collect();
+    // Lookups without a timestamp are tracked by ID, so they can go anywhere
+    lookups.sort_by_key(|l| l.timestamp);
+
+    let mut batches = Vec::new();
+    let mut batch = Batch::default();
+    let mut size = 0;
+    for lookup in lookups {
+        let book = lookup
+            .book_key
+            .as_deref()
+            .and_then(|key| delta.books.iter().find(|b| b.id == key));
+        // Every batch carries the books its own lookups reference
+        let new_book = |batch: &Batch| book.filter(|b| !batch.books.iter().any(|s| s.id == b.id));
+        // Each element costs its JSON plus a separating comma
+        let added =
+            |batch: &Batch| json_len(lookup) + 1 + new_book(batch).map_or(0, |b| json_len(b) + 1);
+
+        if size + added(&batch) > max_bytes && !batch.lookups.is_empty() {
+            batches.push(std::mem::take(&mut batch));
+            size = 0;
+        }
+        size += added(&batch);
+        let book = new_book(&batch);
+        batch.books.extend(book);
+        batch.lookups.push(lookup);
+    }
+    if !batch.lookups.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+fn request_body(
+    batch: &impl serde::Serialize,
+    native_language_code: Option<&str>,
+) -> Result<Vec<u8>, KindleError> {
+    serde_json::to_vec(&RequestBody {
+        delta: batch,
+        native_language_code,
+    })
+    .map_err(|e| KindleError::Io(e.to_string()))
+}
+
+fn response_body(result: Result<ureq::Response, ureq::Error>) -> Result<String, KindleError> {
+    match result {
+        Ok(response) => response.into_string().map_err(KindleError::from),
+        Err(ureq::Error::Status(status, response)) => {
+            let text = response.into_string().unwrap_or_default();
+            Err(KindleError::Server {
+                status,
+                message: error_message(&text),
+            })
+        }
+        Err(ureq::Error::Transport(e)) => Err(match e.kind() {
+            // No connection was made, so the request never left
+            ureq::ErrorKind::Dns
+            | ureq::ErrorKind::ConnectionFailed
+            | ureq::ErrorKind::ProxyConnect => KindleError::Unreachable(e.to_string()),
+            _ => KindleError::Network(e.to_string()),
+        }),
+    }
+}
+
+fn json_len(value: &impl serde::Serialize) -> usize {
+    serde_json::to_vec(value)
+        .map(|json| json.len())
+        .unwrap_or(0)
+}
+
+fn parse_json<T: serde::de::DeserializeOwned>(text: &str) -> Result<T, KindleError> {
+    serde_json::from_str(text)
+        .map_err(|e| KindleError::InvalidResponse(format!("Unexpected server response: {}", e)))
+}
+
+/// The `error` (edge functions) or `error_description` (auth) field of an error body
+fn error_message(body: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|value| {
+            ["error_description", "error", "msg", "message"]
+                .iter()
+                .find_map(|key| value.get(key)?.as_str().map(str::to_string))
+        })
+        .unwrap_or_else(|| body.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Clone)]
+    struct Request {
+        path: String,
+        authorization: Option<String>,
+        body: serde_json::Value,
+    }
+
+    /// Local HTTP server answering each request with the next scripted response
+    struct Stub {
+        url: String,
+        requests: Arc<Mutex<Vec<Request>>>,
+    }
+
+    impl Stub {
+        fn start(responses: Vec<(u16, &str)>) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let recorded = requests.clone();
+            let responses: Vec<(u16, String)> = responses
+                .into_iter()
+                .map(|(s, b)| (s, b.to_string()))
+                .collect();
+            std::thread::spawn(move || {
+                for (status, body) in responses {
+                    let Ok((stream, _)) = listener.accept() else {
+                        return;
+                    };
+                    let mut reader = BufReader::new(stream);
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    let mut length = 0;
+                    let mut authorization = None;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        let line = line.trim_end();
+                        if line.is_empty() {
+                            break;
+                        }
+                        let (name, value) = line.split_once(':').unwrap();
+                        match name.to_ascii_lowercase().as_str() {
+                            "content-length" => length = value.trim().parse().unwrap(),
+                            "authorization" => authorization = Some(value.trim().to_string()),
+                            _ => {}
+                        }
+                    }
+                    let mut content = vec![0; length];
+                    reader.read_exact(&mut content).unwrap();
+                    recorded.lock().unwrap().push(Request {
+                        path: request_line.split(' ').nth(1).unwrap().to_string(),
+                        authorization,
+                        body: serde_json::from_slice(&content).unwrap_or_default(),
+                    });
+
+                    let mut stream = reader.into_inner();
+                    write!(
+                        stream,
+                        "HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    )
+                    .unwrap();
+                }
+            });
+            Self { url, requests }
+        }
+
+        fn requests(&self) -> Vec<Request> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    const IMPORTED: &str =
+        r#"{"totalParsed":1,"imported":1,"skipped":0,"encounters":1,"sessionId":"s1"}"#;
+
+    fn client(url: &str) -> UploadClient {
+        let mut client = UploadClient::new(UploadAuth {
+            url: url.to_string(),
+            anon_key: "anon".to_string(),
+            session: Session {
+                access_token: "old-access".to_string(),
+                refresh_token: "old-refresh".to_string(),
+            },
+            native_language_code: Some("de".to_string()),
+        });
+        client.retry.base_delay = Duration::from_millis(1);
+        client
+    }
+
+    fn lookup(id: &str, timestamp: i64, book_key: &str) -> DeltaLookup {
+        DeltaLookup {
+            id: id.to_string(),
+            word: id.to_string(),
+            stem: None,
+            lang: Some("en".to_string()),
+            usage: Some("x".repeat(100)),
+            timestamp: Some(timestamp),
+            book_key: Some(book_key.to_string()),
+        }
+    }
+
+    fn book(id: &str) -> Book {
+        Book {
+            id: id.to_string(),
+            asin: None,
+            guid: None,
+            lang: Some("en".to_string()),
+            title: Some(format!("Title of {}", id)),
+            authors: None,
+        }
+    }
+
+    fn delta(lookups: Vec<DeltaLookup>) -> VocabDelta {
+        VocabDelta {
+            total_lookups: lookups.len(),
+            lookups,
+            books: vec![book("moby"), book("dune")],
+        }
+    }
+
+    fn upload(
+        client: &mut UploadClient,
+        delta: &VocabDelta,
+    ) -> (Result<UploadSummary, KindleError>, Vec<Vec<String>>) {
+        let mut accepted = Vec::new();
+        let result = client.upload(delta, &CancelToken::new(), &mut |lookups, _| {
+            accepted.push(lookups.iter().map(|l| l.id.clone()).collect());
+        });
+        (result, accepted)
+    }
+
+    #[test]
+    fn batches_stay_under_the_limit_oldest_first() {
+        let delta = delta(
+            (0..10)
+                .rev()
+                .map(|i| {
+                    lookup(
+                        &format!("l{}", i),
+                        1000 + i,
+                        if i % 2 == 0 { "moby" } else { "dune" },
+                    )
+                })
+                .collect(),
+        );
+        let max = 3 * (json_len(&delta.lookups[0]) + 1) + 2 * (json_len(&delta.books[0]) + 1);
+        let batches = batches(&delta, max);
+
+        assert!(batches.len() > 1);
+        let mut order = Vec::new();
+        for batch in &batches {
+            assert!(json_len(batch) <= max + r#"{"lookups":[],"books":[]}"#.len());
+            for lookup in &batch.lookups {
+                let key = lookup.book_key.as_deref().unwrap();
+                assert_eq!(batch.books.iter().filter(|b| b.id == key).count(), 1);
+            }
+            order.extend(batch.lookups.iter().map(|l| l.timestamp.unwrap()));
+        }
+        assert_eq!(order, (1000..1010).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn oversized_delta_is_uploaded_in_batches() {
+        let stub = Stub::start(vec![(200, IMPORTED), (200, IMPORTED), (200, IMPORTED)]);
+        let mut client = client(&stub.url);
+        let delta = delta(vec![
+            lookup("a", 1, "moby"),
+            lookup("b", 2, "moby"),
+            lookup("c", 3, "dune"),
+        ]);
+        client.max_body_bytes = request_body(&batches(&delta, usize::MAX)[0], Some("de"))
+            .unwrap()
+            .len()
+            - 1;
+
+        let (result, accepted) = upload(&mut client, &delta);
+        let summary = result.unwrap();
+
+        assert!(summary.batches > 1);
+        assert_eq!(summary.imported, summary.batches as u64);
+        assert_eq!(summary.session_ids, vec!["s1"; summary.batches]);
+        assert_eq!(accepted.concat(), vec!["a", "b", "c"]);
+        let requests = stub.requests();
+        assert_eq!(requests.len(), summary.batches);
+        assert_eq!(requests[0].path, "/functions/v1/parse-vocab");
+        assert_eq!(
+            requests[0].authorization.as_deref(),
+            Some("Bearer old-access")
+        );
+        assert_eq!(requests[0].body["native_language_code"], "de");
+        assert_eq!(requests[0].body["delta"]["lookups"][0]["id"], "a");
+    }
+
+    #[test]
+    fn expired_token_is_refreshed_once() {
+        let stub = Stub::start(vec![
+            (401, r#"{"msg":"JWT expired"}"#),
+            (
+                200,
+                r#"{"access_token":"new-access","refresh_token":"new-refresh"}"#,
+            ),
+            (200, IMPORTED),
+        ]);
+        let mut client = client(&stub.url);
+
+        let (result, _) = upload(&mut client, &delta(vec![lookup("a", 1, "moby")]));
+        assert_eq!(result.unwrap().imported, 1);
+
+        let requests = stub.requests();
+        assert_eq!(requests[1].path, "/auth/v1/token?grant_type=refresh_token");
+        assert_eq!(requests[1].body["refresh_token"], "old-refresh");
+        assert_eq!(
+            requests[2].authorization.as_deref(),
+            Some("Bearer new-access")
+        );
+        assert_eq!(
+            client.refreshed_session().map(|s| s.refresh_token.as_str()),
+            Some("new-refresh")
+        );
+    }
+
+    #[test]
+    fn rejected_refresh_token_expires_the_session() {
+        let stub = Stub::start(vec![
+            (401, r#"{"msg":"JWT expired"}"#),
+            (400, r#"{"error_description":"Invalid Refresh Token"}"#),
+        ]);
+        let mut client = client(&stub.url);
+
+        let (result, accepted) = upload(&mut client, &delta(vec![lookup("a", 1, "moby")]));
+        assert_eq!(result, Err(KindleError::SessionExpired));
+        assert!(accepted.is_empty());
+    }
+
+    #[test]
+    fn expired_token_after_the_refresh_expires_the_session() {
+        let stub = Stub::start(vec![
+            (401, r#"{"msg":"JWT expired"}"#),
+            (
+                200,
+                r#"{"access_token":"new-access","refresh_token":"new-refresh"}"#,
+            ),
+            (200, IMPORTED),
+            (401, r#"{"msg":"JWT expired"}"#),
+        ]);
+        let mut client = client(&stub.url);
+        let delta = delta(vec![lookup("a", 1, "moby"), lookup("b", 2, "moby")]);
+        client.max_body_bytes = request_body(&batches(&delta, usize::MAX)[0], Some("de"))
+            .unwrap()
+            .len()
+            - 1;
+
+        let (result, accepted) = upload(&mut client, &delta);
+        assert_eq!(result, Err(KindleError::SessionExpired));
+        assert_eq!(accepted, vec![vec!["a".to_string()]]);
+        assert_eq!(stub.requests().len(), 4);
+    }
+
+    #[test]
+    fn refused_or_unavailable_requests_are_retried() {
+        let stub = Stub::start(vec![
+            (429, r#"{"error":"Too many requests"}"#),
+            (503, "Service Unavailable"),
+            (200, IMPORTED),
+        ]);
+        let mut client = client(&stub.url);
+
+        let (result, _) = upload(&mut client, &delta(vec![lookup("a", 1, "moby")]));
+        assert_eq!(result.unwrap().batches, 1);
+        assert_eq!(stub.requests().len(), 3);
+    }
+
+    #[test]
+    fn unreachable_server_is_reported_as_never_sent() {
+        // Nothing listens on a port that was just released
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut client = client(&format!("http://127.0.0.1:{}", port));
+
+        let (result, accepted) = upload(&mut client, &delta(vec![lookup("a", 1, "moby")]));
+        let error = result.unwrap_err();
+        assert!(matches!(error, KindleError::Unreachable(_)), "{:?}", error);
+        assert!(error.not_processed());
+        assert!(accepted.is_empty());
+    }
+
+    #[test]
+    fn gateway_errors_are_not_retried() {
+        // parse-vocab may have imported the batch before the gateway gave up
+        let stub = Stub::start(vec![(502, ""), (200, IMPORTED)]);
+        let mut client = client(&stub.url);
+
+        let (result, accepted) = upload(&mut client, &delta(vec![lookup("a", 1, "moby")]));
+        assert!(matches!(
+            result,
+            Err(KindleError::Server { status: 502, .. })
+        ));
+        assert!(!result.unwrap_err().not_processed());
+        assert!(accepted.is_empty());
+        assert_eq!(stub.requests().len(), 1);
+    }
+
+    #[test]
+    fn bad_request_is_not_retried() {
+        let stub = Stub::start(vec![(
+            400,
+            r#"{"error":"Invalid delta: lookup without word"}"#,
+        )]);
+        let mut client = client(&stub.url);
+
+        let (result, _) = upload(&mut client, &delta(vec![lookup("a", 1, "moby")]));
+        assert_eq!(
+            result,
+            Err(KindleError::Server {
+                status: 400,
+                message: "Invalid delta: lookup without word".to_string(),
+            })
+        );
+        assert_eq!(stub.requests().len(), 1);
+    }
+
+    #[test]
+    fn later_batches_are_not_sent_after_a_failure() {
+        let stub = Stub::start(vec![(200, IMPORTED), (400, r#"{"error":"boom"}"#)]);
+        let mut client = client(&stub.url);
+        let delta = delta(vec![
+            lookup("a", 1, "moby"),
+            lookup("b", 2, "moby"),
+            lookup("c", 3, "moby"),
+        ]);
+        client.max_body_bytes = request_body(&batches(&delta, usize::MAX)[0], Some("de"))
+            .unwrap()
+            .len()
+            - 1;
+
+        let (result, accepted) = upload(&mut client, &delta);
+        assert!(result.is_err());
+        assert_eq!(accepted.len(), 1);
+        assert_eq!(stub.requests().len(), 2);
+    }
+
+    #[test]
+    fn known_words_are_read_page_by_page() {
+        let full_page = serde_json::to_string(
+            &(0..VOCABULARY_PAGE_SIZE)
+                .map(|i| serde_json::json!({ "word": format!("w{}", i), "deleted_at": null }))
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let stub = Stub::start(vec![
+            (401, r#"{"msg":"JWT expired"}"#),
+            (
+                200,
+                r#"{"access_token":"new-access","refresh_token":"new-refresh"}"#,
+            ),
+            (200, &full_page),
+            (
+                200,
+                r#"[{"word":"gone","deleted_at":"2024-01-01T00:00:00Z"}]"#,
+            ),
+        ]);
+        let mut client = client(&stub.url);
+
+        let words = client.known_words().unwrap();
+        assert_eq!(words.len(), VOCABULARY_PAGE_SIZE + 1);
+        assert_eq!(
+            words.last(),
+            Some(&KnownWord {
+                word: "gone".to_string(),
+                deleted_at: Some("2024-01-01T00:00:00Z".to_string()),
+            })
+        );
+
+        let requests = stub.requests();
+        assert!(requests[2].path.ends_with("&limit=1000&offset=0"));
+        assert!(requests[3].path.ends_with("&limit=1000&offset=1000"));
+        assert_eq!(
+            requests[3].authorization.as_deref(),
+            Some("Bearer new-access")
+        );
+    }
+}
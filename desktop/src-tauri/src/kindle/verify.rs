@@ -0,0 +1,130 @@
+//! Integrity checks on a database downloaded from a reader before it counts as a
+//! successful sync
+//!
+//! The SQLite file header (https://www.sqlite.org/fileformat.html#the_database_header)
+//! rejects most files cut short by a failed transfer without opening them. What
+//! passes is then opened and run through `PRAGMA quick_check`, which catches a
+//! page-aligned truncation and damaged pages.
+
+use crate::kindle::vocab::with_temp_db;
+use crate::kindle::KindleError;
+use rusqlite::{Connection, OpenFlags};
+
+const SQLITE_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+const HEADER_LEN: usize = 100;
+
+/// Checks an in-memory copy of an SQLite database, like vocab.db or KoboReader.sqlite
+pub fn verify_sqlite(data: &[u8]) -> Result<(), KindleError> {
+    check_sqlite_header(&data[..data.len().min(HEADER_LEN)], data.len() as u64)?;
+    with_temp_db(data, quick_check)
+}
+
+fn quick_check(path: &std::path::Path) -> Result<(), KindleError> {
+    let corrupt = |e: rusqlite::Error| KindleError::CorruptDatabase(e.to_string());
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+    .map_err(corrupt)?;
+    let mut stmt = conn.prepare("PRAGMA quick_check").map_err(corrupt)?;
+    let problems = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+        .map_err(corrupt)?;
+    match problems.as_slice() {
+        [ok] if ok == "ok" => Ok(()),
+        _ => Err(KindleError::CorruptDatabase(problems.join("; "))),
+    }
+}
+
+/// Validates the 100-byte SQLite header against the total file length
+fn check_sqlite_header(header: &[u8], len: u64) -> Result<(), KindleError> {
+    let corrupt = |msg: String| Err(KindleError::CorruptDatabase(msg));
+
+    if header.len() < HEADER_LEN {
+        return corrupt(format!("file is only {} bytes", len));
+    }
+    if &header[..16] != SQLITE_MAGIC {
+        return corrupt("not an SQLite database".to_string());
+    }
+
+    let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+        1 => 65536,
+        n => n as u64,
+    };
+    if !page_size.is_power_of_two() || page_size < 512 {
+        return corrupt(format!("invalid page size {}", page_size));
+    }
+    if !len.is_multiple_of(page_size) {
+        return corrupt(format!(
+            "size {} is not a multiple of the {} byte page size",
+            len, page_size
+        ));
+    }
+
+    // The in-header page count is only trustworthy when written by SQLite 3.7.0+,
+    // which stamps the change counter into "version-valid-for"
+    let change_counter = u32::from_be_bytes([header[24], header[25], header[26], header[27]]);
+    let page_count = u32::from_be_bytes([header[28], header[29], header[30], header[31]]) as u64;
+    let valid_for = u32::from_be_bytes([header[92], header[93], header[94], header[95]]);
+    if page_count > 0 && valid_for == change_counter && len < page_count * page_size {
+        return corrupt(format!(
+            "expected {} pages, found {}",
+            page_count,
+            len / page_size
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kindle::vocab::sample_vocab_db;
+
+    fn sample() -> Vec<u8> {
+        let lookups: Vec<_> = (0..500)
+            .map(|i| ("whale", "whale", "Call me Ishmael.", 1_700_000_000_000 + i))
+            .collect();
+        sample_vocab_db(&lookups)
+    }
+
+    fn is_corrupt(result: Result<(), KindleError>) -> bool {
+        matches!(result, Err(KindleError::CorruptDatabase(_)))
+    }
+
+    #[test]
+    fn accepts_complete_database() {
+        assert_eq!(verify_sqlite(&sample()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_truncated_or_foreign_files() {
+        let data = sample();
+        let page_size = u16::from_be_bytes([data[16], data[17]]) as usize;
+        assert!(is_corrupt(verify_sqlite(&data[..data.len() - page_size])));
+        assert!(is_corrupt(verify_sqlite(&data[..page_size + 100])));
+        assert!(is_corrupt(verify_sqlite(&data[..50])));
+        assert!(is_corrupt(verify_sqlite(&[0u8; 1024])));
+    }
+
+    #[test]
+    fn rejects_page_aligned_truncation_without_a_page_count() {
+        let mut data = sample();
+        let page_size = u16::from_be_bytes([data[16], data[17]]) as usize;
+        // Written by an SQLite older than 3.7.0, the header can't vouch for the length
+        data[92..96].copy_from_slice(&0u32.to_be_bytes());
+        data.truncate(data.len() - page_size);
+        assert!(is_corrupt(verify_sqlite(&data)));
+    }
+
+    #[test]
+    fn rejects_damaged_pages() {
+        let mut data = sample();
+        let page_size = u16::from_be_bytes([data[16], data[17]]) as usize;
+        let last_page = data.len() - page_size;
+        data[last_page..].fill(0xAB);
+        assert!(is_corrupt(verify_sqlite(&data)));
+    }
+}
@@ -0,0 +1,299 @@
+//! Native reader for the Kindle Vocabulary Builder database (vocab.db)
+//!
+//! Mirrors the tables the `parse-vocab` edge function queries so imports can be
+//! previewed and validated on the desktop before anything is uploaded:
+//!
+//! - `WORDS`: one row per looked-up word (`id` is `lang:word`)
+//! - `LOOKUPS`: every lookup, with the sentence it appeared in
+//! - `BOOK_INFO`: books the lookups came from
+//! - `DICT_INFO`: dictionaries used for the lookups
+
+use crate::kindle::KindleError;
+use rusqlite::{Connection, OpenFlags, Row};
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Word {
+    pub id: String,
+    pub word: String,
+    pub stem: Option<String>,
+    pub lang: Option<String>,
+    /// 0 while learning, 100 once marked as mastered on the Kindle
+    pub category: i64,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Lookup {
+    pub id: String,
+    pub word_key: String,
+    pub book_key: Option<String>,
+    pub dict_key: Option<String>,
+    /// Sentence the word was looked up in
+    pub usage: Option<String>,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Book {
+    pub id: String,
+    pub asin: Option<String>,
+    pub guid: Option<String>,
+    pub lang: Option<String>,
+    pub title: Option<String>,
+    pub authors: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Dictionary {
+    pub id: String,
+    pub asin: Option<String>,
+    pub lang_in: Option<String>,
+    pub lang_out: Option<String>,
+}
+
+/// Contents of a vocab.db, read eagerly so the file can be released right away
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Vocab {
+    pub words: Vec<Word>,
+    pub lookups: Vec<Lookup>,
+    pub books: Vec<Book>,
+    pub dictionaries: Vec<Dictionary>,
+}
+
+impl Vocab {
+    /// Opens vocab.db read-only and loads all records
+    pub fn open(path: &Path) -> Result<Self, KindleError> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        Self::read(&conn)
+    }
+
+    /// Loads records from vocab.db bytes, e.g. as returned by `read_vocab_db_content`
+    pub fn from_bytes(data: &[u8]) -> Result<Self, KindleError> {
+        with_temp_db(data, Self::open)
+    }
+
+    fn read(conn: &Connection) -> Result<Self, KindleError> {
+        // DICT_INFO is missing on some older firmware
+        let dictionaries = if has_table(conn, "DICT_INFO")? {
+            query(
+                conn,
+                "SELECT id, asin, langin, langout FROM DICT_INFO",
+                |row| {
+                    Ok(Dictionary {
+                        id: row.get(0)?,
+                        asin: row.get(1)?,
+                        lang_in: row.get(2)?,
+                        lang_out: row.get(3)?,
+                    })
+                },
+            )?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            words: query(
+                conn,
+                "SELECT id, word, stem, lang, category, timestamp FROM WORDS",
+                |row| {
+                    Ok(Word {
+                        id: row.get(0)?,
+                        word: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                        stem: row.get(2)?,
+                        lang: row.get(3)?,
+                        category: row.get::<_, Option<i64>>(4)?.unwrap_or_default(),
+                        timestamp: timestamp(row, 5)?,
+                    })
+                },
+            )?,
+            lookups: query(
+                conn,
+                "SELECT id, word_key, book_key, dict_key, usage, timestamp FROM LOOKUPS \
+                 ORDER BY timestamp DESC",
+                |row| {
+                    Ok(Lookup {
+                        id: row.get(0)?,
+                        word_key: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                        book_key: row.get(2)?,
+                        dict_key: row.get(3)?,
+                        usage: row.get(4)?,
+                        timestamp: timestamp(row, 5)?,
+                    })
+                },
+            )?,
+            books: query(
+                conn,
+                "SELECT id, asin, guid, lang, title, authors FROM BOOK_INFO",
+                |row| {
+                    Ok(Book {
+                        id: row.get(0)?,
+                        asin: row.get(1)?,
+                        guid: row.get(2)?,
+                        lang: row.get(3)?,
+                        title: row.get(4)?,
+                        authors: row.get(5)?,
+                    })
+                },
+            )?,
+            dictionaries,
+        })
+    }
+}
+
+/// Opens SQLite bytes through a temporary file, since rusqlite can't read from memory
+///
+/// The file holds the user's lookups, so it is readable by the user only and
+/// removed when `open` returns, even by panicking.
+pub(crate) fn with_temp_db<T>(
+    data: &[u8],
+    open: impl FnOnce(&Path) -> Result<T, KindleError>,
+) -> Result<T, KindleError> {
+    let mut file = tempfile::Builder::new()
+        .prefix("mastery_vocab_")
+        .suffix(".db")
+        .tempfile()?;
+    file.write_all(data)?;
+    file.flush()?;
+    open(file.path())
+}
+
+pub(crate) fn query<T>(
+    conn: &Connection,
+    sql: &str,
+    map: impl FnMut(&Row<'_>) -> rusqlite::Result<T>,
+) -> Result<Vec<T>, KindleError> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map([], map)?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+pub(crate) fn has_table(conn: &Connection, name: &str) -> Result<bool, KindleError> {
+    let count: i64 = conn.query_row(
+        "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        [name],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Kindle writes 0 when it has no timestamp
+fn timestamp(row: &Row<'_>, index: usize) -> rusqlite::Result<Option<i64>> {
+    Ok(row.get::<_, Option<i64>>(index)?.filter(|ts| *ts > 0))
+}
+
+/// Builds an SQLite database from `schema` and returns its bytes
+#[cfg(test)]
+pub(crate) fn sqlite_bytes(schema: &str) -> Vec<u8> {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    Connection::open(file.path())
+        .unwrap()
+        .execute_batch(schema)
+        .unwrap();
+    std::fs::read(file.path()).unwrap()
+}
+
+/// Builds a vocab.db with the Kindle schema for tests
+#[cfg(test)]
+pub(crate) fn sample_vocab_db(lookups: &[(&str, &str, &str, i64)]) -> Vec<u8> {
+    let text = |value: &str| format!("'{}'", value.replace('\'', "''"));
+    let mut sql = String::from(
+        "CREATE TABLE WORDS (id TEXT PRIMARY KEY NOT NULL UNIQUE, word TEXT, stem TEXT, lang TEXT,
+             category INTEGER DEFAULT 0, timestamp INTEGER DEFAULT 0, profileid TEXT);
+         CREATE TABLE LOOKUPS (id TEXT PRIMARY KEY NOT NULL, word_key TEXT, book_key TEXT,
+             dict_key TEXT, pos TEXT, usage TEXT, timestamp INTEGER DEFAULT 0);
+         CREATE TABLE BOOK_INFO (id TEXT PRIMARY KEY NOT NULL, asin TEXT, guid TEXT, lang TEXT,
+             title TEXT, authors TEXT);
+         CREATE TABLE DICT_INFO (id TEXT PRIMARY KEY NOT NULL, asin TEXT, langin TEXT, langout TEXT);
+         INSERT INTO BOOK_INFO VALUES ('book1', 'B00ASIN001', 'guid1', 'en', 'Moby Dick',
+             'Herman Melville');
+         INSERT INTO DICT_INFO VALUES ('dict1', 'B00DICT001', 'en', 'en');
+         BEGIN;",
+    );
+    for (i, (word, stem, usage, timestamp)) in lookups.iter().enumerate() {
+        let word_key = text(&format!("en:{}", word));
+        sql.push_str(&format!(
+            "INSERT OR IGNORE INTO WORDS VALUES ({}, {}, {}, 'en', 0, {}, '');
+             INSERT INTO LOOKUPS VALUES ('lookup{}', {}, 'book1', 'dict1', '', {}, {});",
+            word_key,
+            text(word),
+            text(stem),
+            timestamp,
+            i,
+            word_key,
+            text(usage),
+            timestamp
+        ));
+    }
+    sql.push_str("COMMIT;");
+    sqlite_bytes(&sql)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_words_lookups_books_and_dictionaries() {
+        let data = sample_vocab_db(&[
+            (
+                "whale",
+                "whale",
+                "Call me Ishmael, said the whale.",
+                1_700_000_000_000,
+            ),
+            (
+                "harpooned",
+                "harpoon",
+                "He harpooned it.",
+                1_700_000_100_000,
+            ),
+            ("whale", "whale", "Another whale.", 0),
+        ]);
+        let vocab = Vocab::from_bytes(&data).unwrap();
+
+        assert_eq!(vocab.words.len(), 2);
+        let harpooned = vocab.words.iter().find(|w| w.id == "en:harpooned").unwrap();
+        assert_eq!(harpooned.stem.as_deref(), Some("harpoon"));
+        assert_eq!(harpooned.lang.as_deref(), Some("en"));
+
+        assert_eq!(vocab.lookups.len(), 3);
+        assert_eq!(vocab.lookups[0].usage.as_deref(), Some("He harpooned it."));
+        assert_eq!(vocab.lookups[0].timestamp, Some(1_700_000_100_000));
+        assert_eq!(vocab.lookups[2].timestamp, None);
+
+        assert_eq!(vocab.lookups[0].book_key.as_deref(), Some("book1"));
+        let book = &vocab.books[0];
+        assert_eq!(book.asin.as_deref(), Some("B00ASIN001"));
+        assert_eq!(book.authors.as_deref(), Some("Herman Melville"));
+
+        assert_eq!(
+            vocab.dictionaries,
+            vec![Dictionary {
+                id: "dict1".to_string(),
+                asin: Some("B00DICT001".to_string()),
+                lang_in: Some("en".to_string()),
+                lang_out: Some("en".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_files_without_the_kindle_schema() {
+        assert!(matches!(
+            Vocab::from_bytes(b"not a database at all, just some text"),
+            Err(KindleError::CorruptDatabase(_))
+        ));
+    }
+}
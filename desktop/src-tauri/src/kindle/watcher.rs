@@ -0,0 +1,190 @@
+//! Background monitoring for readers being plugged in or removed
+//!
+//! Two sources wake the watcher: libusb hotplug callbacks for Kindles on the bus,
+//! and a watch on the mount table for mass-storage readers, which appear some
+//! seconds after their USB device. Either one triggers a rescan of
+//! `connected_readers`, and the difference to the last scan is reported as
+//! `reader-connected` / `reader-disconnected` events.
+
+use crate::kindle::mtp;
+use crate::kindle::readers::{connected_readers, ReaderStatus};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// Devices need a moment after arriving to enumerate and mount
+const SETTLE_DELAY: Duration = Duration::from_millis(750);
+/// How often the mount table is compared with its last snapshot
+const MOUNT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Rescan interval where libusb has no hotplug support
+const USB_POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone)]
+pub enum ReaderEvent {
+    Connected(ReaderStatus),
+    Disconnected(ReaderStatus),
+}
+
+impl ReaderEvent {
+    /// Name of the Tauri event
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReaderEvent::Connected(_) => "reader-connected",
+            ReaderEvent::Disconnected(_) => "reader-disconnected",
+        }
+    }
+
+    pub fn into_reader(self) -> ReaderStatus {
+        match self {
+            ReaderEvent::Connected(reader) | ReaderEvent::Disconnected(reader) => reader,
+        }
+    }
+}
+
+/// Starts watching; readers attached at startup are taken as already known
+pub fn spawn(mut on_event: impl FnMut(ReaderEvent) + Send + 'static) {
+    let (tx, rx) = mpsc::channel();
+
+    let usb_tx = tx.clone();
+    let hotplug = mtp::watch_kindle_hotplug(move || {
+        let _ = usb_tx.send(());
+    });
+
+    thread::spawn(move || {
+        let mut snapshot = mount_table();
+        loop {
+            thread::sleep(MOUNT_POLL_INTERVAL);
+            let current = mount_table();
+            if current != snapshot {
+                snapshot = current;
+                if tx.send(()).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        let mut readers = connected_readers();
+        loop {
+            let woken = if hotplug {
+                rx.recv().is_ok()
+            } else {
+                rx.recv_timeout(USB_POLL_INTERVAL) != Err(RecvTimeoutError::Disconnected)
+            };
+            if !woken {
+                return;
+            }
+
+            // Coalesce the burst of changes a single plug-in causes
+            thread::sleep(SETTLE_DELAY);
+            while rx.try_recv().is_ok() {}
+
+            let current = connected_readers();
+            for event in diff_readers(&readers, &current) {
+                on_event(event);
+            }
+            readers = current;
+        }
+    });
+}
+
+/// Readers that appeared or left between two scans, matched by transport
+///
+/// The ID is no key: a mounted Kindle's may switch to its serial number once its
+/// USB identity can be read.
+pub fn diff_readers(previous: &[ReaderStatus], current: &[ReaderStatus]) -> Vec<ReaderEvent> {
+    let left = previous
+        .iter()
+        .filter(|reader| !current.iter().any(|r| r.transport == reader.transport))
+        .map(|reader| ReaderEvent::Disconnected(reader.clone()));
+    let arrived = current
+        .iter()
+        .filter(|reader| !previous.iter().any(|r| r.transport == reader.transport))
+        .map(|reader| ReaderEvent::Connected(reader.clone()));
+    left.chain(arrived).collect()
+}
+
+/// Cheap fingerprint of the mounted volumes, compared between polls
+fn mount_table() -> String {
+    #[cfg(target_os = "linux")]
+    {
+        std::fs::read_to_string("/proc/self/mountinfo").unwrap_or_default()
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        let mut names: Vec<_> = std::fs::read_dir("/Volumes")
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names.join("\n")
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kindle::readers::{DeviceFamily, Transport};
+
+    fn reader(id: &str, transport: Transport) -> ReaderStatus {
+        ReaderStatus {
+            id: id.to_string(),
+            family: DeviceFamily::Kindle,
+            transport,
+            sources: Vec::new(),
+            device: None,
+            last_synced: None,
+        }
+    }
+
+    #[test]
+    fn reports_readers_that_arrived_or_left() {
+        let mounted = reader(
+            "mounted:/media/user/Kindle",
+            Transport::Mounted {
+                path: "/media/user/Kindle".into(),
+            },
+        );
+        let mtp = reader("G000TEST", Transport::Mtp { bus: 1, address: 4 });
+        let kobo = reader(
+            "N249110000001",
+            Transport::Mounted {
+                path: "/media/user/KOBOeReader".into(),
+            },
+        );
+
+        let events = diff_readers(&[mounted.clone(), mtp.clone()], &[mtp, kobo]);
+        let summary: Vec<_> = events
+            .into_iter()
+            .map(|event| (event.name(), event.into_reader().id))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("reader-disconnected", mounted.id),
+                ("reader-connected", "N249110000001".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_readers_whose_id_was_resolved() {
+        let transport = Transport::Mounted {
+            path: "/Volumes/Kindle".into(),
+        };
+        let before = reader("mounted:/Volumes/Kindle", transport.clone());
+        let after = reader("G000TEST", transport);
+        assert!(diff_readers(&[before], &[after]).is_empty());
+    }
+}
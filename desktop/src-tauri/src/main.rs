@@ -1,31 +1,768 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod cli;
 mod kindle;
+mod tray;
 
-use kindle::{get_kindle_status, KindleStatus, read_vocab_db_content, handle_sync_vocab_cli};
-use tauri::Emitter;
+use kindle::auto_sync::{auto_sync_action, AutoSyncAction, AutoSyncSettings, SyncPolicy};
+use kindle::clippings::{dedupe_clippings, parse_clippings, Clipping};
+use kindle::history::{HistoryEntry, HistoryError, HistoryOrigin, ImportHistory, ImportStage};
+use kindle::kobo::KoboLibrary;
+use kindle::outbox::{Outbox, PendingBatch};
+use kindle::preview::{classify, deselect, ImportPreview, KnownVocabulary};
+use kindle::readers::{connected_readers, fill_last_synced, find_reader, ReaderStatus, Source};
+use kindle::sync_state::{
+    lookups_without_word, sent_state, vocab_delta, DeviceSyncState, SyncStateStore,
+};
+use kindle::upload::{Session, UploadAuth, UploadClient, UploadSummary};
+use kindle::vocab::Vocab;
+use kindle::watcher::ReaderEvent;
+use kindle::{
+    read_device_file, read_kobo_db_content, read_koreader_vocab_content, read_reader_vocab_db,
+    read_vocab_db_content, CancelToken, KindleError, CLIPPINGS,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{Emitter, Manager};
+use tray::SyncStatus;
+
+/// Session the agent uploads with when the webview is not driving the import
+#[derive(Default)]
+struct AgentAuth(Mutex<Option<UploadAuth>>);
+
+/// Held while uploading, so the outbox is never drained during an import
+#[derive(Default)]
+struct UploadLock(Mutex<()>);
+
+/// Wakes the thread that sends the outbox when a session arrives or a batch is queued
+#[derive(Default)]
+struct OutboxSignal {
+    woken: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl OutboxSignal {
+    fn wake(&self) {
+        *self.woken.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        self.condvar.notify_one();
+    }
+
+    /// Blocks until woken, or until `timeout` has passed when there is one
+    fn wait(&self, timeout: Option<Duration>) {
+        let woken = self.woken.lock().unwrap_or_else(|e| e.into_inner());
+        let mut woken = match timeout {
+            Some(timeout) => {
+                self.condvar
+                    .wait_timeout_while(woken, timeout, |woken| !*woken)
+                    .unwrap_or_else(|e| e.into_inner())
+                    .0
+            }
+            None => self
+                .condvar
+                .wait_while(woken, |woken| !*woken)
+                .unwrap_or_else(|e| e.into_inner()),
+        };
+        *woken = false;
+    }
+}
+
+/// When each reader was last synced or asked about on connect, so a reader whose
+/// sync fails is not tried again on every reconnect
+#[derive(Default)]
+struct AutoSyncAttempts(Mutex<BTreeMap<String, i64>>);
+
+/// vocab.db as read for the last preview, with the id of its reader, so the
+/// import that follows doesn't read it again
+#[derive(Default)]
+struct PreviewedVocab(Mutex<Option<(String, Vocab)>>);
+
+/// Entries returned by `get_import_history` unless asked for another number
+const IMPORT_HISTORY_LIMIT: usize = 50;
+
+/// How often the outbox is retried while it holds batches
+const OUTBOX_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+fn sync_state_path(app: &tauri::AppHandle) -> Result<PathBuf, KindleError> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("sync-state.json"))
+        .map_err(|e| KindleError::Io(e.to_string()))
+}
+
+fn outbox_path(app: &tauri::AppHandle) -> Result<PathBuf, KindleError> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("outbox.sqlite"))
+        .map_err(|e| KindleError::Io(e.to_string()))
+}
+
+fn import_history(app: &tauri::AppHandle) -> Result<ImportHistory, KindleError> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| ImportHistory::new(&dir.join("import-history.jsonl")))
+        .map_err(|e| KindleError::Io(e.to_string()))
+}
+
+fn auto_sync_path(app: &tauri::AppHandle) -> Result<PathBuf, KindleError> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("auto-sync.json"))
+        .map_err(|e| KindleError::Io(e.to_string()))
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Imports from a reader that was just plugged in, or offers to in the tray, if
+/// its policy allows
+fn request_auto_sync(app: &tauri::AppHandle, reader: ReaderStatus) {
+    let Ok(path) = auto_sync_path(app) else {
+        return;
+    };
+    let settings = AutoSyncSettings::load(&path);
+    let now = now_millis();
+    let action = {
+        let attempts = app.state::<AutoSyncAttempts>();
+        let mut last_attempts = attempts.0.lock().unwrap_or_else(|e| e.into_inner());
+        let last_attempt = last_attempts.get(&reader.id).copied();
+        let action = auto_sync_action(&settings, &reader, last_attempt, now);
+        if action.is_some() {
+            last_attempts.insert(reader.id.clone(), now);
+        }
+        action
+    };
+    match action {
+        Some(AutoSyncAction::Sync) => sync_in_background(app, reader),
+        Some(AutoSyncAction::Ask) => tray::ask_to_sync(app, reader),
+        None => {}
+    }
+}
+
+/// Imports from `reader` on a thread of its own, with the session the webview last
+/// handed over, so nothing waits on the window being open
+///
+/// Progress shows in the tray; `auto-synced` tells an open window to refresh.
+fn sync_in_background(app: &tauri::AppHandle, reader: ReaderStatus) {
+    let app = app.clone();
+    std::thread::spawn(move || {
+        let auth = app
+            .state::<AgentAuth>()
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let Some(auth) = auth else {
+            let message = KindleError::SessionExpired.to_string();
+            tray::set_sync_status(&app, SyncStatus::Failed { message });
+            return;
+        };
+        tray::set_sync_status(&app, SyncStatus::Syncing);
+        let cancel = CancelToken::new();
+        let result = import_recorded(&app, auth, Some(reader.id.as_str()), None, &cancel);
+        let status = match result {
+            Ok(import) => SyncStatus::Succeeded {
+                imported: import.summary.imported as u32,
+            },
+            Err(KindleError::Cancelled) => SyncStatus::Idle,
+            Err(e) => SyncStatus::Failed {
+                message: e.to_string(),
+            },
+        };
+        tray::set_sync_status(&app, status);
+        let _ = app.emit("auto-synced", &reader.id);
+    });
+}
 
 #[tauri::command]
-fn check_kindle_status() -> KindleStatus {
-    get_kindle_status()
+fn get_auto_sync_settings(app: tauri::AppHandle) -> Result<AutoSyncSettings, KindleError> {
+    Ok(AutoSyncSettings::load(&auto_sync_path(&app)?))
 }
 
+/// Sets whether `device_id` is imported from automatically when connected
 #[tauri::command]
-fn read_kindle_vocab_db() -> Result<Vec<u8>, String> {
-    read_vocab_db_content()
+fn set_auto_sync_policy(
+    app: tauri::AppHandle,
+    device_id: String,
+    policy: SyncPolicy,
+) -> Result<AutoSyncSettings, KindleError> {
+    let path = auto_sync_path(&app)?;
+    let mut settings = AutoSyncSettings::load(&path);
+    settings.devices.insert(device_id, policy);
+    settings.save(&path)?;
+    Ok(settings)
+}
+
+/// Sets how long after a sync a reconnected reader is left alone
+#[tauri::command]
+fn set_auto_sync_interval(
+    app: tauri::AppHandle,
+    minutes: u32,
+) -> Result<AutoSyncSettings, KindleError> {
+    let path = auto_sync_path(&app)?;
+    let mut settings = AutoSyncSettings::load(&path);
+    settings.min_interval_minutes = minutes;
+    settings.save(&path)?;
+    Ok(settings)
+}
+
+/// Pauses or resumes syncing on connect, from the tray menu
+fn set_auto_sync_paused(
+    app: &tauri::AppHandle,
+    paused: bool,
+) -> Result<AutoSyncSettings, KindleError> {
+    let path = auto_sync_path(app)?;
+    let mut settings = AutoSyncSettings::load(&path);
+    settings.paused = paused;
+    settings.save(&path)?;
+    Ok(settings)
+}
+
+/// Shows the state of an import in the tray
+#[tauri::command]
+fn report_sync_status(app: tauri::AppHandle, status: SyncStatus) {
+    tray::set_sync_status(&app, status);
+}
+
+/// Lists every connected reader with when it was last imported from
+#[tauri::command]
+fn check_kindle_status(app: tauri::AppHandle) -> Vec<ReaderStatus> {
+    let mut readers = connected_readers();
+    if let Ok(path) = sync_state_path(&app) {
+        fill_last_synced(&mut readers, &SyncStateStore::load(&path));
+    }
+    readers
+}
+
+/// Reads vocab.db as raw bytes, which reach the webview as an ArrayBuffer
+///
+/// A `Vec<u8>` would be serialized as a JSON array of numbers, three and a half
+/// times the size of a 5 MB vocab.db.
+#[tauri::command]
+async fn read_kindle_vocab_db(app: tauri::AppHandle) -> Result<tauri::ipc::Response, KindleError> {
+    let cancel = app.state::<CancelToken>().inner().clone();
+    cancel.reset();
+    tauri::async_runtime::spawn_blocking(move || {
+        read_vocab_db_content(&cancel, &mut |progress| {
+            let _ = app.emit("kindle-download-progress", progress);
+        })
+    })
+    .await
+    .map_err(|e| KindleError::Io(e.to_string()))?
+    .map(tauri::ipc::Response::new)
+}
+
+#[tauri::command]
+async fn parse_kindle_vocab(app: tauri::AppHandle) -> Result<Vocab, KindleError> {
+    let cancel = app.state::<CancelToken>().inner().clone();
+    cancel.reset();
+    tauri::async_runtime::spawn_blocking(move || {
+        let content = read_vocab_db_content(&cancel, &mut |progress| {
+            let _ = app.emit("kindle-download-progress", progress);
+        })?;
+        Vocab::from_bytes(&content)
+    })
+    .await
+    .map_err(|e| KindleError::Io(e.to_string()))?
+}
+
+/// Outcome of `sync_kindle_import`
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KindleImport {
+    /// Number of lookups in vocab.db
+    total_lookups: usize,
+    /// Lookups the server accepted during this import
+    sent: usize,
+    /// Lookups left in the outbox because the server could not be reached
+    queued: usize,
+    /// New lookups skipped because vocab.db has no word for them
+    without_word: usize,
+    summary: UploadSummary,
+}
+
+/// Reads vocab.db and uploads the lookups not yet sent from this Kindle to `parse-vocab`
+///
+/// `reader_id` names the reader to import from; without one, the first reader
+/// with a vocab.db is used. Its sync state is keyed by its serial number.
+///
+/// The sync state is recorded after every batch the server accepts, so an
+/// interrupted upload resumes where it stopped. When the server can't be
+/// reached, the rest goes to the outbox. A refreshed session is emitted as
+/// `supabase-session` for the webview to adopt. Every run, failed or not, is
+/// added to the local import history.
+///
+/// After a preview, `excluded_lookup_ids` holds the lookups the user deselected.
+/// They are only left out of this upload; the next import offers them again.
+#[tauri::command]
+async fn sync_kindle_import(
+    app: tauri::AppHandle,
+    auth: UploadAuth,
+    reader_id: Option<String>,
+    excluded_lookup_ids: Option<Vec<String>>,
+) -> Result<KindleImport, KindleError> {
+    let cancel = app.state::<CancelToken>().inner().clone();
+    cancel.reset();
+    *app.state::<AgentAuth>()
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner()) = Some(auth.clone());
+    tauri::async_runtime::spawn_blocking(move || {
+        let excluded = excluded_lookup_ids.map(BTreeSet::from_iter);
+        import_recorded(&app, auth, reader_id.as_deref(), excluded, &cancel)
+    })
+    .await
+    .map_err(|e| KindleError::Io(e.to_string()))?
+}
+
+/// Imports from the reader `reader_id` names, as `sync_kindle_import` does, and
+/// adds the run to the local import history
+fn import_recorded(
+    app: &tauri::AppHandle,
+    auth: UploadAuth,
+    reader_id: Option<&str>,
+    excluded: Option<BTreeSet<String>>,
+    cancel: &CancelToken,
+) -> Result<KindleImport, KindleError> {
+    let started = Instant::now();
+    let mut entry = HistoryEntry::new(HistoryOrigin::Import, vec![Source::VocabDb], now_millis());
+    let result = find_reader(reader_id)
+        .map_err(|e| (ImportStage::Read, e))
+        .and_then(|reader| import_kindle(app, auth, &reader, excluded, cancel, &mut entry));
+    entry.duration_ms = started.elapsed().as_millis() as u64;
+    if let Err((stage, e)) = &result {
+        entry.errors.insert(0, HistoryError::new(*stage, e));
+    }
+    let _ = import_history(app).map(|history| history.append(&entry));
+    result.map_err(|(_, e)| e)
+}
+
+fn import_kindle(
+    app: &tauri::AppHandle,
+    auth: UploadAuth,
+    reader: &ReaderStatus,
+    excluded: Option<BTreeSet<String>>,
+    cancel: &CancelToken,
+    entry: &mut HistoryEntry,
+) -> Result<KindleImport, (ImportStage, KindleError)> {
+    let at = |stage: ImportStage| move |e: KindleError| (stage, e);
+
+    // A preview's read serves only the import started from it, for the same reader
+    let previewed = app
+        .state::<PreviewedVocab>()
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+        .filter(|(id, _)| excluded.is_some() && *id == reader.id);
+    let vocab = match previewed {
+        Some((_, vocab)) => vocab,
+        None => read_vocab(app, reader, cancel)?,
+    };
+    // Without a serial there is nothing to key the state by, so send everything
+    let key = reader.sync_key();
+    entry.lookups_read = vocab.lookups.len();
+    entry.device_id = key.clone();
+
+    let uploading = app.state::<UploadLock>();
+    let _uploading = uploading.0.lock().unwrap_or_else(|e| e.into_inner());
+    let mut store = SyncStateStore::load(&sync_state_path(app).map_err(at(ImportStage::Record))?);
+    let previous = key
+        .as_deref()
+        .and_then(|key| store.device(key).cloned())
+        .unwrap_or_default();
+    let (delta, _) = vocab_delta(&vocab, &previous);
+    let excluded = excluded.unwrap_or_default();
+    let selected = deselect(&delta, &excluded);
+
+    let mut client = UploadClient::new(auth);
+    let mut sent = BTreeSet::new();
+    let mut summary = UploadSummary::default();
+    let result = client.upload(&selected, cancel, &mut |lookups, accepted| {
+        summary.add(accepted.clone());
+        sent.extend(lookups.iter().map(|lookup| lookup.id.clone()));
+        let Some(key) = key.as_deref() else {
+            return;
+        };
+        let state = sent_state(&vocab, &previous, &sent);
+        entry.move_watermark(previous.watermark, state.watermark);
+        store.record(key, state);
+        // A batch whose state is lost is sent again; the server skips words it has
+        let _ = store.save();
+    });
+    if let Some(session) = client.refreshed_session() {
+        let _ = app.emit("supabase-session", session);
+        adopt_session(app, session.clone());
+    }
+    let sent_count = sent.len();
+    entry.lookups_sent = sent_count;
+    entry.imported = summary.imported;
+    entry.skipped = summary.skipped;
+    entry.server_session_ids = summary.session_ids.clone();
+    entry.errors = summary
+        .errors
+        .iter()
+        .map(|message| HistoryError {
+            stage: ImportStage::Upload,
+            kind: "server".to_string(),
+            message: message.clone(),
+            code: None,
+        })
+        .collect();
+
+    let mut outbox = outbox_path(app)
+        .and_then(|path| Outbox::open(&path))
+        .map_err(at(ImportStage::Record))?;
+    let queued = match (result, key.as_deref()) {
+        (Ok(_), Some(key)) => {
+            // This delta held every lookup queued for the device
+            outbox
+                .discard_device(key)
+                .map_err(at(ImportStage::Record))?;
+            // Deselected lookups hold the watermark back, so they are offered again
+            let state = sent_state(&vocab, &previous, &sent);
+            entry.move_watermark(previous.watermark, state.watermark);
+            store.record(
+                key,
+                DeviceSyncState {
+                    last_synced: Some(now_millis()),
+                    ..state
+                },
+            );
+            store.save().map_err(at(ImportStage::Record))?;
+            0
+        }
+        (Ok(_), None) => 0,
+        // parse-vocab is not idempotent, so only batches it never received are queued,
+        // and only for readers whose sync state they can move
+        (Err(e), Some(device_id)) if e.not_processed() => {
+            let queued = outbox
+                .queue_unsent(device_id, &client, &vocab, &previous, &selected, &sent)
+                .map_err(at(ImportStage::Record))?;
+            entry.lookups_queued = queued;
+            // Not a failure of the import, but worth seeing in the history
+            entry
+                .errors
+                .insert(0, HistoryError::new(ImportStage::Upload, &e));
+            if let Ok(pending) = outbox.list() {
+                let _ = app.emit("pending-imports", pending);
+            }
+            app.state::<OutboxSignal>().wake();
+            queued
+        }
+        (Err(e), _) => return Err((ImportStage::Upload, e)),
+    };
+    Ok(KindleImport {
+        total_lookups: delta.total_lookups,
+        sent: sent_count,
+        queued,
+        without_word: lookups_without_word(&vocab, &previous),
+        summary,
+    })
+}
+
+fn read_vocab(
+    app: &tauri::AppHandle,
+    reader: &ReaderStatus,
+    cancel: &CancelToken,
+) -> Result<Vocab, (ImportStage, KindleError)> {
+    let content = read_reader_vocab_db(&reader.transport, cancel, &mut |progress| {
+        let _ = app.emit("kindle-download-progress", progress);
+    })
+    .map_err(|e| (ImportStage::Read, e))?;
+    Vocab::from_bytes(&content).map_err(|e| (ImportStage::Parse, e))
+}
+
+/// Reads vocab.db and classifies the lookups not yet sent against the user's
+/// vocabulary, as `parse-vocab` would
+///
+/// Nothing is uploaded or recorded. The vocab.db read is kept for the import the
+/// user starts from the preview, which names the same `reader_id`.
+#[tauri::command]
+async fn preview_kindle_import(
+    app: tauri::AppHandle,
+    auth: UploadAuth,
+    reader_id: Option<String>,
+) -> Result<ImportPreview, KindleError> {
+    let cancel = app.state::<CancelToken>().inner().clone();
+    cancel.reset();
+    tauri::async_runtime::spawn_blocking(move || -> Result<ImportPreview, KindleError> {
+        let reader = find_reader(reader_id.as_deref())?;
+        let vocab = read_vocab(&app, &reader, &cancel).map_err(|(_, e)| e)?;
+        let key = reader.sync_key();
+        let store = SyncStateStore::load(&sync_state_path(&app)?);
+        let previous = key
+            .as_deref()
+            .and_then(|key| store.device(key).cloned())
+            .unwrap_or_default();
+        let (delta, _) = vocab_delta(&vocab, &previous);
+
+        let mut client = UploadClient::new(auth);
+        let known = client.known_words();
+        if let Some(session) = client.refreshed_session() {
+            let _ = app.emit("supabase-session", session);
+            adopt_session(&app, session.clone());
+        }
+        let preview = classify(&delta, &KnownVocabulary::new(known?));
+        *app.state::<PreviewedVocab>()
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some((reader.id, vocab));
+        Ok(preview)
+    })
+    .await
+    .map_err(|e| KindleError::Io(e.to_string()))?
+}
+
+/// Sends the outbox with the session the webview last handed over, and returns
+/// the number of batches left to retry; none without a session, which is
+/// waited for instead
+///
+/// The session is not refreshed here: the webview refreshes its own and hands
+/// over the new one, and a refresh here would spend the token it holds.
+fn drain_outbox(app: &tauri::AppHandle) -> Result<usize, KindleError> {
+    let Some(auth) = app
+        .state::<AgentAuth>()
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+    else {
+        return Ok(0);
+    };
+    let uploading = app.state::<UploadLock>();
+    let _uploading = uploading.0.lock().unwrap_or_else(|e| e.into_inner());
+    let mut outbox = Outbox::open(&outbox_path(app)?)?;
+    if outbox.list()?.is_empty() {
+        return Ok(0);
+    }
+
+    let mut client = UploadClient::new(auth).without_refresh();
+    let mut store = SyncStateStore::load(&sync_state_path(app)?);
+    // One history entry per device whose batches were tried
+    let mut entries: BTreeMap<String, (Instant, HistoryEntry)> = BTreeMap::new();
+    let sent = outbox.drain(&mut |batch| {
+        let (started, entry) = entries.entry(batch.device_id.clone()).or_insert_with(|| {
+            let mut entry =
+                HistoryEntry::new(HistoryOrigin::Outbox, vec![Source::VocabDb], now_millis());
+            entry.id = format!("{}-{}", entry.id, batch.device_id);
+            entry.device_id = Some(batch.device_id.clone());
+            (Instant::now(), entry)
+        });
+        let result = send_pending(&mut client, &mut store, batch, entry);
+        entry.duration_ms = started.elapsed().as_millis() as u64;
+        if let Err(e) = &result {
+            entry
+                .errors
+                .insert(0, HistoryError::new(ImportStage::Upload, e));
+        }
+        result
+    })?;
+    let pending = outbox.list()?;
+    let _ = app.emit("pending-imports", &pending);
+
+    let imported: u64 = entries.values().map(|(_, entry)| entry.imported).sum();
+    if let Ok(history) = import_history(app) {
+        for (_, entry) in entries.values() {
+            let _ = history.append(entry);
+        }
+    }
+    if sent > 0 {
+        tray::set_sync_status(
+            app,
+            SyncStatus::Succeeded {
+                imported: imported as u32,
+            },
+        );
+    }
+    Ok(pending.len())
+}
+
+/// Uploads a batch from the outbox and records the sync state it moves the device to
+fn send_pending(
+    client: &mut UploadClient,
+    store: &mut SyncStateStore,
+    batch: &PendingBatch,
+    entry: &mut HistoryEntry,
+) -> Result<(), KindleError> {
+    let summary = client.send(&batch.payload)?;
+    entry.lookups_sent += batch.lookups;
+    entry.imported += summary.imported;
+    entry.skipped += summary.skipped;
+    entry.server_session_ids.extend(summary.session_ids);
+    entry
+        .errors
+        .extend(summary.errors.into_iter().map(|message| HistoryError {
+            stage: ImportStage::Upload,
+            kind: "server".to_string(),
+            message,
+            code: None,
+        }));
+    if !batch.device_id.is_empty() {
+        let from = store
+            .device(&batch.device_id)
+            .map_or(0, |state| state.watermark);
+        entry.move_watermark(from, batch.state.watermark);
+        store.record(
+            &batch.device_id,
+            DeviceSyncState {
+                last_synced: Some(now_millis()),
+                ..batch.state.clone()
+            },
+        );
+        store.save()?;
+    }
+    Ok(())
+}
+
+/// Lists the newest imports recorded on this machine, newest first
+#[tauri::command]
+fn get_import_history(
+    app: tauri::AppHandle,
+    limit: Option<usize>,
+) -> Result<Vec<HistoryEntry>, KindleError> {
+    Ok(import_history(&app)?.recent(limit.unwrap_or(IMPORT_HISTORY_LIMIT)))
+}
+
+fn adopt_session(app: &tauri::AppHandle, session: Session) {
+    if let Some(auth) = app
+        .state::<AgentAuth>()
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_mut()
+    {
+        auth.session = session;
+    }
+}
+
+/// Hands the agent the session to drain the outbox with, or `None` after sign-out
+#[tauri::command]
+fn set_agent_auth(app: tauri::AppHandle, auth: Option<UploadAuth>) {
+    let signed_in = auth.is_some();
+    *app.state::<AgentAuth>()
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner()) = auth;
+    if signed_in {
+        // A session arriving may be the first chance to send what was queued
+        app.state::<OutboxSignal>().wake();
+    }
+}
+
+/// Lists the import batches waiting to be uploaded
+#[tauri::command]
+fn list_pending_imports(app: tauri::AppHandle) -> Result<Vec<PendingBatch>, KindleError> {
+    Outbox::open(&outbox_path(&app)?)?.list()
+}
+
+/// Tries to upload the pending batches now and returns those still pending
+#[tauri::command]
+async fn retry_pending_imports(
+    app: tauri::AppHandle,
+    auth: UploadAuth,
+) -> Result<Vec<PendingBatch>, KindleError> {
+    *app.state::<AgentAuth>()
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner()) = Some(auth);
+    tauri::async_runtime::spawn_blocking(move || {
+        drain_outbox(&app)?;
+        Outbox::open(&outbox_path(&app)?)?.list()
+    })
+    .await
+    .map_err(|e| KindleError::Io(e.to_string()))?
+}
+
+/// Drops a pending batch; its lookups are not uploaded unless re-imported
+/// before a later batch of the device is sent
+#[tauri::command]
+fn discard_pending_import(
+    app: tauri::AppHandle,
+    id: i64,
+) -> Result<Vec<PendingBatch>, KindleError> {
+    let outbox = Outbox::open(&outbox_path(&app)?)?;
+    outbox.discard(id)?;
+    outbox.list()
+}
+
+/// Reads My Clippings.txt and returns its clippings without duplicates
+#[tauri::command]
+async fn parse_kindle_clippings(app: tauri::AppHandle) -> Result<Vec<Clipping>, KindleError> {
+    let cancel = app.state::<CancelToken>().inner().clone();
+    cancel.reset();
+    tauri::async_runtime::spawn_blocking(move || {
+        let content = read_device_file(&CLIPPINGS, &cancel, &mut |progress| {
+            let _ = app.emit("kindle-download-progress", progress);
+        })?;
+        Ok(dedupe_clippings(parse_clippings(&String::from_utf8_lossy(
+            &content,
+        ))))
+    })
+    .await
+    .map_err(|e| KindleError::Io(e.to_string()))?
+}
+
+/// Reads KOReader's vocabulary builder as the same records as vocab.db
+#[tauri::command]
+async fn parse_koreader_vocab(app: tauri::AppHandle) -> Result<Vocab, KindleError> {
+    let cancel = app.state::<CancelToken>().inner().clone();
+    cancel.reset();
+    tauri::async_runtime::spawn_blocking(move || {
+        let content = read_koreader_vocab_content(&cancel, &mut |progress| {
+            let _ = app.emit("kindle-download-progress", progress);
+        })?;
+        kindle::koreader::from_bytes(&content)
+    })
+    .await
+    .map_err(|e| KindleError::Io(e.to_string()))?
+}
+
+/// Reads a mounted Kobo's dictionary lookups and annotations
+#[tauri::command]
+async fn parse_kobo_library(app: tauri::AppHandle) -> Result<KoboLibrary, KindleError> {
+    let cancel = app.state::<CancelToken>().inner().clone();
+    cancel.reset();
+    tauri::async_runtime::spawn_blocking(move || {
+        let content = read_kobo_db_content(&cancel, &mut |progress| {
+            let _ = app.emit("kindle-download-progress", progress);
+        })?;
+        kindle::kobo::from_bytes(&content)
+    })
+    .await
+    .map_err(|e| KindleError::Io(e.to_string()))?
+}
+
+#[tauri::command]
+fn cancel_kindle_import(cancel: tauri::State<CancelToken>) {
+    cancel.cancel();
 }
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() >= 3 && args[1] == "--sync-vocab" {
-        handle_sync_vocab_cli(&args[2]);
-        return;
+    #[cfg(target_os = "macos")]
+    if args.len() >= 3 && args[1] == kindle::helper::HELPER_FLAG {
+        std::process::exit(kindle::helper::run(&args[2]));
+    }
+    if let Some(code) = cli::run(&args[1..]) {
+        std::process::exit(code);
     }
-    
+
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_deep_link::init())
+        .manage(CancelToken::new())
+        .manage(AgentAuth::default())
+        .manage(UploadLock::default())
+        .manage(PreviewedVocab::default())
+        .manage(OutboxSignal::default())
+        .manage(AutoSyncAttempts::default())
         .setup(|app| {
             #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
             {
@@ -42,12 +779,184 @@ fn main() {
                     }
                 });
             }
+
+            let handle = app.handle().clone();
+            kindle::watcher::spawn(move |event| {
+                let name = event.name();
+                let connected = matches!(event, ReaderEvent::Connected(_));
+                let mut reader = event.into_reader();
+                if let Ok(path) = sync_state_path(&handle) {
+                    let store = SyncStateStore::load(&path);
+                    fill_last_synced(std::slice::from_mut(&mut reader), &store);
+                }
+                let _ = handle.emit(name, &reader);
+                if connected {
+                    tray::reader_connected(&handle, &reader);
+                    request_auto_sync(&handle, reader);
+                } else {
+                    tray::reader_disconnected(&handle, &reader);
+                }
+            });
+
+            let settings = auto_sync_path(app.handle())
+                .map(|path| AutoSyncSettings::load(&path))
+                .unwrap_or_default();
+            tray::create(app.handle(), &settings)?;
+            // Whatever an offline import queued goes out once the server is reachable
+            // again. The thread sleeps until a session arrives or a batch is queued,
+            // and only polls while batches are left.
+            let handle = app.handle().clone();
+            std::thread::spawn(move || {
+                let mut retry = None;
+                loop {
+                    handle.state::<OutboxSignal>().wait(retry);
+                    retry = match drain_outbox(&handle) {
+                        Ok(0) => None,
+                        _ => Some(OUTBOX_RETRY_INTERVAL),
+                    };
+                }
+            });
+            // The watcher only reports changes, so seed the tray with what is attached now
+            let handle = app.handle().clone();
+            std::thread::spawn(move || {
+                for reader in connected_readers() {
+                    tray::reader_connected(&handle, &reader);
+                }
+            });
             Ok(())
         })
+        .on_window_event(|window, event| {
+            // Closing the window leaves the agent running in the tray
+            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+                api.prevent_close();
+                let _ = window.hide();
+            }
+        })
         .invoke_handler(tauri::generate_handler![
             check_kindle_status,
             read_kindle_vocab_db,
+            parse_kindle_vocab,
+            preview_kindle_import,
+            sync_kindle_import,
+            set_agent_auth,
+            list_pending_imports,
+            retry_pending_imports,
+            discard_pending_import,
+            get_import_history,
+            parse_kindle_clippings,
+            parse_koreader_vocab,
+            parse_kobo_library,
+            cancel_kindle_import,
+            get_auto_sync_settings,
+            set_auto_sync_policy,
+            set_auto_sync_interval,
+            report_sync_status,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // Clicking the Dock icon brings back a window closed to the tray
+            #[cfg(target_os = "macos")]
+            if let tauri::RunEvent::Reopen { .. } = event {
+                tray::show_main_window(app);
+            }
+            #[cfg(not(target_os = "macos"))]
+            let _ = (app, event);
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kindle::sync_state::{vocab_delta, DeviceSyncState};
+    use crate::kindle::vocab::{sample_vocab_db, Vocab};
+    use std::time::{Duration, Instant};
+    use tauri::ipc::{InvokeResponseBody, IpcResponse};
+
+    /// A vocab.db of about 5 MB: 22k lookups of typical sentence length
+    fn five_mb_vocab_db() -> Vec<u8> {
+        let words: Vec<_> = (0..4000).map(|i| format!("word{}", i)).collect();
+        let usage = "It was the best of times, it was the worst of times, it was the age of \
+                     wisdom, it was the age of foolishness, it was the epoch of belief.";
+        let lookups: Vec<_> = (0..22_000)
+            .map(|i| {
+                let word = words[i % words.len()].as_str();
+                (word, word, usage, 1_600_000_000_000 + i as i64 * 60_000)
+            })
+            .collect();
+        let data = sample_vocab_db(&lookups);
+        assert!(
+            data.len() >= 5_000_000,
+            "vocab.db is only {} bytes",
+            data.len()
+        );
+        data
+    }
+
+    fn body_len(response: impl IpcResponse) -> usize {
+        match response.body().unwrap() {
+            InvokeResponseBody::Raw(bytes) => bytes.len(),
+            InvokeResponseBody::Json(json) => json.len(),
+        }
+    }
+
+    /// Median time of five runs of `run`, with the body size it returned
+    fn time_body(mut run: impl FnMut() -> usize) -> (Duration, usize) {
+        let mut times = Vec::new();
+        let mut len = 0;
+        for _ in 0..5 {
+            let started = Instant::now();
+            len = run();
+            times.push(started.elapsed());
+        }
+        times.sort();
+        (times[2], len)
+    }
+
+    /// What `read_kindle_vocab_db` puts on the IPC channel for a 5 MB vocab.db,
+    /// against returning it as a `Vec<u8>`
+    #[test]
+    fn raw_response_carries_vocab_db_as_is() {
+        let data = five_mb_vocab_db();
+        let raw = match tauri::ipc::Response::new(data.clone()).body().unwrap() {
+            InvokeResponseBody::Raw(bytes) => bytes,
+            InvokeResponseBody::Json(_) => panic!("vocab.db went out as JSON"),
+        };
+        let numbers = match data.clone().body().unwrap() {
+            InvokeResponseBody::Json(json) => json,
+            InvokeResponseBody::Raw(_) => panic!("a Vec<u8> went out raw"),
+        };
+        assert_eq!(raw, data);
+        // As a JSON array of numbers it would cost over three times as much
+        assert!(
+            numbers.len() > 3 * data.len(),
+            "{} bytes as JSON",
+            numbers.len()
+        );
+    }
+
+    /// Times the bodies the webview could be sent for a 5 MB vocab.db: the raw
+    /// response `read_kindle_vocab_db` returns, the `Vec<u8>` it used to return,
+    /// and the parsed import payload as JSON.
+    /// Run with `cargo test --release ipc_payload_timings -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn ipc_payload_timings() {
+        let data = five_mb_vocab_db();
+        let raw = time_body(|| body_len(tauri::ipc::Response::new(data.clone())));
+        let numbers = time_body(|| body_len(data.clone()));
+        let parsed = time_body(|| {
+            let vocab = Vocab::from_bytes(&data).unwrap();
+            let (delta, _) = vocab_delta(&vocab, &DeviceSyncState::default());
+            body_len(delta)
+        });
+        for (body, (time, len)) in [
+            ("raw response", raw),
+            ("Vec<u8> as JSON", numbers),
+            ("parsed delta as JSON", parsed),
+        ] {
+            println!("{:<21} {:>9} bytes in {:?}", body, len, time);
+        }
+        // Raw bytes are handed over as they are, without a pass to encode them
+        assert!(raw.0 < numbers.0);
+    }
 }
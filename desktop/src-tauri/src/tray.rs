@@ -0,0 +1,222 @@
+//! System tray icon, which keeps the agent reachable while its window is hidden
+//!
+//! The menu's first line shows what the agent is doing: idle, which reader is
+//! connected, syncing, or how the last sync went. A reader whose auto-sync policy
+//! is `ask` waits here for the user: Sync now names it and imports from it.
+//! Closing the window only hides it; Quit in this menu is what ends the app.
+
+use crate::kindle::auto_sync::AutoSyncSettings;
+use crate::kindle::readers::{ReaderStatus, Transport};
+use std::sync::Mutex;
+use tauri::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tauri::tray::{MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent};
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Outcome of the latest import, reported by the frontend
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(tag = "state", rename_all = "camelCase")]
+pub enum SyncStatus {
+    #[default]
+    Idle,
+    Syncing,
+    Succeeded {
+        imported: u32,
+    },
+    Failed {
+        message: String,
+    },
+}
+
+#[derive(Default)]
+struct TrayState {
+    /// Transport and display name of each connected reader; the transport stays
+    /// put while the ID may change once the reader's serial number is read
+    readers: Vec<(Transport, String)>,
+    sync: SyncStatus,
+    /// Reader connected with the `ask` policy, until the user syncs it or unplugs it
+    asking: Option<ReaderStatus>,
+}
+
+impl TrayState {
+    fn label(&self) -> String {
+        let readers = match self.readers.as_slice() {
+            [] => None,
+            [(_, name)] => Some(format!("{} connected", name)),
+            readers => Some(format!("{} readers connected", readers.len())),
+        };
+        let last_sync = match &self.sync {
+            SyncStatus::Idle => None,
+            SyncStatus::Syncing => return "Syncing…".to_string(),
+            SyncStatus::Succeeded { imported: 1 } => Some("last sync: 1 new word".to_string()),
+            SyncStatus::Succeeded { imported } => {
+                Some(format!("last sync: {} new words", imported))
+            }
+            SyncStatus::Failed { .. } => Some("last sync failed".to_string()),
+        };
+        match (readers, last_sync) {
+            (Some(readers), Some(last_sync)) => format!("{}, {}", readers, last_sync),
+            (Some(readers), None) => readers,
+            (None, Some(last_sync)) => capitalize(&last_sync),
+            (None, None) => "Idle".to_string(),
+        }
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+pub struct Tray {
+    icon: TrayIcon,
+    status: MenuItem<tauri::Wry>,
+    sync_now: MenuItem<tauri::Wry>,
+    state: Mutex<TrayState>,
+}
+
+/// Adds the tray icon; `settings` decides whether Pause auto-sync starts checked
+pub fn create(app: &AppHandle, settings: &AutoSyncSettings) -> tauri::Result<()> {
+    let status = MenuItem::with_id(app, "status", "Idle", false, None::<&str>)?;
+    let sync_now = MenuItem::with_id(app, "sync-now", "Sync now", true, None::<&str>)?;
+    let open = MenuItem::with_id(app, "open", "Open Mastery", true, None::<&str>)?;
+    let pause = CheckMenuItem::with_id(
+        app,
+        "pause-auto-sync",
+        "Pause auto-sync",
+        true,
+        settings.paused,
+        None::<&str>,
+    )?;
+    let quit = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
+    let menu = Menu::with_items(
+        app,
+        &[
+            &status,
+            &PredefinedMenuItem::separator(app)?,
+            &sync_now,
+            &open,
+            &pause,
+            &PredefinedMenuItem::separator(app)?,
+            &quit,
+        ],
+    )?;
+
+    let mut builder = TrayIconBuilder::with_id("main")
+        .menu(&menu)
+        .tooltip("Mastery")
+        .show_menu_on_left_click(false)
+        .on_menu_event(move |app, event| on_menu_event(app, event, &pause))
+        .on_tray_icon_event(|icon, event| {
+            if let TrayIconEvent::Click {
+                button: MouseButton::Left,
+                button_state: MouseButtonState::Up,
+                ..
+            } = event
+            {
+                show_main_window(icon.app_handle());
+            }
+        });
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    let icon = builder.build(app)?;
+
+    app.manage(Tray {
+        icon,
+        status,
+        sync_now,
+        state: Mutex::new(TrayState::default()),
+    });
+    Ok(())
+}
+
+fn on_menu_event(app: &AppHandle, event: MenuEvent, pause: &CheckMenuItem<tauri::Wry>) {
+    match event.id().as_ref() {
+        "sync-now" => {
+            let mut asking = None;
+            update(app, |state| asking = state.asking.take());
+            match asking {
+                Some(reader) => crate::sync_in_background(app, reader),
+                None => {
+                    let _ = app.emit("sync-now", ());
+                }
+            }
+        }
+        "open" => show_main_window(app),
+        "pause-auto-sync" => {
+            let paused = pause.is_checked().unwrap_or_default();
+            if let Err(e) = crate::set_auto_sync_paused(app, paused) {
+                eprintln!("[tray] Failed to save auto-sync setting: {}", e);
+            }
+        }
+        "quit" => app.exit(0),
+        _ => {}
+    }
+}
+
+/// Brings back the window after it was closed to the tray
+pub fn show_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+}
+
+pub fn reader_connected(app: &AppHandle, reader: &ReaderStatus) {
+    update(app, |state| {
+        state
+            .readers
+            .retain(|(transport, _)| *transport != reader.transport);
+        state
+            .readers
+            .push((reader.transport.clone(), reader.display_name()));
+    });
+}
+
+pub fn reader_disconnected(app: &AppHandle, reader: &ReaderStatus) {
+    update(app, |state| {
+        state
+            .readers
+            .retain(|(transport, _)| *transport != reader.transport);
+        if state
+            .asking
+            .as_ref()
+            .is_some_and(|asking| asking.transport == reader.transport)
+        {
+            state.asking = None;
+        }
+    });
+}
+
+/// Offers to sync `reader` from the menu, for readers whose policy is `ask`
+pub fn ask_to_sync(app: &AppHandle, reader: ReaderStatus) {
+    update(app, |state| state.asking = Some(reader));
+}
+
+pub fn set_sync_status(app: &AppHandle, status: SyncStatus) {
+    update(app, |state| state.sync = status);
+}
+
+fn update(app: &AppHandle, change: impl FnOnce(&mut TrayState)) {
+    let Some(tray) = app.try_state::<Tray>() else {
+        return;
+    };
+    let mut state = tray.state.lock().unwrap_or_else(|e| e.into_inner());
+    change(&mut state);
+
+    let label = state.label();
+    let tooltip = match &state.sync {
+        SyncStatus::Failed { message } => format!("Mastery: {} ({})", label, message),
+        _ => format!("Mastery: {}", label),
+    };
+    let _ = tray.status.set_text(&label);
+    let _ = tray.sync_now.set_text(match &state.asking {
+        Some(reader) => format!("Sync {} now", reader.display_name()),
+        None => "Sync now".to_string(),
+    });
+    let _ = tray.icon.set_tooltip(Some(tooltip));
+}
@@ -0,0 +1,54 @@
+import { describe, it, expect, vi, beforeEach } from 'vitest';
+import { mockIPC, clearMocks } from '@tauri-apps/api/mocks';
+import { getAutoSyncSettings, onAutoSynced, setAutoSyncPolicy, type AutoSyncSettings } from './autoSync';
+
+const eventHandlers = vi.hoisted(() => new Map<string, (event: { payload: unknown }) => void>());
+vi.mock('@tauri-apps/api/event', () => ({
+  listen: vi.fn(async (name: string, handler: (event: { payload: unknown }) => void) => {
+    eventHandlers.set(name, handler);
+    return () => eventHandlers.delete(name);
+  })
+}));
+
+beforeEach(() => {
+  clearMocks();
+  eventHandlers.clear();
+});
+
+describe('auto-sync settings', () => {
+  it('reads the saved settings', async () => {
+    const settings: AutoSyncSettings = { devices: { G000TEST: 'always' }, minIntervalMinutes: 60, paused: false };
+    mockIPC((cmd) => {
+      if (cmd === 'get_auto_sync_settings') return settings;
+    });
+
+    expect(await getAutoSyncSettings()).toEqual(settings);
+  });
+
+  it('sends the policy for one device', async () => {
+    let sent: unknown;
+    mockIPC((cmd, args) => {
+      if (cmd === 'set_auto_sync_policy') {
+        sent = args;
+        return { devices: { G000TEST: 'never' }, minIntervalMinutes: 60, paused: false };
+      }
+    });
+
+    const settings = await setAutoSyncPolicy('G000TEST', 'never');
+    expect(sent).toEqual({ deviceId: 'G000TEST', policy: 'never' });
+    expect(settings.devices.G000TEST).toBe('never');
+  });
+});
+
+describe('background imports', () => {
+  it('reports the reader the agent imported from', async () => {
+    const synced: string[] = [];
+    const unlisten = await onAutoSynced((readerId) => synced.push(readerId));
+
+    eventHandlers.get('auto-synced')?.({ payload: 'G000TEST' });
+    expect(synced).toEqual(['G000TEST']);
+
+    unlisten();
+    expect(eventHandlers.has('auto-synced')).toBe(false);
+  });
+});
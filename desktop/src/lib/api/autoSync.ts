@@ -0,0 +1,64 @@
+/**
+ * Automatic import when a known reader is plugged in
+ */
+
+import { invoke } from '@tauri-apps/api/core';
+import { listen, type UnlistenFn } from '@tauri-apps/api/event';
+import { toKindleError } from './kindle';
+
+export type SyncPolicy = 'always' | 'ask' | 'never';
+
+export interface AutoSyncSettings {
+  /** Policy by reader ID; readers without one are asked */
+  devices: Record<string, SyncPolicy>;
+  /** A reader synced more recently than this is not synced again on connect */
+  minIntervalMinutes: number;
+  /** Set from the tray menu; no reader is synced on connect while paused */
+  paused: boolean;
+}
+
+export async function getAutoSyncSettings(): Promise<AutoSyncSettings> {
+  return invoke<AutoSyncSettings>('get_auto_sync_settings').catch((e) => {
+    throw toKindleError(e);
+  });
+}
+
+export async function setAutoSyncPolicy(deviceId: string, policy: SyncPolicy): Promise<AutoSyncSettings> {
+  return invoke<AutoSyncSettings>('set_auto_sync_policy', { deviceId, policy }).catch((e) => {
+    throw toKindleError(e);
+  });
+}
+
+export async function setAutoSyncInterval(minutes: number): Promise<AutoSyncSettings> {
+  return invoke<AutoSyncSettings>('set_auto_sync_interval', { minutes }).catch((e) => {
+    throw toKindleError(e);
+  });
+}
+
+/**
+ * Subscribe to imports the desktop agent ran on its own, with the ID of the reader
+ *
+ * The agent imports from a known reader when it is plugged in, or from the tray
+ * menu when its policy is `ask`, whether or not this window is open.
+ */
+export function onAutoSynced(callback: (readerId: string) => void): Promise<UnlistenFn> {
+  return listen<string>('auto-synced', (event) => callback(event.payload));
+}
+
+/** Import state shown in the tray icon */
+export type SyncStatus =
+  | { state: 'idle' }
+  | { state: 'syncing' }
+  | { state: 'succeeded'; imported: number }
+  | { state: 'failed'; message: string };
+
+export async function reportSyncStatus(status: SyncStatus): Promise<void> {
+  return invoke('report_sync_status', { status });
+}
+
+/**
+ * Subscribe to Sync now in the tray menu
+ */
+export function onSyncNowRequest(callback: () => void): Promise<UnlistenFn> {
+  return listen('sync-now', () => callback());
+}
@@ -0,0 +1,39 @@
+import { describe, it, expect, beforeEach } from 'vitest';
+import { mockIPC, clearMocks } from '@tauri-apps/api/mocks';
+import { readKindleClippings, type Clipping } from './clippings';
+
+beforeEach(() => {
+  clearMocks();
+});
+
+describe('readKindleClippings', () => {
+  it('returns clippings parsed by Rust', async () => {
+    const clipping: Clipping = {
+      title: 'The Great Gatsby',
+      author: 'F. Scott Fitzgerald',
+      kind: 'highlight',
+      page: '5',
+      location: '72-75',
+      addedOn: 'Monday, January 20, 2026 10:30:00 AM',
+      content: 'In my younger and more vulnerable years...',
+    };
+    mockIPC((cmd) => {
+      if (cmd === 'parse_kindle_clippings') return [clipping];
+    });
+
+    expect(await readKindleClippings()).toEqual([clipping]);
+  });
+
+  it('surfaces a missing clippings file as a typed Kindle error', async () => {
+    mockIPC((cmd) => {
+      if (cmd === 'parse_kindle_clippings') {
+        throw { kind: 'clippingsNotFound', message: 'My Clippings.txt not found on Kindle', code: null };
+      }
+    });
+
+    await expect(readKindleClippings()).rejects.toMatchObject({
+      name: 'KindleError',
+      kind: 'clippingsNotFound',
+    });
+  });
+});
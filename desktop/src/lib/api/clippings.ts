@@ -0,0 +1,27 @@
+import { invoke } from '@tauri-apps/api/core';
+import { toKindleError } from './kindle';
+
+export type ClippingKind = 'highlight' | 'note' | 'bookmark';
+
+/** Entry of the Kindle's My Clippings.txt, already deduplicated by the desktop agent */
+export interface Clipping {
+  title: string;
+  author: string | null;
+  kind: ClippingKind;
+  page: string | null;
+  /** Single position or range like "72-75" */
+  location: string | null;
+  /** Date as written on the Kindle, in its UI language */
+  addedOn: string | null;
+  /** Empty for bookmarks */
+  content: string;
+}
+
+/**
+ * Read highlights, notes and bookmarks from the Kindle's My Clippings.txt
+ */
+export async function readKindleClippings(): Promise<Clipping[]> {
+  return invoke<Clipping[]>('parse_kindle_clippings').catch((e) => {
+    throw toKindleError(e);
+  });
+}
@@ -1,42 +1,97 @@
 import { describe, it, expect, beforeEach } from 'vitest';
 import { mockIPC, clearMocks } from '@tauri-apps/api/mocks';
-import { checkKindleStatus, type KindleStatus } from './kindle';
+import { checkKindleStatus, readKindleVocabDb, sameTransport, type ReaderStatus } from './kindle';
 
 beforeEach(() => {
   clearMocks();
 });
 
+const mountedKindle: ReaderStatus = {
+  id: 'mounted:/Volumes/Kindle',
+  family: 'kindle',
+  transport: { type: 'mounted', path: '/Volumes/Kindle' },
+  sources: ['vocabDb', 'clippings'],
+  device: null,
+  lastSynced: null,
+};
+
 describe('checkKindleStatus', () => {
-  it('returns connected status when Kindle is connected via USB', async () => {
-    const mockStatus: KindleStatus = { connected: true, connectionType: 'mounted' };
+  it('returns a Kindle connected via USB mass storage', async () => {
     mockIPC((cmd) => {
-      if (cmd === 'check_kindle_status') return mockStatus;
+      if (cmd === 'check_kindle_status') return [mountedKindle];
     });
 
     const result = await checkKindleStatus();
-    expect(result.connected).toBe(true);
-    expect(result.connectionType).toBe('mounted');
+    expect(result).toHaveLength(1);
+    expect(result[0].transport.type).toBe('mounted');
   });
 
-  it('returns connected status when Kindle is connected via MTP', async () => {
-    const mockStatus: KindleStatus = { connected: true, connectionType: 'mtp' };
+  it('returns every reader when a Kindle and a Kobo are attached', async () => {
+    const mtpKindle: ReaderStatus = {
+      ...mountedKindle,
+      id: 'G000TEST',
+      transport: { type: 'mtp', bus: 1, address: 4 },
+      lastSynced: 1700000000000,
+    };
+    const kobo: ReaderStatus = {
+      id: 'N249110000001',
+      family: 'kobo',
+      transport: { type: 'mounted', path: '/media/user/KOBOeReader' },
+      sources: ['koboDb'],
+      device: null,
+      lastSynced: null,
+    };
     mockIPC((cmd) => {
-      if (cmd === 'check_kindle_status') return mockStatus;
+      if (cmd === 'check_kindle_status') return [kobo, mtpKindle];
     });
 
     const result = await checkKindleStatus();
-    expect(result.connected).toBe(true);
-    expect(result.connectionType).toBe('mtp');
+    expect(result.map((reader) => reader.family)).toEqual(['kobo', 'kindle']);
+    expect(result[1].transport).toEqual({ type: 'mtp', bus: 1, address: 4 });
+    expect(result[1].lastSynced).toBe(1700000000000);
   });
 
-  it('returns disconnected status when Kindle is not connected', async () => {
-    const mockStatus: KindleStatus = { connected: false, connectionType: null };
+  it('returns no readers when nothing is connected', async () => {
     mockIPC((cmd) => {
-      if (cmd === 'check_kindle_status') return mockStatus;
+      if (cmd === 'check_kindle_status') return [];
     });
 
-    const result = await checkKindleStatus();
-    expect(result.connected).toBe(false);
-    expect(result.connectionType).toBeNull();
+    expect(await checkKindleStatus()).toEqual([]);
+  });
+});
+
+describe('readKindleVocabDb', () => {
+  it('returns the raw bytes sent by the command', async () => {
+    const header = new TextEncoder().encode('SQLite format 3\0');
+    mockIPC((cmd) => {
+      if (cmd === 'read_kindle_vocab_db') return header.buffer;
+    });
+
+    const bytes = await readKindleVocabDb();
+    expect(bytes).toBeInstanceOf(Uint8Array);
+    expect(Array.from(bytes)).toEqual(Array.from(header));
+  });
+
+  it('rejects with a KindleError when vocab.db is missing', async () => {
+    mockIPC((cmd) => {
+      if (cmd === 'read_kindle_vocab_db') {
+        throw { kind: 'vocabNotFound', message: 'vocab.db not found on Kindle', code: null };
+      }
+    });
+
+    await expect(readKindleVocabDb()).rejects.toMatchObject({
+      name: 'KindleError',
+      kind: 'vocabNotFound',
+    });
+  });
+});
+
+describe('sameTransport', () => {
+  it('matches readers by where they are attached, not by id', () => {
+    expect(sameTransport(mountedKindle.transport, { type: 'mounted', path: '/Volumes/Kindle' })).toBe(true);
+    expect(sameTransport(mountedKindle.transport, { type: 'mounted', path: '/Volumes/KOBOeReader' })).toBe(false);
+    expect(sameTransport({ type: 'mtp', bus: 1, address: 4 }, { type: 'mtp', bus: 1, address: 4 })).toBe(true);
+    expect(sameTransport({ type: 'mtp', bus: 1, address: 4 }, { type: 'mtp', bus: 1, address: 5 })).toBe(false);
+    expect(sameTransport(mountedKindle.transport, { type: 'mtp', bus: 1, address: 4 })).toBe(false);
   });
 });
@@ -3,15 +3,152 @@
  */
 
 import { invoke } from '@tauri-apps/api/core';
+import { listen, type UnlistenFn } from '@tauri-apps/api/event';
 
-export interface KindleStatus {
-  connected: boolean;
-  connectionType: 'mounted' | 'mtp' | null;
+export interface KindleStorage {
+  storageId: number;
+  description: string;
+  volumeLabel: string;
+  maxCapacity: number;
+  freeSpace: number;
+}
+
+export interface KindleIdentity {
+  manufacturer: string;
+  model: string;
+  serialNumber: string;
+  firmwareVersion: string;
+  storages: KindleStorage[];
+}
+
+export type DeviceFamily = 'kindle' | 'kobo';
+
+export type ReaderTransport =
+  | { type: 'mounted'; path: string }
+  | { type: 'mtp'; bus: number; address: number };
+
+/**
+ * Whether two transports are the same attachment. A reader's id can change while
+ * it stays plugged in, e.g. once its serial number is read, so match on this.
+ */
+export function sameTransport(a: ReaderTransport, b: ReaderTransport): boolean {
+  if (a.type === 'mounted' && b.type === 'mounted') return a.path === b.path;
+  if (a.type === 'mtp' && b.type === 'mtp') return a.bus === b.bus && a.address === b.address;
+  return false;
+}
+
+/** What can be imported from a reader */
+export type ReaderSource = 'vocabDb' | 'clippings' | 'koreaderVocab' | 'koboDb';
+
+/** A connected e-reader */
+export interface ReaderStatus {
+  /** Serial number when known, otherwise derived from the transport */
+  id: string;
+  family: DeviceFamily;
+  transport: ReaderTransport;
+  sources: ReaderSource[];
+  device: KindleIdentity | null;
+  /** When an import from this reader last completed, ms since the epoch */
+  lastSynced: number | null;
+}
+
+export interface DownloadProgress {
+  bytesDone: number;
+  total: number;
+}
+
+export type KindleErrorKind =
+  | 'noDevice'
+  | 'permissionDenied'
+  | 'interfaceBusy'
+  | 'udevRuleMissing'
+  | 'timeout'
+  | 'protocolError'
+  | 'invalidResponse'
+  | 'incompleteTransfer'
+  | 'corruptDatabase'
+  | 'vocabNotFound'
+  | 'clippingsNotFound'
+  | 'koreaderVocabNotFound'
+  | 'koboDbNotFound'
+  | 'cancelled'
+  | 'sessionExpired'
+  | 'server'
+  | 'network'
+  | 'usb'
+  | 'io';
+
+/**
+ * Error raised by Kindle commands, tagged by kind so the UI can offer recovery steps
+ */
+export class KindleError extends Error {
+  readonly kind: KindleErrorKind;
+  /** PTP response code, when the device sent one */
+  readonly code: number | null;
+
+  constructor(kind: KindleErrorKind, message: string, code: number | null = null) {
+    super(message);
+    this.name = 'KindleError';
+    this.kind = kind;
+    this.code = code;
+  }
+}
+
+/**
+ * Convert a rejected Kindle command into an Error, keeping the tagged payload
+ */
+export function toKindleError(error: unknown): Error {
+  if (error instanceof Error) return error;
+  if (error && typeof error === 'object' && 'kind' in error && 'message' in error) {
+    const { kind, message, code } = error as { kind: KindleErrorKind; message: string; code?: number | null };
+    return new KindleError(kind, message, code ?? null);
+  }
+  return new Error(String(error));
+}
+
+/**
+ * List every connected reader; empty when none is attached
+ */
+export async function checkKindleStatus(): Promise<ReaderStatus[]> {
+  return invoke<ReaderStatus[]>('check_kindle_status');
+}
+
+/**
+ * Read vocab.db from the Kindle as raw bytes, e.g. to keep a backup of it
+ */
+export async function readKindleVocabDb(): Promise<Uint8Array> {
+  const buffer = await invoke<ArrayBuffer>('read_kindle_vocab_db').catch((e) => {
+    throw toKindleError(e);
+  });
+  return new Uint8Array(buffer);
+}
+
+/**
+ * Stop the vocab.db read of an in-flight import; it rejects with kind `cancelled`
+ */
+export async function cancelKindleImport(): Promise<void> {
+  return invoke('cancel_kindle_import');
+}
+
+/**
+ * Subscribe to vocab.db download progress while an import reads from the Kindle
+ */
+export function onKindleDownloadProgress(
+  callback: (progress: DownloadProgress) => void,
+): Promise<UnlistenFn> {
+  return listen<DownloadProgress>('kindle-download-progress', (event) => callback(event.payload));
+}
+
+/**
+ * Subscribe to readers being plugged in; the desktop agent watches USB and mounts
+ */
+export function onReaderConnected(callback: (reader: ReaderStatus) => void): Promise<UnlistenFn> {
+  return listen<ReaderStatus>('reader-connected', (event) => callback(event.payload));
 }
 
 /**
- * Check Kindle connection status
+ * Subscribe to readers being unplugged or ejected
  */
-export async function checkKindleStatus(): Promise<KindleStatus> {
-  return invoke<KindleStatus>('check_kindle_status');
+export function onReaderDisconnected(callback: (reader: ReaderStatus) => void): Promise<UnlistenFn> {
+  return listen<ReaderStatus>('reader-disconnected', (event) => callback(event.payload));
 }
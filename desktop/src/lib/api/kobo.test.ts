@@ -0,0 +1,69 @@
+import { describe, it, expect, beforeEach } from 'vitest';
+import { mockIPC, clearMocks } from '@tauri-apps/api/mocks';
+import { readKoboLibrary, type KoboLibrary } from './kobo';
+
+beforeEach(() => {
+  clearMocks();
+});
+
+describe('readKoboLibrary', () => {
+  it('returns lookups and clippings parsed by Rust', async () => {
+    const library: KoboLibrary = {
+      vocab: {
+        words: [
+          { id: 'en:leviathan', word: 'leviathan', stem: null, lang: 'en', category: 0, timestamp: 1705746600000 },
+        ],
+        lookups: [
+          {
+            id: 'kobo:en:leviathan',
+            wordKey: 'en:leviathan',
+            bookKey: 'file:///mnt/onboard/moby.epub',
+            dictKey: '-en',
+            usage: null,
+            timestamp: 1705746600000,
+          },
+        ],
+        books: [
+          {
+            id: 'file:///mnt/onboard/moby.epub',
+            asin: null,
+            guid: null,
+            lang: 'en',
+            title: 'Moby Dick',
+            authors: 'Herman Melville',
+          },
+        ],
+        dictionaries: [],
+      },
+      clippings: [
+        {
+          title: 'Moby Dick',
+          author: 'Herman Melville',
+          kind: 'highlight',
+          page: null,
+          location: 'span#kobo\\.1\\.1',
+          addedOn: '2024-01-20T10:31:00.000',
+          content: 'Call me Ishmael.',
+        },
+      ],
+    };
+    mockIPC((cmd) => {
+      if (cmd === 'parse_kobo_library') return library;
+    });
+
+    expect(await readKoboLibrary()).toEqual(library);
+  });
+
+  it('surfaces a missing Kobo as a typed error', async () => {
+    mockIPC((cmd) => {
+      if (cmd === 'parse_kobo_library') {
+        throw { kind: 'koboDbNotFound', message: 'KoboReader.sqlite not found on Kobo', code: null };
+      }
+    });
+
+    await expect(readKoboLibrary()).rejects.toMatchObject({
+      name: 'KindleError',
+      kind: 'koboDbNotFound',
+    });
+  });
+});
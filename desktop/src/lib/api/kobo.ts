@@ -0,0 +1,21 @@
+import { invoke } from '@tauri-apps/api/core';
+import { toKindleError } from './kindle';
+import type { KindleVocab } from './vocab';
+import type { Clipping } from './clippings';
+
+/** Contents of a Kobo's KoboReader.sqlite, mapped onto the Kindle records */
+export interface KoboLibrary {
+  /** Dictionary lookups from `WordList`; Kobo keeps no usage sentence */
+  vocab: KindleVocab;
+  /** Highlights, notes and dog-ears from `Bookmark` */
+  clippings: Clipping[];
+}
+
+/**
+ * Read dictionary lookups and annotations from a mounted Kobo
+ */
+export async function readKoboLibrary(): Promise<KoboLibrary> {
+  return invoke<KoboLibrary>('parse_kobo_library').catch((e) => {
+    throw toKindleError(e);
+  });
+}
@@ -0,0 +1,80 @@
+import { describe, it, expect, beforeEach, vi } from 'vitest';
+import { mockIPC, clearMocks } from '@tauri-apps/api/mocks';
+import { discardPendingImport, retryPendingImports, setAgentSession, type PendingBatch } from './outbox';
+
+vi.mock('$lib/supabase', () => ({
+  supabaseUrl: 'https://project.supabase.co',
+  supabasePublishableKey: 'publishable-key',
+  supabase: {}
+}));
+
+beforeEach(() => {
+  clearMocks();
+});
+
+const batch: PendingBatch = {
+  id: 7,
+  deviceId: 'G000TEST',
+  watermark: 1700000000000,
+  lookups: 250,
+  queuedAt: 1700000100000,
+  attempts: 2,
+  lastError: 'Could not reach the server: offline',
+};
+
+describe('import outbox', () => {
+  it('retries with the current session', async () => {
+    let sent: unknown;
+    mockIPC((cmd, args) => {
+      if (cmd === 'retry_pending_imports') {
+        sent = args;
+        return [];
+      }
+    });
+
+    expect(await retryPendingImports({ accessToken: 'access', refreshToken: 'refresh' })).toEqual([]);
+    expect(sent).toEqual({
+      auth: {
+        url: 'https://project.supabase.co',
+        anonKey: 'publishable-key',
+        session: { accessToken: 'access', refreshToken: 'refresh' },
+      },
+    });
+  });
+
+  it('discards one batch and returns the rest', async () => {
+    let sent: unknown;
+    mockIPC((cmd, args) => {
+      if (cmd === 'discard_pending_import') {
+        sent = args;
+        return [batch];
+      }
+    });
+
+    expect(await discardPendingImport(6)).toEqual([batch]);
+    expect(sent).toEqual({ id: 6 });
+  });
+
+  it('clears the agent session on sign-out', async () => {
+    let sent: unknown;
+    mockIPC((cmd, args) => {
+      if (cmd === 'set_agent_auth') sent = args;
+    });
+
+    await setAgentSession(null);
+    expect(sent).toEqual({ auth: null });
+  });
+
+  it('surfaces a lost connection as a typed Kindle error', async () => {
+    mockIPC((cmd) => {
+      if (cmd === 'retry_pending_imports') {
+        throw { kind: 'network', message: 'Could not reach the server: offline', code: null };
+      }
+    });
+
+    await expect(retryPendingImports({ accessToken: 'a', refreshToken: 'r' })).rejects.toMatchObject({
+      name: 'KindleError',
+      kind: 'network',
+    });
+  });
+});
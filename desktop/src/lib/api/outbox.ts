@@ -0,0 +1,61 @@
+/**
+ * Import batches the desktop agent could not upload yet, e.g. while offline
+ */
+
+import { invoke } from '@tauri-apps/api/core';
+import { listen, type UnlistenFn } from '@tauri-apps/api/event';
+import { toKindleError } from './kindle';
+import { uploadAuth, type SupabaseTokens } from './vocab';
+
+export interface PendingBatch {
+  id: number;
+  /** Kindle serial number; empty when the reader has none */
+  deviceId: string;
+  /** Watermark the reader's sync state moves to once the batch is sent */
+  watermark: number;
+  /** Number of lookups in the batch */
+  lookups: number;
+  /** When the batch was queued, in ms since the epoch */
+  queuedAt: number;
+  attempts: number;
+  lastError: string | null;
+}
+
+export async function listPendingImports(): Promise<PendingBatch[]> {
+  return invoke<PendingBatch[]>('list_pending_imports').catch((e) => {
+    throw toKindleError(e);
+  });
+}
+
+/**
+ * Upload the pending batches now; resolves with those still pending
+ */
+export async function retryPendingImports(session: SupabaseTokens): Promise<PendingBatch[]> {
+  return invoke<PendingBatch[]>('retry_pending_imports', { auth: uploadAuth(session) }).catch((e) => {
+    throw toKindleError(e);
+  });
+}
+
+/**
+ * Drop a pending batch; its lookups are skipped once a later batch of the reader is sent
+ */
+export async function discardPendingImport(id: number): Promise<PendingBatch[]> {
+  return invoke<PendingBatch[]>('discard_pending_import', { id }).catch((e) => {
+    throw toKindleError(e);
+  });
+}
+
+/**
+ * Hand the agent the current session so it can send pending batches in the background,
+ * or `null` after sign-out
+ */
+export async function setAgentSession(session: SupabaseTokens | null): Promise<void> {
+  return invoke('set_agent_auth', { auth: session ? uploadAuth(session) : null });
+}
+
+/**
+ * Subscribe to the pending batches whenever they change
+ */
+export function onPendingImportsChanged(callback: (batches: PendingBatch[]) => void): Promise<UnlistenFn> {
+  return listen<PendingBatch[]>('pending-imports', (event) => callback(event.payload));
+}
@@ -1,9 +1,25 @@
 import { describe, it, expect, vi, beforeEach } from 'vitest';
 import { mockIPC, clearMocks } from '@tauri-apps/api/mocks';
-import { importFromKindle, getImportHistory } from './vocab';
+import {
+  importFromKindle,
+  getImportHistory,
+  mergeImportHistory,
+  previewKindleImport,
+  readKoreaderVocabulary,
+  summarizeSelection,
+  type ImportPreview,
+  type LocalImportEntry,
+  type PreviewLookup,
+} from './vocab';
 
 vi.mock('$lib/supabase', () => ({
+  supabaseUrl: 'https://project.supabase.co',
+  supabasePublishableKey: 'publishable-key',
   supabase: {
+    auth: {
+      getSession: vi.fn(),
+      setSession: vi.fn()
+    },
     functions: {
       invoke: vi.fn()
     },
@@ -11,37 +27,99 @@ vi.mock('$lib/supabase', () => ({
   }
 }));
 
+const eventHandlers = vi.hoisted(() => new Map<string, (event: { payload: unknown }) => void>());
+vi.mock('@tauri-apps/api/event', () => ({
+  listen: vi.fn(async (name: string, handler: (event: { payload: unknown }) => void) => {
+    eventHandlers.set(name, handler);
+    return () => eventHandlers.delete(name);
+  })
+}));
+
 describe('vocab API', () => {
-  beforeEach(() => {
+  beforeEach(async () => {
     clearMocks();
     vi.clearAllMocks();
+    eventHandlers.clear();
+    const { supabase } = await import('$lib/supabase');
+    vi.mocked(supabase.auth.getSession).mockResolvedValue({
+      data: { session: { access_token: 'access', refresh_token: 'refresh' } },
+      error: null
+    } as any);
+    vi.mocked(supabase.auth.setSession).mockResolvedValue({ data: {}, error: null } as any);
   });
 
-  it('importFromKindle reads from Rust and uploads to Supabase', async () => {
-    const mockBytes = [83, 81, 76, 105, 116, 101];
-    mockIPC((cmd) => {
-      if (cmd === 'read_kindle_vocab_db') return mockBytes;
+  const mockImport = {
+    totalLookups: 5000,
+    sent: 100,
+    queued: 0,
+    withoutWord: 0,
+    summary: { totalParsed: 100, imported: 50, skipped: 50, encounters: 100, errors: [], batches: 1 },
+  };
+
+  it('importFromKindle uploads through Rust with the current session', async () => {
+    let args: unknown;
+    mockIPC((cmd, payload) => {
+      if (cmd === 'sync_kindle_import') {
+        args = payload;
+        return mockImport;
+      }
     });
 
+    const result = await importFromKindle();
+
+    expect(args).toEqual({
+      auth: {
+        url: 'https://project.supabase.co',
+        anonKey: 'publishable-key',
+        session: { accessToken: 'access', refreshToken: 'refresh' },
+      },
+    });
     const { supabase } = await import('$lib/supabase');
-    vi.mocked(supabase.functions.invoke).mockResolvedValue({
-      data: { totalParsed: 100, imported: 50, skipped: 50, books: 5 },
-      error: null
+    expect(supabase.functions.invoke).not.toHaveBeenCalled();
+    // Lookups sent by an earlier import count as skipped
+    expect(result).toEqual({ totalParsed: 5000, imported: 50, skipped: 4950, error: undefined });
+  });
+
+  it('importFromKindle reports lookups without a word as skipped', async () => {
+    mockIPC((cmd) => {
+      if (cmd === 'sync_kindle_import') return { ...mockImport, withoutWord: 3 };
     });
 
     const result = await importFromKindle();
-    
-    expect(supabase.functions.invoke).toHaveBeenCalledWith('parse-vocab', {
-      body: { file: expect.any(String) }
+
+    expect(result).toMatchObject({ totalParsed: 5000, skipped: 4950, withoutWord: 3 });
+  });
+
+  it('importFromKindle adopts a session refreshed during the upload', async () => {
+    mockIPC((cmd) => {
+      if (cmd === 'sync_kindle_import') {
+        eventHandlers.get('supabase-session')?.({
+          payload: { accessToken: 'new-access', refreshToken: 'new-refresh' },
+        });
+        return mockImport;
+      }
     });
-    expect(result.imported).toBe(50);
-    expect(result.skipped).toBe(50);
-    expect(result.books).toBe(5);
+
+    await importFromKindle();
+
+    const { supabase } = await import('$lib/supabase');
+    expect(supabase.auth.setSession).toHaveBeenCalledWith({
+      access_token: 'new-access',
+      refresh_token: 'new-refresh',
+    });
+    expect(eventHandlers.size).toBe(0);
+  });
+
+  it('importFromKindle requires a signed-in user', async () => {
+    const { supabase } = await import('$lib/supabase');
+    vi.mocked(supabase.auth.getSession).mockResolvedValue({ data: { session: null }, error: null } as any);
+
+    await expect(importFromKindle()).rejects.toThrow('Not signed in');
   });
 
   it('importFromKindle handles Kindle not connected error', async () => {
     mockIPC((cmd) => {
-      if (cmd === 'read_kindle_vocab_db') {
+      if (cmd === 'sync_kindle_import') {
         throw new Error('Kindle not connected');
       }
     });
@@ -49,12 +127,124 @@ describe('vocab API', () => {
     await expect(importFromKindle()).rejects.toThrow('Kindle not connected');
   });
 
-  it('importFromKindle handles empty data error', async () => {
+  it('importFromKindle surfaces typed Kindle errors', async () => {
+    mockIPC((cmd) => {
+      if (cmd === 'sync_kindle_import') {
+        throw { kind: 'vocabNotFound', message: 'vocab.db not found on Kindle', code: null };
+      }
+    });
+
+    await expect(importFromKindle()).rejects.toMatchObject({
+      name: 'KindleError',
+      kind: 'vocabNotFound',
+      message: 'vocab.db not found on Kindle',
+    });
+  });
+
+  it('importFromKindle surfaces an expired session', async () => {
     mockIPC((cmd) => {
-      if (cmd === 'read_kindle_vocab_db') return [];
+      if (cmd === 'sync_kindle_import') {
+        throw { kind: 'sessionExpired', message: 'Your session has expired. Sign in again.', code: null };
+      }
     });
 
-    await expect(importFromKindle()).rejects.toThrow('No data read from Kindle');
+    await expect(importFromKindle()).rejects.toMatchObject({
+      name: 'KindleError',
+      kind: 'sessionExpired',
+    });
+  });
+
+  it('importFromKindle leaves out the lookups deselected in the preview', async () => {
+    let args: any;
+    mockIPC((cmd, payload) => {
+      if (cmd === 'sync_kindle_import') {
+        args = payload;
+        return { ...mockImport, sent: 98 };
+      }
+    });
+
+    const result = await importFromKindle('G000KA0000000001', ['l1', 'l2']);
+
+    expect(args.readerId).toBe('G000KA0000000001');
+    expect(args.excludedLookupIds).toEqual(['l1', 'l2']);
+    expect(result.skipped).toBe(4952);
+  });
+
+  const previewLookup = (id: string, normalized: string, classification: PreviewLookup['classification']) => ({
+    id,
+    word: normalized,
+    stem: null,
+    lang: 'en',
+    usage: null,
+    timestamp: null,
+    bookKey: 'moby',
+    normalized,
+    classification,
+  });
+
+  // Newest first, as parse-vocab classifies them
+  const preview: ImportPreview = {
+    totalLookups: 10,
+    lookups: [
+      previewLookup('a', 'whale', 'new'),
+      previewLookup('b', 'sea', 'existing'),
+      previewLookup('c', 'whale', 'existing'),
+      previewLookup('d', 'harpoon', 'reactivated'),
+    ],
+    books: [],
+    counts: { new: 1, reactivated: 1, existing: 2 },
+  };
+
+  it('previewKindleImport classifies through Rust and adopts a refreshed session', async () => {
+    let args: unknown;
+    mockIPC((cmd, payload) => {
+      if (cmd === 'preview_kindle_import') {
+        args = payload;
+        eventHandlers.get('supabase-session')?.({
+          payload: { accessToken: 'new-access', refreshToken: 'new-refresh' },
+        });
+        return preview;
+      }
+    });
+
+    await expect(previewKindleImport('G000KA0000000001')).resolves.toEqual(preview);
+
+    expect(args).toEqual({
+      auth: {
+        url: 'https://project.supabase.co',
+        anonKey: 'publishable-key',
+        session: { accessToken: 'access', refreshToken: 'refresh' },
+      },
+      readerId: 'G000KA0000000001',
+    });
+    const { supabase } = await import('$lib/supabase');
+    expect(supabase.auth.setSession).toHaveBeenCalledWith({
+      access_token: 'new-access',
+      refresh_token: 'new-refresh',
+    });
+  });
+
+  it('summarizeSelection lets an older lookup add a word whose newest was deselected', () => {
+    expect(summarizeSelection(preview, new Set())).toEqual(preview.counts);
+    expect(summarizeSelection(preview, new Set(['a']))).toEqual({ new: 1, reactivated: 1, existing: 1 });
+    expect(summarizeSelection(preview, new Set(['a', 'c', 'd']))).toEqual({ new: 0, reactivated: 0, existing: 1 });
+  });
+
+  it('readKoreaderVocabulary surfaces a missing database as a typed Kindle error', async () => {
+    mockIPC((cmd) => {
+      if (cmd === 'parse_koreader_vocab') {
+        throw {
+          kind: 'koreaderVocabNotFound',
+          message: 'KOReader vocabulary builder not found on the reader',
+          code: null,
+        };
+      }
+    });
+
+    await expect(readKoreaderVocabulary()).rejects.toMatchObject({
+      name: 'KindleError',
+      kind: 'koreaderVocabNotFound',
+    });
   });
 
   it('getImportHistory fetches from Supabase', async () => {
@@ -100,4 +290,67 @@ describe('vocab API', () => {
 
     await expect(getImportHistory()).rejects.toThrow('Failed to fetch');
   });
+
+  const localEntry: LocalImportEntry = {
+    id: 'import-1704103200000',
+    origin: 'import',
+    deviceId: 'G000TEST',
+    sources: ['vocabDb'],
+    startedAt: Date.parse('2024-01-01T10:00:00Z'),
+    durationMs: 4200,
+    lookupsRead: 5000,
+    lookupsSent: 100,
+    lookupsQueued: 0,
+    imported: 60,
+    skipped: 40,
+    watermark: { from: 1700000000000, to: 1700000500000 },
+    serverSessionIds: ['server-a', 'server-b'],
+    errors: [],
+  };
+
+  it('mergeImportHistory drops server sessions the agent already recorded', () => {
+    const server = ['server-a', 'server-b', 'server-c'].map((id, i) => ({
+      id,
+      timestamp: `2024-01-0${i + 1}T09:00:00Z`,
+      totalParsed: 50,
+      imported: 30,
+      skipped: 20,
+      status: 'success' as const,
+      origin: 'server' as const,
+    }));
+
+    const merged = mergeImportHistory([localEntry], server);
+
+    expect(merged.map((s) => s.id)).toEqual(['server-c', 'import-1704103200000']);
+    expect(merged[1]).toMatchObject({ origin: 'local', deviceId: 'G000TEST', totalParsed: 5000, imported: 60 });
+  });
+
+  it('getImportHistory shows local failures while the server is unreachable', async () => {
+    const failed: LocalImportEntry = {
+      ...localEntry,
+      id: 'import-1704106800000',
+      startedAt: Date.parse('2024-01-01T11:00:00Z'),
+      lookupsSent: 0,
+      imported: 0,
+      skipped: 0,
+      watermark: null,
+      serverSessionIds: [],
+      errors: [{ stage: 'read', kind: 'vocabNotFound', message: 'vocab.db not found on Kindle', code: null }],
+    };
+    mockIPC((cmd) => {
+      if (cmd === 'get_import_history') return [failed, localEntry];
+    });
+    const { supabase } = await import('$lib/supabase');
+    vi.mocked(supabase.from).mockReturnValue({
+      select: vi.fn().mockReturnThis(),
+      order: vi.fn().mockReturnThis(),
+      limit: vi.fn().mockResolvedValue({ data: null, error: { message: 'Failed to fetch' } }),
+    } as any);
+
+    const result = await getImportHistory();
+
+    expect(result).toHaveLength(2);
+    expect(result[0]).toMatchObject({ status: 'error', error: 'vocab.db not found on Kindle' });
+    expect(result[1].status).toBe('success');
+  });
 });
@@ -1,10 +1,16 @@
-import { supabase } from '$lib/supabase';
+import { supabase, supabasePublishableKey, supabaseUrl } from '$lib/supabase';
 import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
+import { toKindleError } from './kindle';
 
 export interface ImportResult {
   totalParsed: number;
   imported: number;
   skipped: number;
+  /** Lookups kept in the outbox until the server can be reached */
+  queued?: number;
+  /** Lookups skipped, and counted in `skipped`, because vocab.db has no word for them */
+  withoutWord?: number;
   error?: string;
 }
 
@@ -16,77 +22,332 @@ export interface ImportSession {
   skipped: number;
   status: 'success' | 'error';
   error?: string;
+  /** `local` when recorded by this machine's agent, which may be the only record of a failure */
+  origin?: 'local' | 'server';
+  deviceId?: string;
+  /** Lookups left in the outbox */
+  queued?: number;
+  durationMs?: number;
 }
 
-function bytesToBase64(bytes: number[]): string {
-  const binary = String.fromCharCode(...bytes);
-  return btoa(binary);
+export type ImportStage = 'read' | 'parse' | 'upload' | 'record';
+
+/** Import recorded by the desktop agent, including failures before the upload */
+export interface LocalImportEntry {
+  id: string;
+  origin: 'import' | 'outbox';
+  deviceId: string | null;
+  sources: Array<'vocabDb' | 'clippings' | 'koreaderVocab' | 'koboDb'>;
+  /** ms since the epoch */
+  startedAt: number;
+  durationMs: number;
+  lookupsRead: number;
+  lookupsSent: number;
+  lookupsQueued: number;
+  imported: number;
+  skipped: number;
+  watermark: { from: number; to: number } | null;
+  /** `import_sessions` rows created by the batches this import sent */
+  serverSessionIds: string[];
+  /** The failure that ended the import, then the lookups the server rejected */
+  errors: Array<{ stage: ImportStage; kind: string; message: string; code: number | null }>;
 }
 
-export async function importFromKindle(): Promise<ImportResult> {
-  try {
-    const vocabBytes: number[] = await invoke('read_kindle_vocab_db');
-    
-    if (!vocabBytes || vocabBytes.length === 0) {
-      throw new Error('No data read from Kindle');
-    }
+/** Records read from vocab.db on the desktop; timestamps are ms since the epoch */
+export interface KindleWord {
+  id: string;
+  word: string;
+  stem: string | null;
+  lang: string | null;
+  category: number;
+  timestamp: number | null;
+}
+
+export interface KindleLookup {
+  id: string;
+  wordKey: string;
+  bookKey: string | null;
+  dictKey: string | null;
+  usage: string | null;
+  timestamp: number | null;
+}
+
+export interface KindleBook {
+  id: string;
+  asin: string | null;
+  guid: string | null;
+  lang: string | null;
+  title: string | null;
+  authors: string | null;
+}
+
+export interface KindleDictionary {
+  id: string;
+  asin: string | null;
+  langIn: string | null;
+  langOut: string | null;
+}
+
+export interface KindleVocab {
+  words: KindleWord[];
+  lookups: KindleLookup[];
+  books: KindleBook[];
+  dictionaries: KindleDictionary[];
+}
+
+/**
+ * Read and parse vocab.db from the Kindle without uploading anything
+ */
+export async function readKindleVocabulary(): Promise<KindleVocab> {
+  return invoke<KindleVocab>('parse_kindle_vocab').catch((e) => {
+    throw toKindleError(e);
+  });
+}
+
+/**
+ * Read KOReader's vocabulary builder from a jailbroken Kindle or a Kobo,
+ * as the same records as vocab.db
+ */
+export async function readKoreaderVocabulary(): Promise<KindleVocab> {
+  return invoke<KindleVocab>('parse_koreader_vocab').catch((e) => {
+    throw toKindleError(e);
+  });
+}
+
+/** Totals over every batch the desktop uploaded to parse-vocab */
+export interface KindleUploadSummary {
+  totalParsed: number;
+  imported: number;
+  skipped: number;
+  encounters: number;
+  errors: string[];
+  batches: number;
+}
 
-    const base64 = bytesToBase64(vocabBytes);
+/** Outcome of an import uploaded by the desktop app */
+export interface KindleImport {
+  totalLookups: number;
+  sent: number;
+  queued: number;
+  /** New lookups skipped because vocab.db has no word for them */
+  withoutWord: number;
+  summary: KindleUploadSummary;
+}
+
+export interface SupabaseTokens {
+  accessToken: string;
+  refreshToken: string;
+}
+
+/** Project and session the desktop app uploads with */
+export function uploadAuth(session: SupabaseTokens) {
+  return {
+    url: supabaseUrl,
+    anonKey: supabasePublishableKey,
+    session: { accessToken: session.accessToken, refreshToken: session.refreshToken },
+  };
+}
 
-    const { data, error } = await supabase.functions.invoke('parse-vocab', {
-      body: { file: base64 },
+/**
+ * Run a desktop command that talks to Supabase with the current session.
+ * A session it had to refresh replaces ours, whose refresh token is now spent.
+ */
+async function withDesktopSession<T>(command: string, args: Record<string, unknown> = {}): Promise<T> {
+  const { data: { session } } = await supabase.auth.getSession();
+  if (!session) {
+    throw new Error('Not signed in');
+  }
+
+  const unlisten = await listen<SupabaseTokens>('supabase-session', ({ payload }) => {
+    supabase.auth
+      .setSession({ access_token: payload.accessToken, refresh_token: payload.refreshToken })
+      .catch((e) => console.error('Failed to adopt refreshed session:', e));
+  });
+  try {
+    return await invoke<T>(command, {
+      auth: uploadAuth({ accessToken: session.access_token, refreshToken: session.refresh_token }),
+      ...args,
+    }).catch((e) => {
+      throw toKindleError(e);
     });
+  } finally {
+    unlisten();
+  }
+}
 
-    if (error) {
-      throw new Error(error.message || 'Failed to parse vocabulary');
-    }
+export type LookupClassification = 'new' | 'reactivated' | 'existing';
+
+/** A lookup not imported yet, and what importing it would do */
+export interface PreviewLookup {
+  id: string;
+  word: string;
+  stem: string | null;
+  lang: string | null;
+  usage: string | null;
+  timestamp: number | null;
+  bookKey: string | null;
+  /** The word as stored in the vocabulary */
+  normalized: string;
+  classification: LookupClassification;
+}
+
+export interface PreviewCounts {
+  new: number;
+  reactivated: number;
+  existing: number;
+}
+
+/** Dry run of an import; lookups are newest first */
+export interface ImportPreview {
+  totalLookups: number;
+  lookups: PreviewLookup[];
+  books: KindleBook[];
+  counts: PreviewCounts;
+}
+
+/**
+ * Read vocab.db from the reader with `readerId` (the first with a vocab.db when
+ * omitted) and classify the lookups not imported yet against the user's
+ * vocabulary, the way parse-vocab would, without uploading anything
+ */
+export async function previewKindleImport(readerId?: string): Promise<ImportPreview> {
+  return withDesktopSession<ImportPreview>('preview_kindle_import', { readerId });
+}
+
+/**
+ * What importing the preview without the `excluded` lookups would do. The newest
+ * selected lookup of a word is the one that adds or restores it.
+ */
+export function summarizeSelection(preview: ImportPreview, excluded: ReadonlySet<string>): PreviewCounts {
+  // Whether each word is new, deleted or in the vocabulary, from its newest lookup
+  const wordStatus = new Map<string, LookupClassification>();
+  for (const lookup of preview.lookups) {
+    if (!wordStatus.has(lookup.normalized)) wordStatus.set(lookup.normalized, lookup.classification);
+  }
 
-    if (!data) {
-      throw new Error('No data returned from server');
+  const counts: PreviewCounts = { new: 0, reactivated: 0, existing: 0 };
+  const seen = new Set<string>();
+  for (const lookup of preview.lookups) {
+    if (excluded.has(lookup.id)) continue;
+    const status = wordStatus.get(lookup.normalized)!;
+    if (status === 'existing' || seen.has(lookup.normalized)) {
+      counts.existing++;
+    } else {
+      counts[status]++;
     }
+    seen.add(lookup.normalized);
+  }
+  return counts;
+}
 
+/**
+ * Read vocab.db from the reader with `readerId` (the first with a vocab.db when
+ * omitted) and let the desktop upload the lookups not sent yet to parse-vocab.
+ * After a preview, `excludedLookupIds` are the lookups the user deselected; they
+ * are left out of this import only and offered again by the next one.
+ */
+export async function importFromKindle(readerId?: string, excludedLookupIds?: string[]): Promise<ImportResult> {
+  try {
+    const result = await withDesktopSession<KindleImport>('sync_kindle_import', { readerId, excludedLookupIds });
+
+    // Lookups uploaded by an earlier import of this Kindle, or deselected in the preview
+    const alreadySynced = result.totalLookups - result.sent - result.queued - result.withoutWord;
+    const { summary } = result;
     return {
-      totalParsed: data.totalParsed || 0,
-      imported: data.imported || 0,
-      skipped: data.skipped || 0,
-      error: data.errors ? data.errors.join('; ') : undefined,
+      totalParsed: summary.totalParsed + alreadySynced + result.withoutWord,
+      imported: summary.imported,
+      skipped: summary.skipped + alreadySynced + result.withoutWord,
+      queued: result.queued > 0 ? result.queued : undefined,
+      withoutWord: result.withoutWord > 0 ? result.withoutWord : undefined,
+      error: summary.errors.length > 0 ? summary.errors.join('; ') : undefined,
     };
   } catch (error) {
     throw error instanceof Error ? error : new Error(String(error));
   }
 }
 
-export async function getImportHistory(): Promise<ImportSession[]> {
-  try {
-    const { data, error } = await supabase
-      .from('import_sessions')
-      .select('*')
-      .order('started_at', { ascending: false })
-      .limit(50);
-
-    if (error) {
-      throw new Error(error.message || 'Failed to fetch import history');
-    }
+export async function getLocalImportHistory(limit?: number): Promise<LocalImportEntry[]> {
+  return invoke<LocalImportEntry[]>('get_import_history', { limit }).catch((e) => {
+    throw toKindleError(e);
+  });
+}
 
-    if (!data) {
-      return [];
-    }
+async function getServerImportHistory(): Promise<ImportSession[]> {
+  const { data, error } = await supabase
+    .from('import_sessions')
+    .select('*')
+    .order('started_at', { ascending: false })
+    .limit(50);
 
-    return data.map((row: any) => {
-      const hasErrors = (row.errors || 0) > 0;
-      return {
-        id: row.id,
-        timestamp: row.started_at || '',
-        totalParsed: row.total_found || 0,
-        imported: row.imported || 0,
-        skipped: row.skipped || 0,
-        status: hasErrors ? 'error' : 'success',
-        error: hasErrors ? `${row.errors} errors` : undefined,
-      };
-    });
-  } catch (error) {
+  if (error) {
+    throw new Error(error.message || 'Failed to fetch import history');
+  }
+
+  if (!data) {
+    return [];
+  }
+
+  return data.map((row: any) => {
+    const hasErrors = (row.errors || 0) > 0;
+    return {
+      id: row.id,
+      timestamp: row.started_at || '',
+      totalParsed: row.total_found || 0,
+      imported: row.imported || 0,
+      skipped: row.skipped || 0,
+      status: hasErrors ? 'error' : 'success',
+      error: hasErrors ? `${row.errors} errors` : undefined,
+      origin: 'server',
+    };
+  });
+}
+
+/**
+ * Combine the agent's history with `import_sessions`, newest first. A server
+ * session created by a batch the agent recorded is covered by its local entry.
+ */
+export function mergeImportHistory(local: LocalImportEntry[], server: ImportSession[]): ImportSession[] {
+  const covered = new Set(local.flatMap((entry) => entry.serverSessionIds));
+  const fromAgent = local.map((entry): ImportSession => {
+    const failure = entry.errors[0];
+    return {
+      id: entry.id,
+      timestamp: new Date(entry.startedAt).toISOString(),
+      // Outbox entries send lookups read by an earlier import
+      totalParsed: entry.origin === 'outbox' ? entry.lookupsSent : entry.lookupsRead,
+      imported: entry.imported,
+      skipped: entry.skipped,
+      status: entry.errors.length > 0 ? 'error' : 'success',
+      error: failure
+        ? entry.errors.length > 1
+          ? `${failure.message} (+${entry.errors.length - 1} more)`
+          : failure.message
+        : undefined,
+      origin: 'local',
+      deviceId: entry.deviceId ?? undefined,
+      queued: entry.lookupsQueued > 0 ? entry.lookupsQueued : undefined,
+      durationMs: entry.durationMs,
+    };
+  });
+  return [...fromAgent, ...server.filter((session) => !covered.has(session.id))].sort(
+    (a, b) => Date.parse(b.timestamp) - Date.parse(a.timestamp),
+  );
+}
+
+/**
+ * Imports from this machine's history and the server's, so failures and
+ * offline imports show up too. Fails only when neither can be read.
+ */
+export async function getImportHistory(): Promise<ImportSession[]> {
+  const [local, server] = await Promise.allSettled([getLocalImportHistory(), getServerImportHistory()]);
+  if (server.status === 'rejected' && (local.status === 'rejected' || local.value.length === 0)) {
+    const error = server.reason;
     throw error instanceof Error ? error : new Error(String(error));
   }
+  return mergeImportHistory(
+    local.status === 'fulfilled' ? local.value : [],
+    server.status === 'fulfilled' ? server.value : [],
+  );
 }
 
 export function formatTimestamp(isoString: string): string {
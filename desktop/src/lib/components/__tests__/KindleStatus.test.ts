@@ -1,16 +1,25 @@
 import { describe, it, expect, beforeEach, vi } from 'vitest';
 import { mockIPC, clearMocks } from '@tauri-apps/api/mocks';
-import { checkKindleStatus, type KindleStatus } from '../../api/kindle';
+import { checkKindleStatus, type ReaderStatus } from '../../api/kindle';
 import { importFromKindle } from '../../api/vocab';
 
 vi.mock('$lib/supabase', () => ({
+  supabaseUrl: 'https://project.supabase.co',
+  supabasePublishableKey: 'publishable-key',
   supabase: {
-    functions: {
-      invoke: vi.fn()
+    auth: {
+      getSession: vi.fn().mockResolvedValue({
+        data: { session: { access_token: 'access', refresh_token: 'refresh' } },
+        error: null
+      })
     }
   }
 }));
 
+vi.mock('@tauri-apps/api/event', () => ({
+  listen: vi.fn().mockResolvedValue(() => {})
+}));
+
 beforeEach(() => {
   clearMocks();
   vi.clearAllMocks();
@@ -18,53 +27,53 @@ beforeEach(() => {
 
 describe('Kindle API Integration', () => {
   it('can check Kindle status when connected', async () => {
-    const mockStatus: KindleStatus = { connected: true, connectionType: 'mounted' };
+    const reader: ReaderStatus = {
+      id: 'mounted:/Volumes/Kindle',
+      family: 'kindle',
+      transport: { type: 'mounted', path: '/Volumes/Kindle' },
+      sources: ['vocabDb'],
+      device: null,
+      lastSynced: null,
+    };
     mockIPC((cmd) => {
-      if (cmd === 'check_kindle_status') return mockStatus;
+      if (cmd === 'check_kindle_status') return [reader];
     });
 
-    const status = await checkKindleStatus();
-    expect(status.connected).toBe(true);
-    expect(status.connectionType).toBe('mounted');
+    const readers = await checkKindleStatus();
+    expect(readers).toHaveLength(1);
+    expect(readers[0].transport.type).toBe('mounted');
   });
 
   it('can check Kindle status when disconnected', async () => {
-    const mockStatus: KindleStatus = { connected: false, connectionType: null };
     mockIPC((cmd) => {
-      if (cmd === 'check_kindle_status') return mockStatus;
+      if (cmd === 'check_kindle_status') return [];
     });
 
-    const status = await checkKindleStatus();
-    expect(status.connected).toBe(false);
-    expect(status.connectionType).toBeNull();
+    const readers = await checkKindleStatus();
+    expect(readers).toHaveLength(0);
   });
 
   it('can import from Kindle when connected', async () => {
-    const mockBytes = [83, 81, 76, 105, 116, 101];
     mockIPC((cmd) => {
-      if (cmd === 'read_kindle_vocab_db') return mockBytes;
-    });
-
-    const { supabase } = await import('$lib/supabase');
-    vi.mocked(supabase.functions.invoke).mockResolvedValue({
-      data: {
-        totalParsed: 100,
-        imported: 95,
-        skipped: 5,
-        books: 10,
-      },
-      error: null
+      if (cmd === 'sync_kindle_import') {
+        return {
+          totalLookups: 100,
+          sent: 100,
+          queued: 0,
+          withoutWord: 0,
+          summary: { totalParsed: 100, imported: 95, skipped: 5, encounters: 100, errors: [], batches: 1 },
+        };
+      }
     });
 
     const result = await importFromKindle();
     expect(result.imported).toBe(95);
     expect(result.skipped).toBe(5);
-    expect(result.books).toBe(10);
   });
 
   it('handles import errors gracefully', async () => {
     mockIPC((cmd) => {
-      if (cmd === 'read_kindle_vocab_db') {
+      if (cmd === 'sync_kindle_import') {
         throw new Error('Kindle not connected');
       }
     });
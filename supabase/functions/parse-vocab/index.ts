@@ -1,5 +1,7 @@
 // Edge function: parse-vocab
 // Parses a Kindle Vocabulary Builder SQLite database and imports words.
+// The desktop app may instead send a JSON delta of only the lookups it has not
+// uploaded before (see desktop/src-tauri/src/kindle/sync_state.rs).
 
 import { handleCors } from '../_shared/cors.ts';
 import { createSupabaseClient, createServiceClient, getUserId } from '../_shared/supabase.ts';
@@ -29,6 +31,23 @@ interface KindleBook {
   asin: string | null;
 }
 
+/** Lookup as sent by the desktop app in a delta upload; timestamps in ms */
+interface DeltaLookup {
+  id: string;
+  word: string;
+  stem: string | null;
+  usage: string | null;
+  timestamp: number | null;
+  bookKey: string | null;
+}
+
+interface DeltaBook {
+  id: string;
+  title: string | null;
+  authors: string | null;
+  asin: string | null;
+}
+
 interface ClassifiedEntries {
   newEntries: KindleLookup[];
   reactivateEntries: KindleLookup[];
@@ -48,9 +67,10 @@ Deno.serve(async (req) => {
   if (!userId) return unauthorizedResponse();
 
   try {
-    const { file, native_language_code } = await req.json();
-    const fileBuffer = decodeFile(file);
-    const { lookups, books } = await parseKindleDb(fileBuffer);
+    const { file, delta, native_language_code } = await req.json();
+    const { lookups, books } = delta !== undefined
+      ? parseDelta(delta)
+      : await parseKindleDb(decodeFile(file));
 
     const sourceIdMap = await upsertSources(client, userId, books);
     const session = await createImportSession(client, userId, lookups.length);
@@ -87,6 +107,7 @@ Deno.serve(async (req) => {
       encounters: encountersCreated,
       skipped: existingEntries.length,
       errors: errors.length > 0 ? errors : undefined,
+      sessionId: session?.id,
     });
   } catch (error) {
     if (error instanceof BadRequest) return errorResponse(error.message, 400);
@@ -203,6 +224,57 @@ function extractLookups(db: Database): { lookups: KindleLookup[]; books: KindleB
   return { lookups, books: Array.from(booksMap.values()) };
 }
 
+// =============================================================================
+// Delta Parsing
+// =============================================================================
+
+const MAX_DELTA_LOOKUPS = 50_000;
+
+/** Maps a desktop delta onto the same lookups and books a vocab.db would yield */
+function parseDelta(delta: unknown): { lookups: KindleLookup[]; books: KindleBook[] } {
+  const { lookups, books } = (delta ?? {}) as { lookups?: unknown; books?: unknown };
+  if (!Array.isArray(lookups) || !Array.isArray(books)) {
+    throw new BadRequest('Invalid delta: expected lookups and books arrays');
+  }
+  if (lookups.length > MAX_DELTA_LOOKUPS) {
+    throw new BadRequest(`Delta too large (max ${MAX_DELTA_LOOKUPS} lookups)`);
+  }
+
+  const booksMap = new Map<string, KindleBook>();
+  for (const book of books as DeltaBook[]) {
+    if (!book || typeof book.id !== 'string') throw new BadRequest('Invalid delta: book without id');
+    if (!book.title) continue;
+    booksMap.set(book.id, {
+      kindleId: book.id,
+      title: book.title,
+      author: book.authors || null,
+      asin: book.asin || null,
+    });
+  }
+
+  const parsed: KindleLookup[] = [];
+  for (const lookup of lookups as DeltaLookup[]) {
+    if (!lookup || typeof lookup.word !== 'string') {
+      throw new BadRequest('Invalid delta: lookup without word');
+    }
+    if (!lookup.word) continue;
+
+    const cleanedWord = sanitizeKindleWord(lookup.word);
+    parsed.push({
+      word: cleanedWord,
+      stem: lookup.stem ? sanitizeKindleWord(lookup.stem) : null,
+      context: lookup.usage || null,
+      lookupTimestamp: toISOTimestamp(lookup.timestamp),
+      bookTitle: (lookup.bookKey && booksMap.get(lookup.bookKey)?.title) || null,
+      normalized: normalize(cleanedWord),
+    });
+  }
+
+  // Match the vocab.db query, which returns the newest lookups first
+  parsed.sort((a, b) => (b.lookupTimestamp ?? '').localeCompare(a.lookupTimestamp ?? ''));
+  return { lookups: parsed, books: Array.from(booksMap.values()) };
+}
+
 // =============================================================================
 // Sources
 // =============================================================================
@@ -48,6 +48,15 @@ let TEST_USER_ID = "";
 
 /** Invoke parse-vocab via the local edge function using dev mode. */
 async function invokeParseVocab(fileBase64: string) {
+  return postParseVocab({ file: fileBase64 });
+}
+
+/** Invoke parse-vocab with a delta of lookups, as sent by the desktop app. */
+async function invokeParseVocabDelta(delta: unknown) {
+  return postParseVocab({ delta });
+}
+
+async function postParseVocab(payload: Record<string, unknown>) {
   const response = await fetch(`${SUPABASE_URL}/functions/v1/parse-vocab`, {
     method: "POST",
     headers: {
@@ -56,7 +65,7 @@ async function invokeParseVocab(fileBase64: string) {
       "X-Dev-Secret": DEV_SECRET,
     },
     body: JSON.stringify({
-      file: fileBase64,
+      ...payload,
       userId: TEST_USER_ID,
     }),
   });
@@ -580,3 +589,79 @@ Deno.test({
     await cleanupTestData(TEST_USER_ID);
   },
 });
+
+Deno.test({
+  name: "integration: parse-vocab imports only the lookups in a delta",
+  sanitizeOps: false,
+  sanitizeResources: false,
+  fn: async () => {
+    if (!(await isSupabaseRunning()) || !(await isFunctionServed("parse-vocab"))) {
+      console.log("  ⏭ Skipping: local Supabase or functions not running");
+      return;
+    }
+
+    TEST_USER_ID = await ensureTestUser(TEST_EMAIL, TEST_PASSWORD);
+    await cleanupTestData(TEST_USER_ID);
+
+    const full = await invokeParseVocab(base64Encode(readFixture()));
+    assertEquals(full.status, 200);
+
+    const client = serviceClient();
+    const { data: known } = await client
+      .from("vocabulary")
+      .select("word")
+      .eq("user_id", TEST_USER_ID)
+      .limit(1);
+    assertExists(known);
+
+    const { status, data } = await invokeParseVocabDelta({
+      lookups: [
+        {
+          id: "delta-1",
+          word: "serendipitous",
+          stem: "serendipitous",
+          usage: "A serendipitous find.",
+          timestamp: Date.now(),
+          bookKey: "delta-book",
+        },
+        {
+          id: "delta-2",
+          word: known![0].word,
+          stem: null,
+          usage: null,
+          timestamp: Date.now() - 1000,
+          bookKey: null,
+        },
+      ],
+      books: [
+        { id: "delta-book", title: "Delta Book", authors: "Test Author", asin: null },
+      ],
+    });
+
+    assertEquals(status, 200, `Expected 200, got ${status}: ${JSON.stringify(data)}`);
+    assertEquals(data.totalParsed, 2);
+    assertEquals(data.imported, 1);
+    assertEquals(data.skipped, 1);
+    assertEquals(data.encounters, 2);
+
+    // The desktop app keys its local history to the session it created
+    const { data: session } = await client
+      .from("import_sessions")
+      .select("total_found")
+      .eq("id", data.sessionId)
+      .single();
+    assertEquals(session?.total_found, 2);
+
+    const { data: sources } = await client
+      .from("sources")
+      .select("title")
+      .eq("user_id", TEST_USER_ID)
+      .eq("title", "Delta Book");
+    assertEquals(sources?.length, 1);
+
+    const malformed = await invokeParseVocabDelta({ lookups: "nope" });
+    assertEquals(malformed.status, 400);
+
+    await cleanupTestData(TEST_USER_ID);
+  },
+});